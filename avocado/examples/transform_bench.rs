@@ -0,0 +1,181 @@
+//! This example measures how long deserializing a multi-megabyte result set
+//! takes with and without a transform. Queries which don't need one, e.g.
+//! plain `Document` filters, return `None` from `Query::transformer()`,
+//! so their documents are deserialized directly. Queries with a transform
+//! call it for every document, and deserialize the `Bson` it returns.
+//!
+//! Run it in release mode, otherwise the timings are meaningless:
+//! ```
+//! cargo run --release --example transform_bench
+//! ```
+
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate bson;
+extern crate mongodb;
+
+use std::fmt;
+use std::fs::create_dir_all;
+use std::env::temp_dir;
+use std::process::{ Command, Stdio };
+use std::time::{ Duration, Instant };
+use std::error::Error;
+use avocado::prelude::*;
+use avocado::cursor::BoxedTransform;
+
+/// The number of documents in the collection.
+const DOCUMENT_COUNT: usize = 4000;
+
+/// The length of the text of each document, in bytes.
+const TEXT_LEN: usize = 2048;
+
+/// The number of times each query is run. The fastest run is reported.
+const ROUNDS: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize, Doc)]
+struct Article {
+    #[serde(rename = "_id")]
+    id: Uid<Article>,
+    title: String,
+    tags: Vec<String>,
+    scores: Vec<f64>,
+    text: String,
+}
+
+/// Returns every document through a transform which doesn't change it,
+/// so it measures the cost of having a transform at all.
+#[derive(Debug)]
+struct Transformed;
+
+impl Query<Article> for Transformed {
+    type Output = Article;
+
+    fn transformer(&self) -> Option<BoxedTransform> {
+        Some(Box::new(|raw| Ok(raw.into())))
+    }
+}
+
+/// Runs a query `ROUNDS` times and returns the duration of the fastest run.
+fn fastest<Q: Query<Article, Output = Article>>(
+    articles: &Collection<Article>,
+    query: &Q,
+) -> AvocadoResult<Duration> {
+    let mut best = Duration::from_secs(u64::max_value());
+
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let count = articles.find_many(query)?.collect::<AvocadoResult<Vec<_>>>()?.len();
+        let elapsed = start.elapsed();
+
+        assert_eq!(count, DOCUMENT_COUNT);
+        best = best.min(elapsed);
+    }
+
+    Ok(best)
+}
+
+/// Returns the size of a document when serialized as BSON.
+fn encoded_size(article: &Article) -> AvocadoResult<usize> {
+    let mut buf = Vec::new();
+
+    match bson::to_bson(article)? {
+        Bson::Document(doc) => bson::encode_document(&mut buf, &doc)?,
+        _ => unreachable!("a struct is always serialized as a document"),
+    }
+
+    Ok(buf.len())
+}
+
+/// Prints the duration of a run and the corresponding throughput.
+fn report(name: &str, duration: Duration, bytes: usize) {
+    let secs = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9;
+    let mib = bytes as f64 / (1024.0 * 1024.0);
+
+    println!("{:<12} {:>10.2} ms {:>10.1} MiB/s", name, secs * 1e3, mib / secs);
+}
+
+#[derive(Debug)]
+struct AnyError(Box<dyn Error>); // fast and loose, don't do this in prod
+
+impl fmt::Display for AnyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<E: Error + 'static> From<E> for AnyError {
+    fn from(error: E) -> Self {
+        AnyError(Box::new(error))
+    }
+}
+
+fn example_main() -> Result<(), AnyError> {
+    // Spawn a MongoDB instance. Will shut down when this program exits.
+    let port = "12985";
+    let mut dbpath = temp_dir();
+
+    dbpath.push("avocado_transform_bench_db");
+    create_dir_all(&dbpath)?;
+
+    let mut mongodb_process = Command::new("mongod")
+        .arg("--noscripting")
+        .arg("--dbpath")
+        .arg(&dbpath)
+        .arg("--port")
+        .arg(port)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // the process handle doesn't implement `Drop`, so we need to make sure
+    // that the mongo daemon is shut down before this function returns.
+    let _guard = {
+        struct Guard<F: FnOnce() -> ()>(Option<F>);
+
+        impl<F: FnOnce() -> ()> Drop for Guard<F> {
+            fn drop(&mut self) {
+                self.0.take().unwrap()();
+            }
+        }
+
+        Guard(Some(|| { mongodb_process.kill().ok(); }))
+    };
+
+    let client = Client::with_uri(&format!("mongodb://localhost:{}/", port))?;
+    let db = client.db("avocado_transform_bench_db");
+    let articles: Collection<Article> = db.empty_collection_novalidate()?;
+
+    let text: String = "lorem ipsum ".chars().cycle().take(TEXT_LEN).collect();
+    let mut docs = Vec::with_capacity(DOCUMENT_COUNT);
+
+    for i in 0..DOCUMENT_COUNT {
+        docs.push(Article {
+            id: Uid::new_oid()?,
+            title: format!("Article #{}", i),
+            tags: vec!["bench".into(), format!("tag{}", i % 16)],
+            scores: (0..16).map(|j| (i * j) as f64).collect(),
+            text: text.clone(),
+        });
+    }
+
+    articles.insert_many(&docs)?;
+
+    let bytes = docs.iter().map(encoded_size).sum::<AvocadoResult<usize>>()?;
+
+    println!("{} documents, {:.1} MiB in total", DOCUMENT_COUNT, bytes as f64 / (1024.0 * 1024.0));
+
+    // Warm up the server's cache before measuring anything.
+    fastest(&articles, &doc!{})?;
+
+    report("identity", fastest(&articles, &doc!{})?, bytes);
+    report("transformed", fastest(&articles, &Transformed)?, bytes);
+
+    Ok(())
+}
+
+fn main() {
+    example_main().expect("error running example");
+}
@@ -4,6 +4,7 @@ use std::borrow::Borrow;
 use serde_json::Value;
 use bson::{ Bson, Document, ValueAccessError };
 use serde::Serialize;
+use crate::doc::Doc;
 use crate::error::{ Error, ErrorKind, Result };
 
 /// Methods for dynamically type-checking JSON.
@@ -90,12 +91,130 @@ impl BsonExt for Bson {
     }
 }
 
-/// Creates a BSON `Document` out of a serializable value.
-pub fn serialize_document<T: Serialize>(value: &T) -> Result<Document> {
+/// Creates a BSON value out of a serializable value, in the same manner
+/// as `serialize_document()`, i.e. exactly as it is stored in a document.
+pub fn serialize_bson<T: Serialize>(value: &T) -> Result<Bson> {
     serde_json::to_value(value)
         .map_err(From::from)
         .and_then(JsonExt::try_into_bson)
-        .and_then(BsonExt::try_into_doc)
+}
+
+/// Creates a BSON `Document` out of a serializable value. Fails with
+/// `ErrorKind::DocumentTooLarge` if the document couldn't be stored in
+/// MongoDB because its encoded size exceeds `MAX_DOCUMENT_SIZE`.
+pub fn serialize_document<T: Serialize>(value: &T) -> Result<Document> {
+    serialize_sized(value, "document")
+}
+
+/// Creates a BSON `Document` out of an entity, in the same manner as
+/// `serialize_document()`. If the document is too large, the error message
+/// names the type of the entity.
+pub fn serialize_entity<T: Doc>(entity: &T) -> Result<Document> {
+    serialize_sized(entity, T::NAME)
+}
+
+/// Serializes a value as a `Document`, then checks its encoded size.
+fn serialize_sized<T: Serialize>(value: &T, name: &str) -> Result<Document> {
+    let doc = serialize_bson(value).and_then(BsonExt::try_into_doc)?;
+    check_document_size(&doc, name)?;
+    Ok(doc)
+}
+
+/// The maximal size of a BSON document stored in MongoDB, in bytes.
+pub const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// The number of the largest top-level fields listed in the message of
+/// `ErrorKind::DocumentTooLarge` errors.
+const LARGEST_FIELDS_SHOWN: usize = 3;
+
+/// Ensures that a document doesn't exceed `MAX_DOCUMENT_SIZE` when encoded.
+/// Otherwise, returns an `ErrorKind::DocumentTooLarge` error, the message
+/// of which contains `name`, the size, and the largest top-level fields.
+pub fn check_document_size(doc: &Document, name: &str) -> Result<()> {
+    let size = document_size(doc);
+
+    if size <= MAX_DOCUMENT_SIZE {
+        return Ok(());
+    }
+
+    let mut fields: Vec<_> = doc
+        .iter()
+        .map(|(key, value)| (key, element_size(key.len(), value)))
+        .collect();
+
+    fields.sort_by(|lhs, rhs| rhs.1.cmp(&lhs.1));
+
+    let largest: Vec<_> = fields
+        .iter()
+        .take(LARGEST_FIELDS_SHOWN)
+        .map(|&(key, field_size)| format!("`{}` ({} bytes)", key, field_size))
+        .collect();
+
+    Err(Error::new(ErrorKind::DocumentTooLarge, format!(
+        "{} is {} bytes when encoded as BSON, exceeding the limit of {} bytes; largest fields: {}",
+        name, size, MAX_DOCUMENT_SIZE, largest.join(", ")
+    )))
+}
+
+/// Returns the size of a document when encoded as BSON, in bytes.
+pub fn document_size(doc: &Document) -> usize {
+    // The total size as an `i32`, the elements, and the terminating NUL.
+    4 + doc.iter().map(|(key, value)| element_size(key.len(), value)).sum::<usize>() + 1
+}
+
+/// Returns the size of a document element encoded as BSON, given the
+/// length of its key and its value: the type tag, the NUL-terminated key,
+/// and the value itself.
+pub fn element_size(key_len: usize, value: &Bson) -> usize {
+    1 + key_len + 1 + value_size(value)
+}
+
+/// Returns the size of a BSON value, without its type tag and key.
+#[allow(unreachable_patterns)]
+fn value_size(value: &Bson) -> usize {
+    // Strings are prefixed with their size as an `i32`, and NUL-terminated.
+    let string_size = |string: &str| 4 + string.len() + 1;
+
+    match *value {
+        Bson::Null => 0,
+        Bson::Boolean(_) => 1,
+        Bson::I32(_) => 4,
+        Bson::FloatingPoint(_) | Bson::I64(_) | Bson::TimeStamp(_) | Bson::UtcDatetime(_) => 8,
+        Bson::ObjectId(_) => 12,
+        Bson::String(ref string) |
+        Bson::JavaScriptCode(ref string) |
+        Bson::Symbol(ref string) => string_size(string),
+        Bson::RegExp(ref pattern, ref options) => pattern.len() + 1 + options.len() + 1,
+        Bson::JavaScriptCodeWithScope(ref code, ref scope) => {
+            4 + string_size(code) + document_size(scope)
+        }
+        Bson::Binary(_, ref bytes) => 4 + 1 + bytes.len(),
+        Bson::Document(ref doc) => document_size(doc),
+        // Arrays are documents keyed by the decimal indexes of the items.
+        Bson::Array(ref items) => {
+            let items_size: usize = items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| element_size(decimal_digits(i), item))
+                .sum();
+
+            4 + items_size + 1
+        }
+        // `Decimal128`, if enabled in the `bson` crate.
+        _ => 16,
+    }
+}
+
+/// Returns the number of decimal digits of a non-negative integer.
+fn decimal_digits(mut n: usize) -> usize {
+    let mut digits = 1;
+
+    while n >= 10 {
+        n /= 10;
+        digits += 1;
+    }
+
+    digits
 }
 
 /// Creates an array of `Document`s from an iterator over serializable values.
@@ -169,6 +288,65 @@ mod tests {
         assert_eq!(Bson::String("hello world".into()).try_as_bool(), None);
     }
 
+    #[test]
+    fn encoded_document_size() -> Result<()> {
+        let oid = ObjectId::new()?;
+        let docs = vec![
+            doc!{},
+            doc!{ "_id": oid, "name": "avocado", "ripe": true, "weight": 0.25 },
+            doc!{
+                "nested": { "array": (0..12).map(Bson::I32).collect::<Vec<_>>() },
+                "nothing": null,
+                "big": 1_i64 << 40,
+                "regex": Bson::RegExp("^a.*o$".into(), "i".into()),
+                "code": Bson::JavaScriptCodeWithScope("x + 1".into(), doc!{ "x": 41 }),
+                "bytes": Bson::Binary(bson::spec::BinarySubtype::Generic, vec![1, 2, 3]),
+            },
+        ];
+
+        for doc in &docs {
+            let mut bytes = Vec::new();
+            bson::encode_document(&mut bytes, doc)?;
+            assert_eq!(document_size(doc), bytes.len());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn document_too_large() -> Result<()> {
+        #[derive(Serialize)]
+        struct Fruit {
+            name: &'static str,
+            photo: String,
+            thumbnail: String,
+        }
+
+        let small = Fruit {
+            name: "Avocado",
+            photo: "x".repeat(MAX_DOCUMENT_SIZE - 2048),
+            thumbnail: "x".repeat(1024),
+        };
+        let large = Fruit {
+            name: "Avocado",
+            photo: "x".repeat(MAX_DOCUMENT_SIZE),
+            thumbnail: "x".repeat(1024),
+        };
+
+        assert!(serialize_document(&small).is_ok());
+
+        let error = serialize_document(&large).unwrap_err();
+        let message = error.to_string();
+
+        assert_eq!(error.kind(), ErrorKind::DocumentTooLarge);
+        assert!(message.contains(&MAX_DOCUMENT_SIZE.to_string()));
+        assert!(message.contains("`photo` ("));
+        assert!(message.find("`photo`") < message.find("`thumbnail`"));
+        assert!(message.find("`thumbnail`") < message.find("`name`"));
+
+        Ok(())
+    }
+
     #[test]
     fn serialize_one_document() -> Result<()> {
         #[derive(Serialize)]
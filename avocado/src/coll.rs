@@ -1,6 +1,9 @@
 //! A MongoDB collection of a single homogeneous type.
 
-use std::borrow::Borrow;
+use std::borrow::{ Borrow, Cow };
+use std::mem;
+use std::cell::RefCell;
+use std::sync::Arc;
 use std::marker::PhantomData;
 use std::any::TypeId;
 use std::cmp::Ordering;
@@ -10,133 +13,451 @@ use std::result::Result as StdResult;
 use std::hash::{ Hash, Hasher };
 use std::fmt::{ Debug, Formatter, Result as FmtResult };
 use serde::Deserialize;
-use bson::{ Bson, Document, from_bson };
+use bson::{ Bson, Document, from_bson, oid::ObjectId };
 use mongodb::options::{
-    UpdateOptions,
+    UpdateOptions as DriverUpdateOptions,
+    CollectionOptions,
+    FindOptions,
+    CursorType,
+    SelectionCriteria,
+    ReadPreference,
+    ReadConcern,
     FindOneAndDeleteOptions,
     FindOneAndUpdateOptions,
     ReturnDocument,
+    IndexModel,
 };
 use mongodb::results::UpdateResult;
+use mongodb::CommandType;
 use typemap::Key;
 use crate::{
-    cursor::Cursor,
+    cursor::{ Cursor, transform_and_deserialize },
+    db::DatabaseExt,
     doc::Doc,
     uid::Uid,
+    sequence,
     ops::*,
+    options::{ ReadOptions, UpdateOptions, DeleteOptions },
     bsn::*,
     utils::*,
-    error::{ Error, ErrorKind::{ MissingId, BsonDecoding }, Result, ResultExt },
+    retry::{ RetryPolicy, OperationKind },
+    instrument::{ self, Instrument, Counts, Documents },
+    explain::{ ExplainPlan, Verbosity },
+    error::{
+        Error,
+        ErrorKind::{
+            MissingId, MissingDocumentField, BsonDecoding,
+            MongoDbError, MongoDbWriteException, MongoDbBulkWriteException,
+        },
+        ServerError, Result, ResultExt,
+    },
 };
 
 /// A statically-typed (homogeneous) `MongoDB` collection.
 pub struct Collection<T: Doc> {
     /// The backing `MongoDB` collection.
     inner: mongodb::Collection,
+    /// Governs how operations failing with transient errors are retried.
+    retry: RetryPolicy,
+    /// Receives a description of every operation, if installed.
+    instrument: Option<Arc<dyn Instrument>>,
     /// Just here so that the type parameter is used.
     _marker: PhantomData<T>,
 }
 
 impl<T: Doc> Collection<T> {
+    /// Sets the retry policy of this collection handle. By default, failed
+    /// operations are not retried. See the [`retry`](../retry/index.html)
+    /// module for which operations are retried under which circumstances.
+    pub fn with_retry_policy(self, retry: RetryPolicy) -> Self {
+        Collection { retry, ..self }
+    }
+
+    /// Returns the retry policy of this collection handle.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Returns a collection handle that routes read operations according to
+    /// `read_preference`, e.g. to secondaries for analytics queries. This
+    /// overrides the default set by `Doc::read_options()`. Individual
+    /// operations can still override it via their `read_options()` method.
+    pub fn with_read_preference(self, read_preference: ReadPreference) -> Self {
+        let inner = reconfigured::<T>(&self.inner, read_preference.into());
+        Collection { inner, ..self }
+    }
+
+    /// Returns a collection handle whose read operations use `read_concern`,
+    /// e.g. `ReadConcern::Majority`. This overrides the default set by
+    /// `Doc::read_options()`. Individual operations can still override it
+    /// via their `read_options()` method.
+    pub fn with_read_concern(self, read_concern: ReadConcern) -> Self {
+        let inner = reconfigured::<T>(&self.inner, read_concern.into());
+        Collection { inner, ..self }
+    }
+
+    /// Returns the backing collection for a read operation with the given
+    /// per-operation read options. It's only re-created if necessary.
+    fn reader(&self, read_options: ReadOptions) -> Cow<mongodb::Collection> {
+        if read_options.is_empty() {
+            Cow::Borrowed(&self.inner)
+        } else {
+            Cow::Owned(reconfigured::<T>(&self.inner, read_options))
+        }
+    }
+
+    /// Runs `op` under the retry policy of this collection.
+    fn retrying<R, F>(&self, kind: OperationKind, op: F) -> Result<R>
+        where F: FnMut() -> Result<R>
+    {
+        self.retry.run(kind, op)
+    }
+
+    /// Installs an instrument on this collection handle, which will be
+    /// notified of every operation instead of the global instrument.
+    /// See the [`instrument`](../instrument/index.html) module for details.
+    pub fn with_instrument(self, instrument: Arc<dyn Instrument>) -> Self {
+        Collection { instrument: Some(instrument), ..self }
+    }
+
+    /// Runs `op`, reporting it to the instrument of this collection, or to
+    /// the global instrument if this collection doesn't have its own.
+    fn instrumented<R, D, X, C, F>(
+        &self,
+        operation: &'static str,
+        documents: D,
+        counts: C,
+        op: F,
+    ) -> Result<R>
+        where D: FnOnce() -> X,
+              X: Into<Documents>,
+              C: FnOnce(&R) -> Counts,
+              F: FnOnce() -> Result<R>,
+    {
+        instrument::observe(self.hook(), operation, T::NAME, documents, counts, op)
+    }
+
+    /// Returns the instrument of this collection, or the global instrument
+    /// if this collection doesn't have its own.
+    fn hook(&self) -> Option<Arc<dyn Instrument>> {
+        self.instrument.clone().or_else(instrument::global)
+    }
+
+    /// Returns a place for keeping copies of the documents that an operation
+    /// sends, which are only kept if there's an instrument to report them to.
+    fn sent_documents(&self) -> SentDocuments {
+        SentDocuments(self.hook().map(|_| RefCell::new(Vec::new())))
+    }
+
     /// Creates indexes on the underlying `MongoDB` collection
     /// according to the given index specifications.
     pub fn create_indexes(&self) -> Result<()> {
-        let indexes = T::indexes();
-        if indexes.is_empty() {
-            Ok(())
-        } else {
-            self.inner
-                .create_indexes(indexes)
-                .map(drop)
-                .chain(|| format!("can't create indexes on {}", T::NAME))
+        self.create_index_models(T::indexes)
+    }
+
+    /// Creates the indexes returned by `models` on the underlying `MongoDB`
+    /// collection. Used by the helpers which store their own bookkeeping
+    /// data in the documents, and thus need indexes beyond `T::indexes()`.
+    pub(crate) fn create_index_models<F>(&self, models: F) -> Result<()>
+        where F: Fn() -> Vec<IndexModel>
+    {
+        let documents = || Documents::others(models().into_iter().map(|model| model.keys).collect());
+
+        self.instrumented("create_indexes", documents, no_counts, || {
+            if models().is_empty() {
+                Ok(())
+            } else {
+                self.retrying(OperationKind::IdempotentWrite, || {
+                    self.inner
+                        .create_indexes(models())
+                        .map(drop)
+                        .chain(|| format!("can't create indexes on {}", T::NAME))
+                })
+            }
+        })
+    }
+
+    /// Returns the collection of `U` in the same database, with the same
+    /// retry policy and instrument. Used by the helpers which keep their
+    /// bookkeeping data in a collection of its own.
+    pub(crate) fn sibling<U: Doc>(&self) -> Collection<U> {
+        Collection {
+            retry: self.retry,
+            instrument: self.instrument.clone(),
+            ..self.inner.db.existing_collection()
         }
     }
 
     /// Deletes the collection.
     pub fn drop(&self) -> Result<()> {
-        self.inner.drop().map_err(Into::into)
+        self.instrumented("drop", || (None, None), no_counts, || {
+            self.inner.drop().map_err(Into::into)
+        })
     }
 
     /// Returns the number of documents matching the query criteria.
     pub fn count<Q: Count<T>>(&self, query: Q) -> Result<usize> {
-        self.inner
-            .count(query.filter().into(), query.options().into())
-            .chain(|| format!("error in {}::count({:#?})", T::NAME, query))
-            .and_then(|n| int_to_usize_with_msg(n, "# of counted documents"))
+        self.instrumented("count", || (Some(query.filter()), None), |&n| Counts::matched(n), || {
+            let reader = self.reader(query.read_options());
+            self.retrying(OperationKind::Read, || {
+                reader
+                    .count(query.filter().into(), query.options().into())
+                    .chain(|| format!("error in {}::count({:#?})", T::NAME, query))
+            }).and_then(|n| int_to_usize_with_msg(n, "# of counted documents"))
+        })
     }
 
     /// Returns the distinct values of a certain field.
+    ///
+    /// A [`Field`](../field/struct.Field.html) can be passed as the query,
+    /// in which case the element type of the returned collection is the
+    /// type of the field itself.
     pub fn distinct<Q, C>(&self, query: Q) -> Result<C>
         where Q: Distinct<T>,
               C: FromIterator<Q::Output>,
     {
-        self.inner
-            .distinct(Q::FIELD, query.filter().into(), query.options().into())
-            .chain(|| format!("error in {}::distinct({:#?})", T::NAME, query))
-            .and_then(|values| {
+        self.instrumented("distinct", || (Some(query.filter()), None), no_counts, || {
+            let reader = self.reader(query.read_options());
+            self.retrying(OperationKind::Read, || {
+                reader
+                    .distinct(query.field(), query.filter().into(), query.options().into())
+                    .chain(|| format!("error in {}::distinct({:#?})", T::NAME, query))
+            }).and_then(|values| {
                 values
                     .into_iter()
                     .map(|b| from_bson(Q::transform(b)?).chain(|| format!(
-                        "can't deserialize {}::{}", T::NAME, Q::FIELD
+                        "can't deserialize {}::{}", T::NAME, query.field()
                     )))
                     .collect()
             })
+        })
     }
 
     /// Runs an aggregation pipeline.
     pub fn aggregate<P: Pipeline<T>>(&self, pipeline: P) -> Result<Cursor<P::Output>> {
-        self.inner
-            .aggregate(pipeline.stages(), pipeline.options().into())
-            .chain(|| format!("error in {}::aggregate({:#?})", T::NAME, pipeline))
-            .map(|crs| Cursor::from_cursor_and_transform(crs, P::transform))
+        let documents = || Documents::others(pipeline.stages());
+
+        let inner = self.instrumented("aggregate", documents, no_counts, || {
+            let reader = self.reader(pipeline.read_options());
+            self.retrying(OperationKind::Read, || {
+                reader
+                    .aggregate(pipeline.stages(), pipeline.options().into())
+                    .chain(|| format!("error in {}::aggregate({:#?})", T::NAME, pipeline))
+            })
+        })?;
+
+        Ok(Cursor::from_cursor_and_optional_transformer(inner, pipeline.transformer()))
     }
 
     /// Retrieves a single document satisfying the query, if one exists.
     pub fn find_one<Q: Query<T>>(&self, query: Q) -> Result<Option<Q::Output>> {
-        // This uses `impl Deserialize for Option<T> where T: Deserialize`
-        // and the fact that in MongoDB, top-level documents are always
-        // `Document`s and never `Null`.
-        self.inner
-            .find_one(query.filter().into(), query.options().into())
-            .chain(|| format!("error in {}::find_one({:#?})", T::NAME, query))
-            .and_then(|opt| opt.map_or(Ok(None), |doc| {
-                let transformed = Q::transform(doc)?;
-                from_bson(transformed).map_err(From::from)
+        self.instrumented("find_one", || (Some(query.filter()), None), found, || {
+            // This uses `impl Deserialize for Option<T> where T: Deserialize`
+            // and the fact that in MongoDB, top-level documents are always
+            // `Document`s and never `Null`.
+            let reader = self.reader(query.read_options());
+            self.retrying(OperationKind::Read, || {
+                reader
+                    .find_one(query.filter().into(), query.options().into())
+                    .chain(|| format!("error in {}::find_one({:#?})", T::NAME, query))
+            }).and_then(|opt| opt.map_or(Ok(None), |doc| {
+                // Unless the query deserializes documents as-is, this boxes
+                // its transform for a single document. That's one small
+                // allocation, which is negligible next to the round trip.
+                transform_and_deserialize(query.transformer().as_ref(), doc)
             }))
+        })
     }
 
     /// Retrieves all documents satisfying the query.
     pub fn find_many<Q: Query<T>>(&self, query: Q) -> Result<Cursor<Q::Output>> {
-        self.inner
-            .find(query.filter().into(), query.options().into())
-            .chain(|| format!("error in {}::find_many({:#?})", T::NAME, query))
-            .map(|crs| Cursor::from_cursor_and_transform(crs, Q::transform))
+        let inner = self.instrumented("find_many", || (Some(query.filter()), None), no_counts, || {
+            let reader = self.reader(query.read_options());
+            self.retrying(OperationKind::Read, || {
+                reader
+                    .find(query.filter().into(), query.options().into())
+                    .chain(|| format!("error in {}::find_many({:#?})", T::NAME, query))
+            })
+        })?;
+
+        Ok(Cursor::from_cursor_and_optional_transformer(inner, query.transformer()))
+    }
+
+    /// Retrieves the documents satisfying the query from a capped collection
+    /// (see `Doc::capped()`), then keeps waiting for new matching documents
+    /// to be inserted, like `tail -f` does. Iterating the returned cursor
+    /// blocks until the next document arrives, polling the server once per
+    /// `maxAwaitTimeMS` (one second by default) while there are none.
+    ///
+    /// Documents are returned in insertion order, so the query shouldn't
+    /// specify a sort. The collection must not be empty when the cursor is
+    /// opened, otherwise the server doesn't keep the cursor alive, and the
+    /// iteration ends right away. It also ends whenever the server closes
+    /// the cursor later, e.g. because it fell behind the capped collection.
+    pub fn tail<Q: Query<T>>(&self, query: Q) -> Result<Cursor<Q::Output>> {
+        let inner = self.instrumented("tail", || (Some(query.filter()), None), no_counts, || {
+            let reader = self.reader(query.read_options());
+            let options = FindOptions {
+                cursor_type: Some(CursorType::TailableAwait),
+                ..query.options()
+            };
+
+            self.retrying(OperationKind::Read, || {
+                reader
+                    .find(query.filter().into(), options.clone().into())
+                    .chain(|| format!("error in {}::tail({:#?})", T::NAME, query))
+            })
+        })?;
+        let cursor = Cursor::from_cursor_and_optional_transformer(inner, query.transformer());
+
+        Ok(cursor.into_tailable())
+    }
+
+    /// Describes how the server would execute `find_many(query)`.
+    pub fn explain_find<Q: Query<T>>(&self, query: Q, verbosity: Verbosity) -> Result<ExplainPlan> {
+        let options = query.options();
+        let mut command = doc!{
+            "find": T::NAME,
+            "filter": query.filter(),
+        };
+
+        if let Some(sort) = options.sort {
+            command.insert("sort", sort);
+        }
+        if let Some(projection) = options.projection {
+            command.insert("projection", projection);
+        }
+        if let Some(skip) = options.skip {
+            command.insert("skip", skip);
+        }
+        if let Some(limit) = options.limit {
+            command.insert("limit", limit);
+        }
+        if let Some(ref hint) = options.hint {
+            command.insert("hint", bson::to_bson(hint)?);
+        }
+        if let Some(ref collation) = options.collation {
+            command.insert("collation", bson::to_bson(collation)?);
+        }
+
+        self.explain(command, query.read_options(), verbosity)
+            .chain(|| format!("can't explain {}::find_many({:#?})", T::NAME, query))
+    }
+
+    /// Describes how the server would execute `count(query)`.
+    pub fn explain_count<Q: Count<T>>(&self, query: Q, verbosity: Verbosity) -> Result<ExplainPlan> {
+        let options = query.options();
+        let mut command = doc!{
+            "count": T::NAME,
+            "query": query.filter(),
+        };
+
+        if let Some(skip) = options.skip {
+            command.insert("skip", skip);
+        }
+        if let Some(limit) = options.limit {
+            command.insert("limit", limit);
+        }
+        if let Some(ref hint) = options.hint {
+            command.insert("hint", bson::to_bson(hint)?);
+        }
+        if let Some(ref collation) = options.collation {
+            command.insert("collation", bson::to_bson(collation)?);
+        }
+
+        self.explain(command, query.read_options(), verbosity)
+            .chain(|| format!("can't explain {}::count({:#?})", T::NAME, query))
+    }
+
+    /// Describes how the server would execute `aggregate(pipeline)`.
+    pub fn explain_aggregate<P: Pipeline<T>>(&self, pipeline: P, verbosity: Verbosity) -> Result<ExplainPlan> {
+        let options = pipeline.options();
+        let mut read_options = pipeline.read_options();
+        let mut command = doc!{
+            "aggregate": T::NAME,
+            "pipeline": pipeline.stages(),
+            "cursor": {},
+        };
+
+        if let Some(allow_disk_use) = options.allow_disk_use {
+            command.insert("allowDiskUse", allow_disk_use);
+        }
+        if let Some(ref hint) = options.hint {
+            command.insert("hint", bson::to_bson(hint)?);
+        }
+        if let Some(ref collation) = options.collation {
+            command.insert("collation", bson::to_bson(collation)?);
+        }
+        if options.read_concern.is_some() {
+            read_options.read_concern = options.read_concern;
+        }
+
+        self.explain(command, read_options, verbosity)
+            .chain(|| format!("can't explain {}::aggregate({:#?})", T::NAME, pipeline))
+    }
+
+    /// Runs the `explain` command on the given command and parses its reply.
+    /// The read concern and the read preference are those the explained
+    /// operation itself would use with the given per-operation read options.
+    fn explain(&self, mut command: Document, read_options: ReadOptions, verbosity: Verbosity) -> Result<ExplainPlan> {
+        let reader = self.reader(read_options);
+
+        if let Some(read_concern) = reader.read_concern() {
+            command.insert("readConcern", doc!{ "level": read_concern.as_str() });
+        }
+
+        let selection_criteria = reader.selection_criteria().cloned();
+        let explain = doc!{
+            "explain": command,
+            "verbosity": verbosity,
+        };
+
+        self.instrumented("explain", || Documents::others(vec![explain.clone()]), no_counts, || {
+            self.retrying(OperationKind::Read, || {
+                reader.db
+                    .command(explain.clone(), CommandType::Suppressed, selection_criteria.clone())
+                    .map_err(Into::into)
+            }).and_then(check_ok).and_then(ExplainPlan::from_raw)
+        })
     }
 
     /// Inserts a single document.
     pub fn insert_one(&self, entity: &T) -> Result<Uid<T>> {
-        let doc = serialize_document(entity)?;
-        let write_concern = T::insert_options().write_concern;
-        let message = || format!("error in {}::insert_one()", T::NAME);
+        let sent = self.sent_documents();
 
-        self.inner
-            .insert_one(doc, write_concern)
-            .chain(&message)
-            .and_then(|result| {
-                if let Some(error) = result.write_exception {
-                    Err(Error::with_cause(message(), error))
-                } else if let Some(id) = result.inserted_id {
-                    from_bson(id).chain(
-                        || format!("can't deserialize ID for {}", T::NAME)
-                    )
-                } else {
-                    Err(Error::new(MissingId, message() + ": missing `inserted_id`"))
-                }
-            })
+        self.instrumented("insert_one", || sent.take(), |_| Counts::inserted(1), || {
+            let mut doc = serialize_entity(entity)?;
+            self.assign_id(&mut doc)?;
+            sent.record(&doc);
+            self.insert_document(doc, "insert_one")
+        })
+    }
+
+    /// Inserts a single document, after assigning it an ID on the client
+    /// side if it doesn't have one yet, using `Doc::set_id()`. The ID comes
+    /// from the sequence or the ID generator of `T` if it has one; otherwise,
+    /// an `ObjectId` is generated, just like the server would do.
+    ///
+    /// Thus, when this method returns, `entity` is exactly what was stored.
+    /// The ID is assigned even if the insertion itself fails.
+    pub fn insert_one_mut(&self, entity: &mut T) -> Result<Uid<T>> {
+        let sent = self.sent_documents();
+
+        self.instrumented("insert_one_mut", || sent.take(), |_| Counts::inserted(1), || {
+            let doc = self.identified_document(entity)?;
+            sent.record(&doc);
+            self.insert_document(doc, "insert_one_mut")
+        })
     }
 
     /// Inserts many documents.
     ///
+    /// The documents are sent in as many batches as necessary for respecting
+    /// the limits of the server on the size and the number of documents in a
+    /// single command. The entities are serialized lazily, batch by batch,
+    /// so `entities` can be a streaming iterator of any length: memory usage
+    /// is bounded by the size of a batch (plus the returned IDs).
+    ///
     /// If this method fails to insert all documents, the returned error will
     /// contain as context info the IDs of the documents successfully inserted.
     /// If possible, each ID will be deserialized as an `Ok(Uid<T>)`; otherwise
@@ -144,78 +465,332 @@ impl<T: Doc> Collection<T> {
     /// for IDs which couldn't be deserialized.
     ///
     /// The context map can be accessed as: `error.context::<InsertManyErrorContext<T>>()`
+    ///
+    /// If some documents were rejected by the server, e.g. because of a
+    /// duplicate key, the error also contains the reason of each failure, as
+    /// `error.context::<InsertFailure>()`. Unless `T::insert_options()` says
+    /// otherwise, the insertion is ordered, so it stops at the first failure.
+    ///
+    /// In all cases, the indexes are those of the entities in `entities`.
+    ///
+    /// If an `Instrument` is installed, a copy of every inserted document is
+    /// kept until the end of the operation, so that it can be reported.
     pub fn insert_many<I>(&self, entities: I) -> Result<BTreeMap<u64, Uid<T>>>
         where I: IntoIterator,
               I::Item: Borrow<T>,
-              I::IntoIter: ExactSizeIterator,
               T::Id: Clone + Debug,
               T: 'static,
     {
-        let values = entities.into_iter();
-        let n_docs = values.len();
-        let docs = serialize_documents(values)?;
-        let options = T::insert_options();
-        let message = || format!("error in {}::insert_many()", T::NAME);
+        let sent = self.sent_documents();
 
-        // MongoDB complains if you try to insert 0 documents, but that's silly.
-        if n_docs == 0 {
-            return Ok(BTreeMap::new());
+        self.instrumented("insert_many", || sent.take(), |ids| Counts::inserted(ids.len()), || {
+            let docs = entities.into_iter().map(|entity| {
+                let mut doc = serialize_entity(entity.borrow())?;
+                self.assign_id(&mut doc)?;
+                sent.record(&doc);
+                Ok((doc, ()))
+            });
+            let ordered = T::insert_options().ordered.unwrap_or(true);
+
+            self.insert_batched(docs, ordered, "insert_many", |_| None)
+                .and_then(RawInsert::into_ids)
+        })
+    }
+
+    /// Inserts many documents, after assigning an ID to each of them that
+    /// doesn't have one yet, in the same manner as `insert_one_mut()`.
+    /// Errors are reported in the same manner as by `insert_many()`.
+    pub fn insert_many_mut(&self, entities: &mut [T]) -> Result<BTreeMap<u64, Uid<T>>>
+        where T::Id: Clone + Debug,
+              T: 'static,
+    {
+        let sent = self.sent_documents();
+
+        self.instrumented("insert_many_mut", || sent.take(), |ids| Counts::inserted(ids.len()), || {
+            let docs = entities.iter_mut().map(|entity| {
+                let doc = self.identified_document(entity)?;
+                sent.record(&doc);
+                Ok((doc, ()))
+            });
+            let ordered = T::insert_options().ordered.unwrap_or(true);
+
+            self.insert_batched(docs, ordered, "insert_many_mut", |_| None)
+                .and_then(RawInsert::into_ids)
+        })
+    }
+
+    /// Inserts many documents without stopping at the first failure. The
+    /// documents rejected by the server (e.g. because of a duplicate key)
+    /// don't make this method fail; they are reported in the returned
+    /// `PartialInsert` instead, along with the `Debug` representation of
+    /// the corresponding entities. Batching works as in `insert_many()`.
+    ///
+    /// An error is only returned if the insertion couldn't be carried out
+    /// at all, or if the write concern couldn't be satisfied. In the latter
+    /// case, the error has the same context info as that of `insert_many()`.
+    pub fn insert_many_unordered<I>(&self, entities: I) -> Result<PartialInsert<T>>
+        where I: IntoIterator,
+              I::Item: Borrow<T>,
+              T::Id: Clone + Debug,
+              T: Debug + 'static,
+    {
+        let sent = self.sent_documents();
+
+        self.instrumented(
+            "insert_many_unordered",
+            || sent.take(),
+            |partial| Counts::inserted(partial.inserted.len()),
+            || {
+                let docs = entities.into_iter().map(|entity| {
+                    let mut doc = serialize_entity(entity.borrow())?;
+                    self.assign_id(&mut doc)?;
+                    sent.record(&doc);
+                    Ok((doc, entity))
+                });
+                let describe = |entity: &I::Item| Some(format!("{:#?}", entity.borrow()));
+
+                self.insert_batched(docs, false, "insert_many_unordered", describe)
+                    .and_then(RawInsert::into_partial)
+            }
+        )
+    }
+
+    /// Serializes an entity to be inserted, after assigning it an ID if it
+    /// has none. Makes sure that the ID round-trips through `Doc::set_id()`.
+    fn identified_document(&self, entity: &mut T) -> Result<Document> {
+        let mut doc = serialize_entity(entity)?;
+
+        match doc.get("_id") {
+            None | Some(&Bson::Null) => {}
+            Some(_) => return Ok(doc),
         }
 
+        self.assign_id(&mut doc)?;
+
+        let raw_id = match doc.remove("_id") {
+            None | Some(Bson::Null) => Bson::ObjectId(ObjectId::new()?),
+            Some(id) => id,
+        };
+        let id = from_bson(raw_id.clone()).chain(
+            || format!("can't use generated ID {} for {}", raw_id, T::NAME)
+        )?;
+
+        entity.set_id(id);
+
+        let stored = serialize_entity(entity)?;
+
+        if stored.get("_id") == Some(&raw_id) {
+            Ok(stored)
+        } else {
+            Err(Error::new(MissingId, format!(
+                "{}::set_id() didn't store the generated ID {}", T::NAME, raw_id
+            )))
+        }
+    }
+
+    /// Inserts a single serialized document.
+    fn insert_document(&self, doc: Document, operation: &str) -> Result<Uid<T>> {
+        let write_concern = T::insert_options().write_concern;
+        let message = || format!("error in {}::{}()", T::NAME, operation);
+
         self.inner
-            .insert_many(docs, options.into())
+            .insert_one(doc, write_concern)
             .chain(&message)
             .and_then(|result| {
-                // Attempt to deserialize the returned IDs as `Uid<T>`.
-                let ids: BTreeMap<_, _> = result.inserted_ids
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|(i, id)| {
-                        assert!(i >= 0, "negative index {} for id {}", i, id);
-                        (i as u64, from_bson(id.clone()).map_err(|_| id))
-                    })
-                    .collect();
-
-                if let Some(error) = result.bulk_write_exception {
-                    // If there was an insertion error, report an error, but
-                    // return all the IDs of the inserted documents anyway.
-                    Err(Error::with_cause(message(), error)
-                        .with_context::<InsertManyErrorContext<T>>(ids))
-                } else if ids.len() == n_docs {
-                    // If there's exacly one ID returned for each document,
-                    // that's a success - at least when we were able to BSON
-                    // decode all the returned IDs.
-                    let ids_res: StdResult<BTreeMap<_, _>, _> = ids
-                        .clone()
-                        .into_iter()
-                        .map(|(i, res)| res.map(|id| (i, id)))
-                        .collect();
-
-                    ids_res.map_err(|_| Error::new(
-                        BsonDecoding,
-                        format!("{}: can't deserialize some IDs", message())
-                    ).with_context::<InsertManyErrorContext<T>>(
-                        ids
-                    ))
+                if let Some(error) = result.write_exception {
+                    Err(Error::with_cause(message(), error))
+                } else if let Some(id) = result.inserted_id {
+                    from_bson(id).chain(
+                        || format!("can't deserialize ID for {}", T::NAME)
+                    )
                 } else {
-                    // If the # of inserted IDs doesn't match the # of
-                    // documents originally given, something is fishy.
-                    let msg = format!("{}: {} documents given, but {} IDs returned",
-                                      message(), n_docs, ids.len());
-
-                    Err(Error::new(MissingId, msg)
-                        .with_context::<InsertManyErrorContext<T>>(ids))
+                    Err(Error::new(MissingId, message() + ": missing `inserted_id`"))
                 }
             })
     }
 
+    /// Inserts many serialized documents, each along with the entity it was
+    /// created from (or any other payload), in batches of the maximal size
+    /// allowed for a single `insert` command. Collects the IDs of the inserted
+    /// documents as well as the reasons for the failure of the others.
+    /// `describe` provides the `Debug` representation of a payload, if any.
+    fn insert_batched<D, E, F>(
+        &self,
+        docs: D,
+        ordered: bool,
+        operation: &'static str,
+        describe: F,
+    ) -> Result<RawInsert<T>>
+        where D: IntoIterator<Item = Result<(Document, E)>>,
+              F: Fn(&E) -> Option<String>,
+              T::Id: Clone + Debug,
+              T: 'static,
+    {
+        let mut raw = RawInsert {
+            operation,
+            n_docs: 0,
+            ids: BTreeMap::new(),
+            failures: Vec::new(),
+        };
+        let mut batch = InsertBatch::new(ordered);
+
+        for item in docs {
+            let (mut doc, payload) = item.map_err(|error| raw.attach_to(error))?;
+
+            // Unlike the driver, the `insert` command doesn't return the IDs
+            // of the inserted documents, so the missing ones are generated here.
+            let id = match doc.get("_id").cloned() {
+                None | Some(Bson::Null) => {
+                    let generated = Bson::ObjectId(ObjectId::new()?);
+                    doc.insert("_id", generated.clone());
+                    generated
+                }
+                Some(existing) => existing,
+            };
+            let size = document_size(&doc) + INSERT_ELEMENT_OVERHEAD;
+
+            if batch.is_full(size) {
+                self.insert_batch(&mut batch, &mut raw, &describe)?;
+
+                // An ordered insertion stops at the first failure.
+                if ordered && !raw.failures.is_empty() {
+                    return Ok(raw);
+                }
+            }
+
+            batch.push(doc, id, payload, size);
+            raw.n_docs += 1;
+        }
+
+        // MongoDB complains if you try to insert 0 documents, but that's silly.
+        if !batch.docs.is_empty() {
+            self.insert_batch(&mut batch, &mut raw, &describe)?;
+        }
+
+        Ok(raw)
+    }
+
+    /// Inserts a single batch of documents using an `insert` command, and
+    /// empties the batch. The IDs and the failures are added to `raw`.
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn insert_batch<E, F>(
+        &self,
+        batch: &mut InsertBatch<E>,
+        raw: &mut RawInsert<T>,
+        describe: &F,
+    ) -> Result<()>
+        where F: Fn(&E) -> Option<String>,
+              T::Id: Clone + Debug,
+              T: 'static,
+    {
+        let options = T::insert_options();
+        let offset = raw.n_docs - batch.docs.len();
+        let docs: Vec<_> = batch.docs.drain(..).map(Bson::Document).collect();
+        let raw_ids: Vec<_> = batch.ids.drain(..).collect();
+        let payloads: Vec<_> = batch.payloads.drain(..).collect();
+        let mut command = doc!{
+            "insert": T::NAME,
+            "documents": docs,
+            "ordered": batch.ordered,
+        };
+
+        batch.size = 0;
+
+        // `ordered` comes from the batch, since some insertion methods
+        // override it; every other option is forwarded as-is.
+        if let Some(bypass) = options.bypass_document_validation {
+            command.insert("bypassDocumentValidation", bypass);
+        }
+        if let Some(ref write_concern) = options.write_concern {
+            command.insert("writeConcern", write_concern.to_bson());
+        }
+
+        // A reply without `ok: 1` means that nothing was inserted, and
+        // it has no `n` or `writeErrors` to collect the IDs from.
+        let reply = self.inner.db
+            .command(command, CommandType::Suppressed, None)
+            .map_err(Into::into)
+            .and_then(check_ok)
+            .chain(|| raw.message())
+            .map_err(|error| raw.attach_to(error))?;
+
+        let mut failed = Vec::new();
+
+        for error in reply.get_array("writeErrors").map(Vec::as_slice).unwrap_or(&[]) {
+            if let Bson::Document(ref error) = *error {
+                let index = match error.get("index") {
+                    Some(&Bson::I32(index)) => index as usize,
+                    Some(&Bson::I64(index)) => index as usize,
+                    _ => continue,
+                };
+
+                failed.push(index);
+                raw.failures.push(InsertFailure {
+                    index: (offset + index) as u64,
+                    error: ServerError::from_document(error),
+                    entity: payloads.get(index).and_then(describe),
+                });
+            }
+        }
+
+        // An ordered insertion stops at the first failure, while an
+        // unordered one attempts to insert every document.
+        let first_failure = failed.iter().cloned().min();
+        let mut n_expected = 0;
+
+        for (i, id) in raw_ids.into_iter().enumerate() {
+            let inserted = if batch.ordered {
+                first_failure.map_or(true, |first| i < first)
+            } else {
+                !failed.contains(&i)
+            };
+
+            if inserted {
+                raw.ids.insert((offset + i) as u64, from_bson(id.clone()).map_err(|_| id));
+                n_expected += 1;
+            }
+        }
+
+        let n_inserted = match reply.get("n") {
+            Some(&Bson::I32(n)) => n as usize,
+            Some(&Bson::I64(n)) => n as usize,
+            _ => {
+                let msg = format!("{}: no number of inserted documents in reply", raw.message());
+                return Err(raw.attach_to(Error::new(MissingDocumentField, msg)));
+            }
+        };
+
+        if n_inserted != n_expected {
+            // If the # of inserted documents doesn't match the # of IDs
+            // we think were inserted, something is fishy.
+            let msg = format!("{}: {} documents inserted, but {} IDs expected",
+                              raw.message(), n_inserted, n_expected);
+
+            return Err(raw.attach_to(Error::new(MissingId, msg)));
+        }
+
+        if let Ok(error) = reply.get_document("writeConcernError") {
+            // The documents were inserted, but not durably enough.
+            let server_error = ServerError::from_document(error);
+            let msg = format!("{}: {}", raw.message(), server_error.message);
+            let error = Error::new(server_error.kind_or(MongoDbWriteException), msg)
+                .with_context::<ServerError>(server_error);
+
+            return Err(raw.attach_to(error));
+        }
+
+        Ok(())
+    }
+
     /// Convenience method for updating a single document based on identity (its
     /// `_id` field), setting all fields to the values supplied by `entity`.
     ///
     /// This doesn't add a new document if none with the specified `_id` exists.
     pub fn replace_entity(&self, entity: &T) -> Result<UpdateOneResult> where T: Debug {
-        self.update_entity_internal(entity, false)
-            .and_then(UpdateOneResult::from_raw)
+        self.instrumented("replace_entity", || entity_documents(entity), UpdateOneResult::counts, || {
+            self.update_entity_internal(entity, false)
+                .and_then(UpdateOneResult::from_raw)
+        })
     }
 
     /// Convenience method for updating a single document based on identity (its
@@ -223,38 +798,57 @@ impl<T: Doc> Collection<T> {
     ///
     /// This method adds a new document if none with the specified `_id` exists.
     pub fn upsert_entity(&self, entity: &T) -> Result<UpsertOneResult<Uid<T>>> where T: Debug {
-        self.update_entity_internal(entity, true)
-            .and_then(UpsertOneResult::from_raw)
+        self.instrumented("upsert_entity", || entity_documents(entity), UpsertOneResult::counts, || {
+            self.update_entity_internal(entity, true)
+                .and_then(UpsertOneResult::from_raw)
+        })
     }
 
     /// Helper for the `{...}_entity` convenience methods above.
     fn update_entity_internal(&self, entity: &T, upsert: bool) -> Result<UpdateResult>
         where T: Debug
     {
-        let mut document = serialize_document(entity)?;
+        let mut document = serialize_entity(entity)?;
         let id = document.remove("_id").ok_or_else(
             || Error::new(MissingId, format!("No `_id` in entity of type {}", T::NAME))
         )?;
         let filter = doc!{ "_id": id };
-        let options = UpdateOptions {
-            upsert: upsert.into(),
-            write_concern: T::update_options().into(),
-        };
+        let options = T::replace_options();
         let message = || format!("error in {}::{}_entity({:#?})",
                                  T::NAME,
                                  if upsert { "upsert" } else { "replace" },
                                  entity);
 
-        self.inner
-            .replace_one(filter, document, options.into())
-            .chain(&message)
-            .and_then(|result| {
-                if let Some(error) = result.write_exception {
-                    Err(Error::with_cause(message(), error))
-                } else {
-                    Ok(result)
-                }
-            })
+        // Replacing a document by its `_id` is idempotent.
+        self.retrying(OperationKind::IdempotentWrite, || {
+            let outcome = if options.is_write_concern_only() {
+                let driver_options = DriverUpdateOptions {
+                    upsert: Some(upsert),
+                    write_concern: options.write_concern.clone(),
+                };
+                self.inner
+                    .replace_one(filter.clone(), document.clone(), driver_options.into())
+                    .map_err(From::from)
+            } else {
+                self.update_command(
+                    filter.clone(),
+                    document.clone(),
+                    upsert,
+                    false,
+                    &options.clone().into(),
+                )
+            };
+
+            outcome
+                .chain(&message)
+                .and_then(|result| {
+                    if let Some(error) = result.write_exception {
+                        Err(Error::with_cause(message(), error))
+                    } else {
+                        Ok(result)
+                    }
+                })
+        })
     }
 
     /// Updates a single document.
@@ -262,16 +856,17 @@ impl<T: Doc> Collection<T> {
     /// This method only works with update operators (with field names starting
     /// with `$`), i.e. it does **not** replace entire documents.
     pub fn update_one<U: Update<T>>(&self, update: U) -> Result<UpdateOneResult> {
-        let filter = update.filter();
-        let change = update.update();
-        let options = UpdateOptions {
-            upsert: Some(false),
-            write_concern: update.options().into(),
-        };
-        let message = || format!("error in {}::update_one({:#?})", T::NAME, update);
+        let documents = || (Some(update.filter()), Some(update.update()));
 
-        self.update_one_internal(filter, change, options, &message)
-            .and_then(UpdateOneResult::from_raw)
+        self.instrumented("update_one", documents, UpdateOneResult::counts, || {
+            let filter = update.filter();
+            let change = update.update();
+            let options = update.options();
+            let message = || format!("error in {}::update_one({:#?})", T::NAME, update);
+
+            self.update_one_internal(filter, change, false, &options, &message)
+                .and_then(UpdateOneResult::from_raw)
+        })
     }
 
     /// Upserts a single document.
@@ -279,16 +874,17 @@ impl<T: Doc> Collection<T> {
     /// This method only works with update operators (with field names starting
     /// with `$`), i.e. it does **not** replace entire documents.
     pub fn upsert_one<U: Upsert<T>>(&self, upsert: U) -> Result<UpsertOneResult<Uid<T>>> {
-        let filter = upsert.filter();
-        let change = upsert.upsert();
-        let options = UpdateOptions {
-            upsert: Some(true),
-            write_concern: upsert.options().into(),
-        };
-        let message = || format!("error in {}::upsert_one({:#?})", T::NAME, upsert);
+        let documents = || (Some(upsert.filter()), Some(upsert.upsert()));
+
+        self.instrumented("upsert_one", documents, UpsertOneResult::counts, || {
+            let filter = upsert.filter();
+            let change = upsert.upsert();
+            let options = upsert.options();
+            let message = || format!("error in {}::upsert_one({:#?})", T::NAME, upsert);
 
-        self.update_one_internal(filter, change, options, &message)
-            .and_then(UpsertOneResult::from_raw)
+            self.update_one_internal(filter, change, true, &options, &message)
+                .and_then(UpsertOneResult::from_raw)
+        })
     }
 
     /// Updates or upserts a single document.
@@ -299,11 +895,21 @@ impl<T: Doc> Collection<T> {
         &self,
         filter: Document,
         change: Document,
-        options: UpdateOptions,
+        upsert: bool,
+        options: &UpdateOptions,
         message: F,
     ) -> Result<UpdateResult> {
-        self.inner
-            .update_one(filter, change, options.into())
+        let outcome = if options.is_write_concern_only() {
+            let driver_options = DriverUpdateOptions {
+                upsert: Some(upsert),
+                write_concern: options.write_concern.clone(),
+            };
+            self.inner.update_one(filter, change, driver_options.into()).map_err(From::from)
+        } else {
+            self.update_command(filter, change, upsert, false, options)
+        };
+
+        outcome
             .chain(message)
             .and_then(|result| {
                 if let Some(error) = result.write_exception {
@@ -319,14 +925,15 @@ impl<T: Doc> Collection<T> {
     /// This method only works with update operators (with field names starting
     /// with `$`), i.e. it does **not** replace entire documents.
     pub fn update_many<U: Update<T>>(&self, update: U) -> Result<UpdateManyResult> {
-        let filter = update.filter();
-        let change = update.update();
-        let options = UpdateOptions {
-            upsert: Some(false),
-            write_concern: update.options().into(),
-        };
-        let message = || format!("error in {}::update_many({:#?})", T::NAME, update);
-        self.update_many_internal(filter, change, options, &message)
+        let documents = || (Some(update.filter()), Some(update.update()));
+
+        self.instrumented("update_many", documents, UpdateManyResult::counts, || {
+            let filter = update.filter();
+            let change = update.update();
+            let options = update.options();
+            let message = || format!("error in {}::update_many({:#?})", T::NAME, update);
+            self.update_many_internal(filter, change, false, &options, &message)
+        })
     }
 
     /// Upserts multiple documents (updates many or inserts one if none found).
@@ -334,14 +941,15 @@ impl<T: Doc> Collection<T> {
     /// This method only works with update operators (with field names starting
     /// with `$`), i.e. it does **not** replace entire documents.
     pub fn upsert_many<U: Upsert<T>>(&self, upsert: U) -> Result<UpsertManyResult> {
-        let filter = upsert.filter();
-        let change = upsert.upsert();
-        let options = UpdateOptions {
-            upsert: Some(true),
-            write_concern: upsert.options().into(),
-        };
-        let message = || format!("error in {}::upsert_many({:#?})", T::NAME, upsert);
-        self.update_many_internal(filter, change, options, &message)
+        let documents = || (Some(upsert.filter()), Some(upsert.upsert()));
+
+        self.instrumented("upsert_many", documents, UpdateManyResult::counts, || {
+            let filter = upsert.filter();
+            let change = upsert.upsert();
+            let options = upsert.options();
+            let message = || format!("error in {}::upsert_many({:#?})", T::NAME, upsert);
+            self.update_many_internal(filter, change, true, &options, &message)
+        })
     }
 
     /// Updates or upserts multiple documents.
@@ -352,11 +960,21 @@ impl<T: Doc> Collection<T> {
         &self,
         filter: Document,
         change: Document,
-        options: UpdateOptions,
+        upsert: bool,
+        options: &UpdateOptions,
         message: F,
     ) -> Result<UpdateManyResult> {
-        self.inner
-            .update_many(filter, change, options.into())
+        let outcome = if options.is_write_concern_only() {
+            let driver_options = DriverUpdateOptions {
+                upsert: Some(upsert),
+                write_concern: options.write_concern.clone(),
+            };
+            self.inner.update_many(filter, change, driver_options.into()).map_err(From::from)
+        } else {
+            self.update_command(filter, change, upsert, true, options)
+        };
+
+        outcome
             .chain(message)
             .and_then(|result| {
                 if let Some(error) = result.write_exception {
@@ -369,17 +987,155 @@ impl<T: Doc> Collection<T> {
             })
     }
 
+    /// Issues an `update` command directly. This is needed for passing
+    /// `arrayFilters`, a collation, an index hint or `bypassDocumentValidation`,
+    /// which the driver's update methods don't support.
+    /// Returns the same kind of result as the driver does.
+    fn update_command(
+        &self,
+        filter: Document,
+        change: Document,
+        upsert: bool,
+        multi: bool,
+        options: &UpdateOptions,
+    ) -> Result<UpdateResult> {
+        let mut statement = doc!{
+            "q": filter,
+            "u": change,
+            "upsert": upsert,
+            "multi": multi,
+        };
+
+        options.extend_statement(&mut statement);
+
+        let mut command = doc!{
+            "update": T::NAME,
+            "updates": [statement],
+        };
+
+        options.extend_command(&mut command);
+
+        let reply = self.write_command(command)?;
+
+        // `n` counts upserted documents too, but the driver doesn't.
+        let upserted = reply.get_array("upserted").map(Vec::as_slice).unwrap_or(&[]);
+        let num_upserted = i32::from(!upserted.is_empty());
+
+        Ok(UpdateResult {
+            acknowledged: true,
+            matched_count: reply.get_i32("n").unwrap_or(0) - num_upserted,
+            modified_count: reply.get_i32("nModified").unwrap_or(0),
+            upserted_id: upserted.first().cloned(),
+            write_exception: None,
+        })
+    }
+
+    /// Issues a `delete` command directly, for passing a collation or an
+    /// index hint. `limit` is 1 for deleting a single document, and 0 for
+    /// deleting all matching documents. Returns the number of deleted ones.
+    fn delete_command(&self, filter: Document, limit: i32, options: &DeleteOptions) -> Result<usize> {
+        let mut statement = doc!{
+            "q": filter,
+            "limit": limit,
+        };
+
+        options.extend_statement(&mut statement);
+
+        let mut command = doc!{
+            "delete": T::NAME,
+            "deletes": [statement],
+        };
+
+        options.extend_command(&mut command);
+
+        let reply = self.write_command(command)?;
+
+        int_to_usize_with_msg(reply.get_i32("n").unwrap_or(0), "# of deleted documents")
+    }
+
+    /// Fills in the missing `_id` of a document about to be inserted from
+    /// the sequence of `T` if it uses sequential IDs, or using its client-side
+    /// ID generator if it has one.
+    fn assign_id(&self, doc: &mut Document) -> Result<()> {
+        match doc.get("_id") {
+            None | Some(&Bson::Null) => {}
+            Some(_) => return Ok(()),
+        }
+
+        if let Some(settings) = T::sequence() {
+            let id = sequence::next_value(&self.inner.db, T::NAME, settings)?;
+            doc.insert("_id", id);
+        } else if let Some(id) = T::generate_id()? {
+            doc.insert("_id", id.to_bson()?);
+        }
+
+        Ok(())
+    }
+
+    /// Runs a write command and converts the failure of the command itself,
+    /// or the write errors and the write concern error in its reply, if any,
+    /// into an `Error`.
+    fn write_command(&self, command: Document) -> Result<Document> {
+        let reply = self.inner.db.command(command, CommandType::Suppressed, None)?;
+        let reply = check_ok(reply)?;
+
+        for key in &["writeErrors", "writeConcernError"] {
+            let errors = match reply.get(key) {
+                Some(&Bson::Array(ref errors)) => errors.clone(),
+                Some(error) => vec![error.clone()],
+                None => continue,
+            };
+            let message = errors
+                .iter()
+                .map(|error| match *error {
+                    Bson::Document(ref error) => format!(
+                        "{} (code {})",
+                        error.get_str("errmsg").unwrap_or("unknown error"),
+                        error.get("code").map_or_else(String::new, ToString::to_string),
+                    ),
+                    ref other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+
+            // The error is classified according to the first write error,
+            // since ordered writes stop there anyway.
+            let server_error = errors.iter().find_map(|error| match *error {
+                Bson::Document(ref error) => Some(ServerError::from_document(error)),
+                _ => None,
+            });
+
+            return Err(match server_error {
+                Some(server_error) => {
+                    Error::new(server_error.kind_or(MongoDbWriteException), message)
+                        .with_context::<ServerError>(server_error)
+                }
+                None => Error::new(MongoDbWriteException, message),
+            });
+        }
+
+        Ok(reply)
+    }
+
     /// Convenience method for deleting a single entity based on its identity
     /// (the `_id` field). Returns `true` if it was found and deleted.
     pub fn delete_entity(&self, entity: &T) -> Result<bool> where T: Debug {
         let id = entity.id().ok_or_else(
             || Error::new(MissingId, format!("No `_id` in entity of type {}", T::NAME))
         )?;
-        let id_bson = bson::to_bson(id)?;
+        let filter = doc!{ "_id": id.to_bson()? };
+        let documents = || (Some(filter.clone()), None);
 
-        self.delete_one(doc!{ "_id": id_bson }).chain(
-            || format!("error in {}::delete_entity({:#?})", T::NAME, entity)
-        )
+        self.instrumented("delete_entity", documents, deleted_one, || {
+            let message = || format!("error in {}::delete_entity({:#?})", T::NAME, entity);
+            let options = T::delete_options();
+
+            // At most one document can match an `_id`, so deleting it again
+            // is a no-op, which makes this `delete_one` safe to retry.
+            self.retrying(OperationKind::IdempotentWrite, || {
+                self.delete_one_internal(filter.clone(), &options, message)
+            })
+        })
     }
 
     /// Convenience method for deleting entities based on their identity
@@ -397,7 +1153,7 @@ impl<T: Doc> Collection<T> {
                     MissingId,
                     format!("No `_id` in entity to delete: {:#?}", entity)
                 ))?;
-                bson::to_bson(id).map_err(From::from)
+                id.to_bson()
             })
             .collect::<Result<_>>()?;
 
@@ -414,10 +1170,30 @@ impl<T: Doc> Collection<T> {
 
     /// Deletes one document. Returns `true` if one was found and deleted.
     pub fn delete_one<Q: Delete<T>>(&self, query: Q) -> Result<bool> {
-        let message = || format!("error in {}::delete_one({:#?})", T::NAME, query);
+        self.instrumented("delete_one", || (Some(query.filter()), None), deleted_one, || {
+            let message = || format!("error in {}::delete_one({:#?})", T::NAME, query);
+
+            self.delete_one_internal(query.filter(), &query.options(), message)
+        })
+    }
+
+    /// Deletes at most one document matching `filter`. Returns `true` if
+    /// one was found and deleted.
+    fn delete_one_internal<F: Copy + FnOnce() -> String>(
+        &self,
+        filter: Document,
+        options: &DeleteOptions,
+        message: F,
+    ) -> Result<bool> {
+        if !options.is_write_concern_only() {
+            return self.delete_command(filter, 1, options)
+                .map(|n| n > 0)
+                .chain(message);
+        }
+
         self.inner
-            .delete_one(query.filter(), query.options().into())
-            .chain(&message)
+            .delete_one(filter, options.write_concern.clone())
+            .chain(message)
             .and_then(|result| {
                 if let Some(error) = result.write_exception {
                     Err(Error::with_cause(message(), error))
@@ -428,43 +1204,63 @@ impl<T: Doc> Collection<T> {
     }
 
     /// Deletes many documents. Returns the number of deleted documents.
+    ///
+    /// Deleting all matching documents is idempotent, so unlike
+    /// `delete_one()`, this method is retried if the retry policy of the
+    /// collection allows retrying writes.
     pub fn delete_many<Q: Delete<T>>(&self, query: Q) -> Result<usize> {
-        let message = || format!("error in {}::delete_many({:#?})", T::NAME, query);
-        self.inner
-            .delete_many(query.filter(), query.options().into())
-            .chain(&message)
-            .and_then(|result| {
-                if let Some(error) = result.write_exception {
-                    Err(Error::with_cause(message(), error))
-                } else {
-                    int_to_usize_with_msg(result.deleted_count, "# of deleted documents")
+        let documents = || (Some(query.filter()), None);
+
+        self.instrumented("delete_many", documents, |&n| Counts::deleted(n), || {
+            let message = || format!("error in {}::delete_many({:#?})", T::NAME, query);
+            let options = query.options();
+
+            self.retrying(OperationKind::IdempotentWrite, || {
+                if !options.is_write_concern_only() {
+                    return self.delete_command(query.filter(), 0, &options).chain(&message);
                 }
+
+                self.inner
+                    .delete_many(query.filter(), options.write_concern.clone())
+                    .chain(&message)
+                    .and_then(|result| {
+                        if let Some(error) = result.write_exception {
+                            Err(Error::with_cause(message(), error))
+                        } else {
+                            int_to_usize_with_msg(result.deleted_count, "# of deleted documents")
+                        }
+                    })
             })
+        })
     }
 
     /// Deletes a single document based on the query criteria,
     /// returning it if it was found.
     pub fn find_one_and_delete<Q: Query<T>>(&self, query: Q) -> Result<Option<Q::Output>> {
-        let query_options = query.options();
-        let find_delete_options = FindOneAndDeleteOptions {
-            max_time_ms: query_options.max_time_ms,
-            projection: query_options.projection,
-            sort: query_options.sort,
-            write_concern: None, // TODO(H2CO3): do something intelligent here
-        };
+        let documents = || (Some(query.filter()), None);
 
-        self.inner
-            .find_one_and_delete(query.filter(), find_delete_options.into())
-            .chain(|| format!(
-                "error in {}::find_one_and_delete({:#?})", T::NAME, query
-            ))
-            .and_then(|opt| match opt {
-                Some(document) => {
-                    let transformed = Q::transform(document)?;
-                    from_bson(transformed).map_err(From::from)
-                }
-                None => Ok(None)
-            })
+        self.instrumented("find_one_and_delete", documents, found_and_deleted, || {
+            let query_options = query.options();
+            let find_delete_options = FindOneAndDeleteOptions {
+                max_time_ms: query_options.max_time_ms,
+                projection: query_options.projection,
+                sort: query_options.sort,
+                write_concern: None, // TODO(H2CO3): do something intelligent here
+            };
+
+            self.inner
+                .find_one_and_delete(query.filter(), find_delete_options.into())
+                .chain(|| format!(
+                    "error in {}::find_one_and_delete({:#?})", T::NAME, query
+                ))
+                .and_then(|opt| match opt {
+                    Some(document) => {
+                        // See `find_one()` about the cost of the transformer.
+                        transform_and_deserialize(query.transformer().as_ref(), document)
+                    }
+                    None => Ok(None)
+                })
+        })
     }
 
     /// Replaces a single document based on the query criteria.
@@ -475,31 +1271,35 @@ impl<T: Doc> Collection<T> {
     pub fn find_one_and_replace<Q: Query<T>>(&self, query: Q, replacement: &T) -> Result<Option<Q::Output>>
         where T: Debug
     {
-        let query_options = query.options();
-        let find_replace_options = FindOneAndUpdateOptions {
-            return_document: Some(ReturnDocument::Before),
-            max_time_ms: query_options.max_time_ms,
-            projection: query_options.projection,
-            sort: query_options.sort,
-            upsert: Some(false),
-            ..Default::default()
-        };
-        let filter = query.filter();
-        let doc = serialize_document(replacement)?;
+        let documents = || (Some(query.filter()), serialize_entity(replacement).ok());
 
-        self.inner
-            .find_one_and_replace(filter, doc, find_replace_options.into())
-            .chain(|| format!(
-                "error in {}::find_one_and_replace({:#?}, {:#?})",
-                T::NAME, query, replacement
-            ))
-            .and_then(|opt| match opt {
-                Some(document) => {
-                    let transformed = Q::transform(document)?;
-                    from_bson(transformed).map_err(From::from)
-                }
-                None => Ok(None)
-            })
+        self.instrumented("find_one_and_replace", documents, found, || {
+            let query_options = query.options();
+            let find_replace_options = FindOneAndUpdateOptions {
+                return_document: Some(ReturnDocument::Before),
+                max_time_ms: query_options.max_time_ms,
+                projection: query_options.projection,
+                sort: query_options.sort,
+                upsert: Some(false),
+                ..Default::default()
+            };
+            let filter = query.filter();
+            let doc = serialize_entity(replacement)?;
+
+            self.inner
+                .find_one_and_replace(filter, doc, find_replace_options.into())
+                .chain(|| format!(
+                    "error in {}::find_one_and_replace({:#?}, {:#?})",
+                    T::NAME, query, replacement
+                ))
+                .and_then(|opt| match opt {
+                    Some(document) => {
+                        // See `find_one()` about the cost of the transformer.
+                        transform_and_deserialize(query.transformer().as_ref(), document)
+                    }
+                    None => Ok(None)
+                })
+        })
     }
 
     /// Finds a single document based on query criteria and updates it.
@@ -508,22 +1308,78 @@ impl<T: Doc> Collection<T> {
     /// separate update and upsert functions.** The options returned by the
     /// `update` argument decide whether an update or an upsert happens.
     pub fn find_one_and_update<U: FindAndUpdate<T>>(&self, update: U) -> Result<Option<U::Output>> {
-        let filter = update.filter();
-        let change = update.update();
-        let options = update.options();
+        let documents = || (Some(update.filter()), Some(update.update()));
 
-        self.inner
-            .find_one_and_update(filter, change, options.into())
-            .chain(|| format!(
-                "error in {}::find_one_and_update({:#?})", T::NAME, update
-            ))
-            .and_then(|opt| match opt {
-                Some(document) => {
-                    let transformed = U::transform(document)?;
-                    from_bson(transformed).map_err(From::from)
-                }
-                None => Ok(None)
-            })
+        self.instrumented("find_one_and_update", documents, found, || {
+            let filter = update.filter();
+            let change = update.update();
+            let options = update.options();
+            let update_options = update.update_options();
+            let outcome = if update_options == UpdateOptions::default() {
+                self.inner
+                    .find_one_and_update(filter, change, options.into())
+                    .map_err(From::from)
+            } else {
+                self.find_and_modify_command(filter, change, options, &update_options)
+            };
+
+            outcome
+                .chain(|| format!(
+                    "error in {}::find_one_and_update({:#?})", T::NAME, update
+                ))
+                .and_then(|opt| match opt {
+                    Some(document) => {
+                        // See `find_one()` about the cost of the transformer.
+                        transform_and_deserialize(update.transformer().as_ref(), document)
+                    }
+                    None => Ok(None)
+                })
+        })
+    }
+
+    /// Issues a `findAndModify` command directly. This is needed for passing
+    /// the options in `UpdateOptions`, which the driver's `find_one_and_update`
+    /// doesn't support. Returns the document before or after the update,
+    /// like the driver does.
+    fn find_and_modify_command(
+        &self,
+        filter: Document,
+        change: Document,
+        options: FindOneAndUpdateOptions,
+        update_options: &UpdateOptions,
+    ) -> Result<Option<Document>> {
+        let mut command = doc!{
+            "findAndModify": T::NAME,
+            "query": filter,
+            "update": change,
+        };
+
+        if let Some(ReturnDocument::After) = options.return_document {
+            command.insert("new", true);
+        }
+        if let Some(projection) = options.projection {
+            command.insert("fields", projection);
+        }
+        if let Some(sort) = options.sort {
+            command.insert("sort", sort);
+        }
+        if let Some(upsert) = options.upsert {
+            command.insert("upsert", upsert);
+        }
+        if let Some(max_time_ms) = options.max_time_ms {
+            command.insert("maxTimeMS", max_time_ms);
+        }
+        if let Some(ref write_concern) = options.write_concern {
+            command.insert("writeConcern", write_concern.to_bson());
+        }
+
+        update_options.extend_statement(&mut command);
+        update_options.extend_command(&mut command);
+
+        match self.write_command(command)?.remove("value") {
+            Some(Bson::Document(document)) => Ok(Some(document)),
+            _ => Ok(None),
+        }
     }
 }
 
@@ -536,13 +1392,38 @@ impl<T: Doc> Debug for Collection<T> {
 #[doc(hidden)]
 impl<T: Doc> From<mongodb::Collection> for Collection<T> {
     fn from(collection: mongodb::Collection) -> Self {
+        let read_options = T::read_options();
+        let inner = if read_options.is_empty() {
+            collection
+        } else {
+            reconfigured::<T>(&collection, read_options)
+        };
+
         Collection {
-            inner: collection,
+            inner,
+            retry: RetryPolicy::default(),
+            instrument: None,
             _marker: PhantomData,
         }
     }
 }
 
+/// Creates a handle to the same collection as `inner`, with the read
+/// preference and read concern that are set in `read_options`. All other
+/// settings, including the write concern, are retained.
+fn reconfigured<T: Doc>(inner: &mongodb::Collection, read_options: ReadOptions) -> mongodb::Collection {
+    let ReadOptions { read_preference, read_concern } = read_options;
+    let options = CollectionOptions {
+        selection_criteria: read_preference
+            .map(SelectionCriteria::ReadPreference)
+            .or_else(|| inner.selection_criteria().cloned()),
+        read_concern: read_concern.or_else(|| inner.read_concern().cloned()),
+        write_concern: inner.write_concern().cloned(),
+    };
+
+    inner.db.collection_with_options(T::NAME, options)
+}
+
 /// The outcome of a successful `update_one()` operation.
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UpdateOneResult {
@@ -553,6 +1434,11 @@ pub struct UpdateOneResult {
 }
 
 impl UpdateOneResult {
+    /// The number of documents affected, for instrumentation.
+    fn counts(&self) -> Counts {
+        Counts::updated(usize::from(self.matched), usize::from(self.modified))
+    }
+
     /// Converts a MongoDB `UpdateResult` to an Avocado `UpdateOneResult`.
     fn from_raw(result: UpdateResult) -> Result<Self> {
         if let Some(error) = result.write_exception {
@@ -577,6 +1463,16 @@ pub struct UpsertOneResult<Id> {
     pub upserted_id: Option<Id>,
 }
 
+impl<Id> UpsertOneResult<Id> {
+    /// The number of documents affected, for instrumentation.
+    fn counts(&self) -> Counts {
+        Counts {
+            inserted: Some(usize::from(self.upserted_id.is_some())),
+            ..Counts::updated(usize::from(self.matched), usize::from(self.modified))
+        }
+    }
+}
+
 impl<Id: for<'a> Deserialize<'a>> UpsertOneResult<Id> {
     /// Converts a MongoDB `UpdateResult` to an Avocado `UpsertOneResult`.
     fn from_raw(result: UpdateResult) -> Result<Self> {
@@ -611,14 +1507,285 @@ pub struct UpdateManyResult {
     pub num_modified: usize,
 }
 
+impl UpdateManyResult {
+    /// The number of documents affected, for instrumentation.
+    fn counts(&self) -> Counts {
+        Counts::updated(self.num_matched, self.num_modified)
+    }
+}
+
 /// An alias for a nicer-looking API.
 pub type UpsertManyResult = UpdateManyResult;
 
+/// Returns the reply to a raw command if it says `ok: 1`, or an error
+/// describing the failure, classified by the server error code, otherwise.
+fn check_ok(reply: Document) -> Result<Document> {
+    if reply.get("ok").and_then(Bson::try_as_bool) == Some(true) {
+        return Ok(reply);
+    }
+
+    let server_error = ServerError::from_document(&reply);
+    let message = format!("command failed: {} (code {})", server_error.message, server_error.code);
+
+    Err(Error::new(server_error.kind_or(MongoDbError), message).with_context::<ServerError>(server_error))
+}
+
+/// The filter and the replacement document of the `{...}_entity` methods,
+/// for reporting them to the instrument.
+fn entity_documents<T: Doc>(entity: &T) -> (Option<Document>, Option<Document>) {
+    let mut document = match serialize_entity(entity) {
+        Ok(document) => document,
+        Err(_) => return (None, None),
+    };
+    let filter = document.remove("_id").map(|id| doc!{ "_id": id });
+
+    (filter, Some(document))
+}
+
+/// Copies of the documents sent by an operation, for reporting them to the
+/// instrument. `None` if there's no instrument, so nothing is copied.
+#[derive(Debug)]
+struct SentDocuments(Option<RefCell<Vec<Document>>>);
+
+impl SentDocuments {
+    /// Keeps a copy of `document` if there's an instrument.
+    fn record(&self, document: &Document) {
+        if let Some(ref documents) = self.0 {
+            documents.borrow_mut().push(document.clone());
+        }
+    }
+
+    /// Returns the documents recorded so far, for the instrument.
+    fn take(&self) -> Documents {
+        Documents::others(self.0.as_ref().map_or_else(Vec::new, |documents| {
+            documents.replace(Vec::new())
+        }))
+    }
+}
+
+/// Instrumentation counts for operations that don't know any counts.
+fn no_counts<R>(_: &R) -> Counts {
+    Counts::default()
+}
+
+/// Instrumentation counts for operations returning an optional document.
+fn found<V>(document: &Option<V>) -> Counts {
+    Counts::matched(usize::from(document.is_some()))
+}
+
+/// Instrumentation counts for operations deleting an optional document.
+fn found_and_deleted<V>(document: &Option<V>) -> Counts {
+    Counts::deleted(usize::from(document.is_some()))
+}
+
+/// Instrumentation counts for `delete_one()`.
+fn deleted_one(deleted: &bool) -> Counts {
+    Counts::deleted(usize::from(*deleted))
+}
+
+/// The outcome of inserting many documents, before deciding whether it's
+/// an error.
+struct RawInsert<T: Doc> {
+    /// The name of the `Collection` method performing the insertion.
+    operation: &'static str,
+    /// The number of documents given.
+    n_docs: usize,
+    /// The IDs of the inserted documents, keyed by their original index.
+    ids: BTreeMap<u64, StdResult<Uid<T>, Bson>>,
+    /// The documents the server refused to insert.
+    failures: Vec<InsertFailure>,
+}
+
+impl<T: Doc + 'static> RawInsert<T> where T::Id: Clone + Debug {
+    /// The error message describing the failed operation.
+    fn message(&self) -> String {
+        format!("error in {}::{}()", T::NAME, self.operation)
+    }
+
+    /// Adds the IDs of the documents inserted so far, and the failures, to
+    /// an error which interrupted the insertion, unless nothing has been
+    /// sent to the server yet.
+    fn attach_to(&mut self, error: Error) -> Error {
+        if self.ids.is_empty() && self.failures.is_empty() {
+            error
+        } else {
+            let failures = mem::replace(&mut self.failures, Vec::new());
+            let ids = mem::replace(&mut self.ids, BTreeMap::new());
+
+            error
+                .with_context::<InsertFailure>(failures)
+                .with_context::<InsertManyErrorContext<T>>(ids)
+        }
+    }
+
+    /// Succeeds only if all documents were inserted and all of their IDs
+    /// could be deserialized.
+    fn into_ids(self) -> Result<BTreeMap<u64, Uid<T>>> {
+        let message = self.message();
+        let failures = self.failures;
+        let ids = self.ids;
+
+        if let Some(first) = failures.first().cloned() {
+            let msg = format!("{}: {} of {} documents couldn't be inserted, the first one because: {}",
+                              message, failures.len(), self.n_docs, first.error.message);
+
+            return Err(Error::new(first.error.kind_or(MongoDbBulkWriteException), msg)
+                .with_context::<ServerError>(first.error)
+                .with_context::<InsertFailure>(failures)
+                .with_context::<InsertManyErrorContext<T>>(ids));
+        }
+
+        decode_ids(ids, &message)
+    }
+
+    /// Succeeds as long as the IDs of the inserted documents could be
+    /// deserialized, regardless of the documents the server refused.
+    fn into_partial(self) -> Result<PartialInsert<T>> {
+        let message = self.message();
+        let inserted = decode_ids(self.ids, &message)?;
+
+        Ok(PartialInsert { inserted, failed: self.failures })
+    }
+}
+
+/// The maximal number of documents in a single `insert` command, i.e. the
+/// `maxWriteBatchSize` of the server.
+const MAX_INSERT_BATCH_COUNT: usize = 100_000;
+
+/// The maximal total size of the documents in a single `insert` command,
+/// i.e. the `maxBsonObjectSize` of the server. The command itself may be
+/// larger by 16 kB, which leaves room for the other fields of the command.
+const MAX_INSERT_BATCH_SIZE: usize = MAX_DOCUMENT_SIZE;
+
+/// The size of a document in the `documents` array of an `insert` command
+/// in addition to its own size: the type tag, and its index (at most 5
+/// decimal digits) as a NUL-terminated key.
+const INSERT_ELEMENT_OVERHEAD: usize = 1 + 5 + 1;
+
+/// The documents of a single `insert` command.
+#[derive(Debug)]
+struct InsertBatch<E> {
+    /// The documents to insert.
+    docs: Vec<Document>,
+    /// The `_id` of each document.
+    ids: Vec<Bson>,
+    /// The entity (or any other payload) belonging to each document.
+    payloads: Vec<E>,
+    /// The total size of the documents within the command.
+    size: usize,
+    /// Whether the insertion of the batch stops at the first failure.
+    ordered: bool,
+}
+
+impl<E> InsertBatch<E> {
+    /// Creates an empty batch.
+    fn new(ordered: bool) -> Self {
+        InsertBatch {
+            docs: Vec::new(),
+            ids: Vec::new(),
+            payloads: Vec::new(),
+            size: 0,
+            ordered,
+        }
+    }
+
+    /// Returns `true` if a document of the given size doesn't fit in this
+    /// batch anymore. A single document always fits in an empty batch,
+    /// since serializing it fails if it's larger than a whole batch.
+    fn is_full(&self, size: usize) -> bool {
+        !self.docs.is_empty() && (
+            self.docs.len() >= MAX_INSERT_BATCH_COUNT
+            ||
+            self.size + size > MAX_INSERT_BATCH_SIZE
+        )
+    }
+
+    /// Adds a document to the batch.
+    fn push(&mut self, doc: Document, id: Bson, payload: E, size: usize) {
+        self.docs.push(doc);
+        self.ids.push(id);
+        self.payloads.push(payload);
+        self.size += size;
+    }
+}
+
+/// Checks that all inserted IDs could be deserialized as a `Uid<T>`.
+fn decode_ids<T>(ids: BTreeMap<u64, StdResult<Uid<T>, Bson>>, message: &str) -> Result<BTreeMap<u64, Uid<T>>>
+    where T: Doc + 'static,
+          T::Id: Clone + Debug,
+{
+    let ids_res: StdResult<BTreeMap<_, _>, _> = ids
+        .clone()
+        .into_iter()
+        .map(|(i, res)| res.map(|id| (i, id)))
+        .collect();
+
+    ids_res.map_err(|_| Error::new(
+        BsonDecoding,
+        format!("{}: can't deserialize some IDs", message)
+    ).with_context::<InsertManyErrorContext<T>>(
+        ids
+    ))
+}
+
+/// A document which the server refused to insert, e.g. because it violated
+/// a unique index or failed schema validation. This type is also the key of
+/// the context info of `Collection::insert_many()` errors listing all such
+/// failures: `error.context::<InsertFailure>()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertFailure {
+    /// The index of the document among those passed to the insert method.
+    pub index: u64,
+    /// The reason of the failure, as reported by the server.
+    pub error: ServerError,
+    /// The `Debug` representation of the entity, if the insert method
+    /// requires `T: Debug`, e.g. `Collection::insert_many_unordered()`.
+    pub entity: Option<String>,
+}
+
+impl Key for InsertFailure {
+    type Value = Vec<InsertFailure>;
+}
+
+/// The result of `Collection::insert_many_unordered()`: the documents that
+/// were inserted, and those which were not.
+pub struct PartialInsert<T: Doc> {
+    /// The IDs of the inserted documents, keyed by their original index.
+    pub inserted: BTreeMap<u64, Uid<T>>,
+    /// The documents the server refused to insert, ordered by their index.
+    pub failed: Vec<InsertFailure>,
+}
+
+impl<T: Doc> PartialInsert<T> {
+    /// Returns `true` if every document was inserted.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl<T: Doc> Clone for PartialInsert<T> where T::Id: Clone {
+    fn clone(&self) -> Self {
+        PartialInsert {
+            inserted: self.inserted.clone(),
+            failed: self.failed.clone(),
+        }
+    }
+}
+
+impl<T: Doc> Debug for PartialInsert<T> where T::Id: Debug {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("PartialInsert")
+            .field("inserted", &self.inserted)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
 /// This additional context info may be associated with an error when
 /// `Collection::insert_many()` fails to insert some of the documents or some
 /// of the inserted IDs fail to deserialize. It is not, however, returned when
 /// the insertion isn't even attempted due to another error, e.g. when
-/// the documents to be inserted fail to serialize as BSON upfront.
+/// the documents of the first batch fail to serialize as BSON upfront.
 pub struct InsertManyErrorContext<T>(PhantomData<T>);
 
 // Manual impls of common traits follow, for more relaxed trait bounds.
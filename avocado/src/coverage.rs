@@ -0,0 +1,771 @@
+//! Checking, without a server, whether the declared indexes can serve a query.
+//!
+//! The functions in this module inspect the filter and the sort order of a
+//! query, count or deletion, and decide whether one of the indexes returned
+//! by `T::indexes()` (or the implicit index on `_id`) could be used to
+//! execute it. They follow the rules of the MongoDB query planner closely
+//! enough to catch accidental collection scans in unit tests:
+//!
+//! * an index can only be used if the filter constrains a prefix of its keys;
+//! * equality conditions come first, then the sort fields, then range
+//!   conditions (the "equality, sort, range" rule); a range condition on a
+//!   key preceding the sort keys causes an in-memory sort;
+//! * each branch of an `$or` must be served by an index of its own, unless
+//!   the conditions outside the `$or` can already use an index;
+//! * `$text` requires a text index, which can't serve anything else;
+//! * a partial index is only used if the filter contains every condition
+//!   of its `partialFilterExpression` verbatim (at the top level or in an
+//!   `$and`), since the planner needs the filter to imply the expression;
+//! * a sparse index is only used if the filter has a condition on one of
+//!   its keys which doesn't match documents missing that key.
+//!
+//! The planner may still choose a different plan at runtime, of course;
+//! use `Collection::explain_find()` to see what it actually does.
+//! ```
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! # use avocado::coverage;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[index(name = "by_owner_date", keys(owner = "ascending", date = "descending"))]
+//! struct Invoice {
+//!     _id: Uid<Invoice>,
+//!     owner: String,
+//!     date: i64,
+//!     total: f64,
+//! }
+//!
+//! # fn main() {
+//! let by_owner = doc!{ "owner": "alice", "date": { "$gte": 1_500_000_000_i64 } };
+//! let coverage = coverage::check_count::<Invoice, _>(&by_owner);
+//! assert!(coverage.is_covered());
+//! assert!(coverage.uses_index("by_owner_date"));
+//!
+//! let by_total = doc!{ "total": { "$gt": 100.0 } };
+//! assert!(coverage::check_count::<Invoice, _>(&by_total).is_collection_scan());
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use bson::{ Bson, Document };
+use mongodb::options::IndexModel;
+use crate::{
+    doc::Doc,
+    ops::{ Query, Count, Delete },
+    bsn::BsonExt,
+};
+
+/// Describes whether and how a query can be served by the declared indexes.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    /// The indexes used: one for a simple filter, one per branch for `$or`.
+    indexes: Vec<IndexUse>,
+    /// Whether the requested sort order is produced by the index.
+    sort_covered: bool,
+}
+
+impl Coverage {
+    /// Returns `true` if no index can serve the filter, so the whole
+    /// collection would need to be scanned.
+    pub fn is_collection_scan(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    /// Returns `true` if the sort order can't be obtained from an index,
+    /// so the matching documents would need to be sorted in memory.
+    pub fn needs_in_memory_sort(&self) -> bool {
+        !self.sort_covered
+    }
+
+    /// Returns `true` if both the filter and the sort order are served
+    /// by indexes.
+    pub fn is_covered(&self) -> bool {
+        !self.is_collection_scan() && !self.needs_in_memory_sort()
+    }
+
+    /// Returns the indexes that would be used.
+    pub fn indexes(&self) -> &[IndexUse] {
+        &self.indexes
+    }
+
+    /// Returns `true` if the index with the given name would be used.
+    pub fn uses_index(&self, name: &str) -> bool {
+        self.indexes.iter().any(|index| index.name == name)
+    }
+}
+
+/// An index that can serve (a part of) a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexUse {
+    /// The name of the index, either explicitly specified, or generated
+    /// from the keys the same way MongoDB does, e.g. `"owner_1_date_-1"`.
+    pub name: String,
+    /// The keys of the index.
+    pub keys: Document,
+    /// The number of keys of the index that are constrained by the filter.
+    /// Zero means that the index is only used for sorting, and it would
+    /// be scanned entirely.
+    pub bounded_keys: usize,
+}
+
+/// Checks whether the filter and the sort order of a query can be served
+/// by the indexes of `T`.
+pub fn check_query<T: Doc, Q: Query<T>>(query: &Q) -> Coverage {
+    check_filter(&T::indexes(), &query.filter(), query.options().sort.as_ref())
+}
+
+/// Checks whether the filter of a count can be served by the indexes of `T`.
+pub fn check_count<T: Doc, Q: Count<T>>(query: &Q) -> Coverage {
+    check_filter(&T::indexes(), &query.filter(), None)
+}
+
+/// Checks whether the filter of a deletion can be served by the indexes of `T`.
+pub fn check_delete<T: Doc, Q: Delete<T>>(query: &Q) -> Coverage {
+    check_filter(&T::indexes(), &query.filter(), None)
+}
+
+/// Checks whether a filter and an optional sort order can be served by the
+/// given indexes, or by the implicit index on `_id`.
+pub fn check_filter(indexes: &[IndexModel], filter: &Document, sort: Option<&Document>) -> Coverage {
+    let id_index = IndexModel {
+        keys: doc!{ "_id": 1 },
+        options: Some(doc!{ "name": "_id_" }),
+    };
+    let candidates: Vec<_> = indexes
+        .iter()
+        .chain(Some(&id_index))
+        .map(Candidate::new)
+        .collect();
+    let sort_keys = sort.map_or_else(Vec::new, parse_sort);
+    let conditions = Conditions::parse(filter);
+
+    match plan(&candidates, &conditions, &sort_keys) {
+        Some((used, sort_covered)) => Coverage { indexes: used, sort_covered },
+        None => Coverage { indexes: Vec::new(), sort_covered: sort_keys.is_empty() },
+    }
+}
+
+/// Returns the name MongoDB would generate for an index with the given keys.
+pub fn default_index_name(keys: &Document) -> String {
+    keys.iter()
+        .map(|(field, value)| match *value {
+            Bson::String(ref kind) => format!("{}_{}", field, kind),
+            ref number => format!("{}_{}", field, key_number(number).unwrap_or(1.0)),
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Finds the indexes serving the conditions, and whether they produce the
+/// sort order. Returns `None` if the conditions need a collection scan.
+fn plan(
+    candidates: &[Candidate],
+    conditions: &Conditions,
+    sort: &[(String, SortKey)],
+) -> Option<(Vec<IndexUse>, bool)> {
+    let best = candidates
+        .iter()
+        .filter_map(|candidate| candidate.serve(conditions, sort))
+        .max_by_key(|&(ref index, sort_covered)| {
+            (index.bounded_keys > 0, sort_covered, index.bounded_keys)
+        });
+
+    // If the conditions outside of `$or` can use an index, the `$or` is
+    // evaluated on the fetched documents; otherwise, every branch of it
+    // needs an index of its own, and the results must be sorted in memory.
+    if let Some((index, sort_covered)) = best.clone() {
+        if index.bounded_keys > 0 {
+            return Some((vec![index], sort_covered));
+        }
+    }
+
+    for branches in &conditions.or {
+        let branch_plans = branches
+            .iter()
+            .map(|branch| {
+                let merged = branch.merged_with(conditions);
+                plan(candidates, &merged, &[]).filter(|&(ref used, _)| {
+                    used.iter().all(|index| index.bounded_keys > 0)
+                })
+            })
+            .collect::<Option<Vec<_>>>();
+
+        if let Some(plans) = branch_plans {
+            let indexes = plans.into_iter().flat_map(|(used, _)| used).collect();
+            return Some((indexes, sort.is_empty()));
+        }
+    }
+
+    best.map(|(index, sort_covered)| (vec![index], sort_covered))
+}
+
+/// How strongly a condition constrains a field, from the point of view of
+/// an index. The variants are ordered from the weakest to the strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Predicate {
+    /// One or more ranges of values, e.g. `$gt`, `$in`, `$regex`.
+    Range,
+    /// A geospatial condition, which needs a `2d` or `2dsphere` key.
+    Geo,
+    /// A single value.
+    Equality,
+}
+
+impl Predicate {
+    /// Classifies the condition on a field, if it can use an index at all.
+    fn of(value: &Bson) -> Option<Self> {
+        match *value {
+            Bson::Document(ref doc) if doc.keys().any(|key| key.starts_with('$')) => {
+                doc.iter().filter_map(|(op, arg)| Self::of_operator(op, arg)).max()
+            }
+            Bson::RegExp(..) => Some(Predicate::Range),
+            _ => Some(Predicate::Equality),
+        }
+    }
+
+    /// Classifies a query operator, if it can use an index at all.
+    fn of_operator(op: &str, arg: &Bson) -> Option<Self> {
+        match op {
+            "$eq" => Self::of(arg).map(|_| Predicate::Equality),
+            "$in" => match *arg {
+                Bson::Array(ref values) if values.len() == 1 => Self::of(&values[0]),
+                _ => Some(Predicate::Range),
+            },
+            "$gt" | "$gte" | "$lt" | "$lte" | "$ne" | "$nin" | "$not" | "$regex"
+                | "$exists" | "$type" | "$all" | "$elemMatch" | "$mod" => Some(Predicate::Range),
+            "$near" | "$nearSphere" | "$geoWithin" | "$geoIntersects" => Some(Predicate::Geo),
+            _ => None,
+        }
+    }
+}
+
+/// The indexable conditions of a filter.
+#[derive(Debug, Clone, Default)]
+struct Conditions {
+    /// Conditions on individual fields, by field path.
+    fields: BTreeMap<String, Predicate>,
+    /// Whether the filter contains a `$text` search.
+    text: bool,
+    /// The branches of each `$or` in the filter.
+    or: Vec<Vec<Conditions>>,
+    /// The conditions on fields, as they appear in the filter (or in its
+    /// `$and` clauses), for matching against partial filter expressions.
+    clauses: Vec<(String, Bson)>,
+}
+
+impl Conditions {
+    /// Collects the conditions of a filter document.
+    fn parse(filter: &Document) -> Self {
+        let mut conditions = Conditions::default();
+        conditions.add(filter);
+        conditions
+    }
+
+    /// Adds the conditions of a filter document to this one.
+    fn add(&mut self, filter: &Document) {
+        for (key, value) in filter {
+            match key.as_str() {
+                "$and" => for clause in subdocuments(value) {
+                    self.add(clause);
+                },
+                "$or" => {
+                    self.or.push(subdocuments(value).map(Conditions::parse).collect());
+                }
+                "$text" => self.text = true,
+                // `$nor`, `$where`, `$expr` etc. can't use indexes.
+                _ if key.starts_with('$') => {}
+                _ => {
+                    self.clauses.push((key.clone(), value.clone()));
+
+                    if let Some(predicate) = Predicate::of(value) {
+                        self.constrain(key, predicate);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records a condition on a field, keeping the stronger one if
+    /// there's already a condition on it.
+    fn constrain(&mut self, field: &str, predicate: Predicate) {
+        let entry = self.fields.entry(field.to_owned()).or_insert(predicate);
+        *entry = (*entry).max(predicate);
+    }
+
+    /// Returns the conditions of an `$or` branch, combined with those of
+    /// the enclosing filter (except for its `$or`s).
+    fn merged_with(&self, outer: &Conditions) -> Self {
+        let mut merged = self.clone();
+
+        for (field, &predicate) in &outer.fields {
+            merged.constrain(field, predicate);
+        }
+
+        merged.text |= outer.text;
+        merged.clauses.extend(outer.clauses.iter().cloned());
+        merged
+    }
+
+    /// Returns the condition on a field, if any.
+    fn get(&self, field: &str) -> Option<Predicate> {
+        self.fields.get(field).cloned()
+    }
+
+    /// Returns `true` if the filter contains every condition of a partial
+    /// filter expression verbatim. This is stricter than what the planner
+    /// does, e.g. `{ a: { $gt: 5 } }` doesn't count as implying
+    /// `{ a: { $gt: 0 } }` here.
+    fn implies(&self, expression: &Document) -> bool {
+        Conditions::parse(expression).clauses.iter().all(|clause| self.clauses.contains(clause))
+    }
+
+    /// Returns `true` if the filter only matches documents which have
+    /// the given field, i.e. which are in a sparse index on the field.
+    fn requires_field(&self, field: &str) -> bool {
+        self.clauses.iter().any(|&(ref key, ref value)| key == field && excludes_missing(value))
+    }
+}
+
+/// Returns `true` if a condition never matches a missing field.
+fn excludes_missing(condition: &Bson) -> bool {
+    match *condition {
+        Bson::Null => false,
+        Bson::Document(ref doc) if doc.keys().any(|key| key.starts_with('$')) => {
+            doc.iter().any(|(op, arg)| match op.as_str() {
+                "$eq" => excludes_missing(arg),
+                "$in" => match *arg {
+                    Bson::Array(ref values) => values.iter().all(excludes_missing),
+                    _ => false,
+                },
+                "$exists" => arg.try_as_bool() == Some(true),
+                "$gt" | "$gte" | "$lt" | "$lte" | "$regex" | "$type" | "$all" | "$size"
+                    | "$elemMatch" | "$mod" | "$near" | "$nearSphere" | "$geoWithin"
+                    | "$geoIntersects" => true,
+                // `$ne`, `$nin`, `$not` etc. match missing fields.
+                _ => false,
+            })
+        }
+        _ => true,
+    }
+}
+
+/// Returns the documents in an array, e.g. the clauses of `$and` and `$or`.
+fn subdocuments<'a>(value: &'a Bson) -> impl Iterator<Item=&'a Document> + 'a {
+    let array: &[Bson] = match *value {
+        Bson::Array(ref array) => array,
+        _ => &[],
+    };
+
+    array.iter().filter_map(|item| match *item {
+        Bson::Document(ref doc) => Some(doc),
+        _ => None,
+    })
+}
+
+/// The kind of a key in an index specification or a sort specification.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    /// An ordered key; the sign of the number is the direction.
+    Ordered(f64),
+    /// Any other kind: hashed, text, geospatial, or `$meta` (in a sort).
+    Special(KeyKind),
+}
+
+/// The kind of a non-ordered index key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyKind {
+    /// A `hashed` key, which only supports equality.
+    Hashed,
+    /// A `text` key, only usable for `$text`.
+    Text,
+    /// A geospatial key, e.g. `2dsphere`.
+    Geo,
+    /// Anything else, e.g. `{ $meta: "textScore" }` in a sort.
+    Other,
+}
+
+impl SortKey {
+    /// Interprets the value of an index or sort key.
+    fn of(value: &Bson) -> Self {
+        match *value {
+            Bson::String(ref kind) => SortKey::Special(match kind.as_str() {
+                "hashed" => KeyKind::Hashed,
+                "text" => KeyKind::Text,
+                "2d" | "2dsphere" | "geoHaystack" => KeyKind::Geo,
+                _ => KeyKind::Other,
+            }),
+            ref number => key_number(number).map_or(
+                SortKey::Special(KeyKind::Other),
+                SortKey::Ordered,
+            ),
+        }
+    }
+
+    /// Returns `true` if this is an ascending or descending key.
+    fn is_ordered(self) -> bool {
+        match self {
+            SortKey::Ordered(_) => true,
+            SortKey::Special(_) => false,
+        }
+    }
+
+    /// Returns `true` if an equality condition on this key can use the index.
+    fn supports_equality(self) -> bool {
+        match self {
+            SortKey::Ordered(_) | SortKey::Special(KeyKind::Hashed) => true,
+            SortKey::Special(_) => false,
+        }
+    }
+}
+
+/// Returns the numeric value of an index or sort key, if it's a number.
+#[allow(clippy::cast_precision_loss)]
+fn key_number(value: &Bson) -> Option<f64> {
+    match *value {
+        Bson::I32(n) => Some(f64::from(n)),
+        Bson::I64(n) => Some(n as f64),
+        Bson::FloatingPoint(x) => Some(x),
+        _ => None,
+    }
+}
+
+/// Parses a sort specification into its fields and directions.
+fn parse_sort(sort: &Document) -> Vec<(String, SortKey)> {
+    sort.iter().map(|(field, value)| (field.clone(), SortKey::of(value))).collect()
+}
+
+/// A declared index, prepared for matching against queries.
+#[derive(Debug, Clone)]
+struct Candidate {
+    /// The name of the index.
+    name: String,
+    /// The original key specification.
+    spec: Document,
+    /// The keys of the index, in order.
+    keys: Vec<(String, SortKey)>,
+    /// Whether the index only contains documents having one of its keys.
+    sparse: bool,
+    /// The partial filter expression, if the index is a partial index.
+    partial: Option<Document>,
+}
+
+impl Candidate {
+    /// Prepares an index for matching.
+    fn new(model: &IndexModel) -> Self {
+        let options = model.options.clone().unwrap_or_default();
+
+        Candidate {
+            name: options.get_str("name").ok().map_or_else(
+                || default_index_name(&model.keys),
+                String::from,
+            ),
+            spec: model.keys.clone(),
+            keys: parse_sort(&model.keys),
+            sparse: options.get("sparse").and_then(BsonExt::try_as_bool).unwrap_or(false),
+            partial: options.get_document("partialFilterExpression").ok().cloned(),
+        }
+    }
+
+    /// Returns `true` if the index contains every document the filter may
+    /// match, so that using it doesn't lead to missing results.
+    fn is_complete_for(&self, conditions: &Conditions) -> bool {
+        let partial_ok = self.partial.as_ref().map_or(true, |expression| {
+            conditions.implies(expression)
+        });
+        let sparse_ok = !self.sparse || self.keys.iter().any(|&(ref field, _)| {
+            conditions.requires_field(field)
+        });
+
+        partial_ok && sparse_ok
+    }
+
+    /// Returns how this index could serve the conditions and the sort order,
+    /// if at all: the index usage and whether the sort order is covered.
+    fn serve(&self, conditions: &Conditions, sort: &[(String, SortKey)]) -> Option<(IndexUse, bool)> {
+        if !self.is_complete_for(conditions) {
+            return None;
+        }
+
+        let text_key = self.keys.iter().position(|&(_, key)| key == SortKey::Special(KeyKind::Text));
+
+        // Text indexes only serve `$text` queries, and `$text` queries
+        // can only be served by text indexes, with equality conditions
+        // on any keys preceding the text keys.
+        match (text_key, conditions.text) {
+            (Some(position), true) => {
+                let prefix_is_equality = self.keys[..position].iter().all(|&(ref field, _)| {
+                    conditions.get(field) == Some(Predicate::Equality)
+                });
+
+                if prefix_is_equality {
+                    Some((self.usage(position + 1), sort.is_empty()))
+                } else {
+                    None
+                }
+            }
+            (None, false) => match self.bounded_keys(conditions, sort) {
+                (0, false) => None,
+                (n, sort_covered) => Some((self.usage(n), sort_covered)),
+            },
+            _ => None,
+        }
+    }
+
+    /// Applies the "equality, sort, range" rule. Returns the number of keys
+    /// that have bounds derived from the conditions, and whether the sort
+    /// order can be obtained by walking the index (forwards or backwards).
+    fn bounded_keys(&self, conditions: &Conditions, sort: &[(String, SortKey)]) -> (usize, bool) {
+        let is_equality = |index: usize| {
+            let (ref field, key) = self.keys[index];
+            key.supports_equality() && conditions.get(field) == Some(Predicate::Equality)
+        };
+
+        // Equality prefix
+        let mut position = 0;
+
+        while position < self.keys.len() && is_equality(position) {
+            position += 1;
+        }
+
+        let mut bounded = position;
+
+        // Sort keys; those pinned by equality conditions don't matter.
+        let free_sort: Vec<_> = sort
+            .iter()
+            .filter(|&&(ref field, _)| conditions.get(field) != Some(Predicate::Equality))
+            .collect();
+        let mut sort_position = position;
+        let mut sort_bounded = bounded;
+        let mut direction = None;
+        let mut sort_covered = true;
+
+        for &&(ref field, sort_key) in &free_sort {
+            while sort_position < self.keys.len()
+                && self.keys[sort_position].0 != *field
+                && is_equality(sort_position)
+            {
+                sort_position += 1;
+                sort_bounded += 1;
+            }
+
+            let index_key = self.keys.get(sort_position).map(|&(ref name, key)| (name, key));
+            let same_direction = match (index_key, sort_key) {
+                (Some((name, SortKey::Ordered(a))), SortKey::Ordered(b)) if name == field => {
+                    Some((a > 0.0) == (b > 0.0))
+                }
+                _ => None,
+            };
+
+            match same_direction {
+                Some(same) if direction.map_or(true, |d| d == same) => {
+                    direction = Some(same);
+
+                    if conditions.get(field).is_some() {
+                        sort_bounded += 1;
+                    }
+
+                    sort_position += 1;
+                }
+                _ => {
+                    sort_covered = false;
+                    break;
+                }
+            }
+        }
+
+        if sort_covered && !free_sort.is_empty() {
+            position = sort_position;
+            bounded = sort_bounded;
+        }
+
+        // A range or geospatial condition directly following the equality
+        // prefix (or the sort keys) can still use the index.
+        if let Some(&(ref field, key)) = self.keys.get(position) {
+            let usable = match conditions.get(field) {
+                Some(Predicate::Equality) => key.supports_equality(),
+                Some(Predicate::Range) => key.is_ordered(),
+                Some(Predicate::Geo) => key == SortKey::Special(KeyKind::Geo),
+                None => false,
+            };
+
+            if usable {
+                bounded += 1;
+            }
+        }
+
+        (bounded, sort_covered && (bounded > 0 || !free_sort.is_empty()))
+    }
+
+    /// Describes the use of this index with the given number of bounded keys.
+    fn usage(&self, bounded_keys: usize) -> IndexUse {
+        IndexUse {
+            name: self.name.clone(),
+            keys: self.spec.clone(),
+            bounded_keys,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(keys: Document, name: &str) -> IndexModel {
+        IndexModel {
+            keys,
+            options: Some(doc!{ "name": name }),
+        }
+    }
+
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            index(doc!{ "owner": 1, "date": -1 }, "owner_date"),
+            index(doc!{ "status": "hashed" }, "status"),
+            index(doc!{ "title": "text", "body": "text" }, "fulltext"),
+            index(doc!{ "location": "2dsphere" }, "location"),
+        ]
+    }
+
+    #[test]
+    fn prefix_match() {
+        let equality = check_filter(&indexes(), &doc!{ "owner": "alice" }, None);
+        assert!(equality.is_covered());
+        assert!(equality.uses_index("owner_date"));
+        assert_eq!(equality.indexes()[0].bounded_keys, 1);
+
+        let both = check_filter(&indexes(), &doc!{
+            "owner": "alice",
+            "date": { "$gt": 10 },
+        }, None);
+        assert_eq!(both.indexes()[0].bounded_keys, 2);
+
+        let no_prefix = check_filter(&indexes(), &doc!{ "date": { "$gt": 10 } }, None);
+        assert!(no_prefix.is_collection_scan());
+
+        let by_id = check_filter(&indexes(), &doc!{ "_id": { "$in": [1, 2, 3] } }, None);
+        assert!(by_id.uses_index("_id_"));
+
+        let hashed = check_filter(&indexes(), &doc!{ "status": { "$in": ["open"] } }, None);
+        assert!(hashed.uses_index("status"));
+        let hashed_range = check_filter(&indexes(), &doc!{ "status": { "$gt": "a" } }, None);
+        assert!(hashed_range.is_collection_scan());
+
+        let unindexable = check_filter(&indexes(), &doc!{ "$where": "true" }, None);
+        assert!(unindexable.is_collection_scan());
+    }
+
+    #[test]
+    fn equality_sort_range() {
+        let indexes = vec![index(doc!{ "a": 1, "b": 1, "c": 1 }, "abc")];
+
+        // E S R
+        let esr = check_filter(&indexes, &doc!{ "a": 1, "c": { "$gt": 0 } }, Some(&doc!{ "b": -1 }));
+        assert!(esr.is_covered());
+        assert_eq!(esr.indexes()[0].bounded_keys, 2);
+
+        // E R S: the range precedes the sort key, so it's an in-memory sort
+        let ers = check_filter(&indexes, &doc!{ "a": 1, "b": { "$gt": 0 } }, Some(&doc!{ "c": 1 }));
+        assert!(!ers.is_collection_scan());
+        assert!(ers.needs_in_memory_sort());
+
+        // Sorting on equality fields is free, and mixed directions don't work
+        let pinned = check_filter(&indexes, &doc!{ "a": 1, "b": 2 }, Some(&doc!{ "a": 1, "c": -1 }));
+        assert!(pinned.is_covered());
+        let mixed = check_filter(&indexes, &doc!{ "a": 1 }, Some(&doc!{ "b": 1, "c": -1 }));
+        assert!(mixed.needs_in_memory_sort());
+
+        // The index can be walked in full just for its order
+        let sort_only = check_filter(&indexes, &doc!{}, Some(&doc!{ "a": -1, "b": -1 }));
+        assert!(sort_only.is_covered());
+        assert_eq!(sort_only.indexes()[0].bounded_keys, 0);
+
+        let unsorted = check_filter(&indexes, &doc!{}, Some(&doc!{ "b": 1 }));
+        assert!(unsorted.is_collection_scan());
+        assert!(unsorted.needs_in_memory_sort());
+    }
+
+    #[test]
+    fn logical_operators() {
+        let and = check_filter(&indexes(), &doc!{ "$and": [{ "owner": "bob" }] }, None);
+        assert!(and.uses_index("owner_date"));
+
+        let or = check_filter(&indexes(), &doc!{
+            "$or": [{ "owner": "bob" }, { "_id": 42 }],
+        }, None);
+        assert!(or.is_covered());
+        assert!(or.uses_index("owner_date"));
+        assert!(or.uses_index("_id_"));
+
+        let partial_or = check_filter(&indexes(), &doc!{
+            "$or": [{ "owner": "bob" }, { "date": 42 }],
+        }, None);
+        assert!(partial_or.is_collection_scan());
+
+        let outer = check_filter(&indexes(), &doc!{
+            "owner": "bob",
+            "$or": [{ "date": 1 }, { "unindexed": 2 }],
+        }, None);
+        assert!(outer.is_covered());
+    }
+
+    #[test]
+    fn special_indexes() {
+        let text = check_filter(&indexes(), &doc!{ "$text": { "$search": "avocado" } }, None);
+        assert!(text.uses_index("fulltext"));
+
+        let geo = check_filter(&indexes(), &doc!{
+            "location": { "$near": { "$geometry": { "type": "Point", "coordinates": [0, 0] } } },
+        }, None);
+        assert!(geo.uses_index("location"));
+
+        let not_geo = check_filter(&indexes(), &doc!{ "title": "avocado" }, None);
+        assert!(not_geo.is_collection_scan());
+    }
+
+    #[test]
+    fn partial_and_sparse_indexes() {
+        let indexes = vec![
+            IndexModel {
+                keys: doc!{ "owner": 1 },
+                options: Some(doc!{
+                    "name": "active_owner",
+                    "partialFilterExpression": { "active": true },
+                }),
+            },
+            IndexModel {
+                keys: doc!{ "email": 1 },
+                options: Some(doc!{ "name": "email", "sparse": true }),
+            },
+        ];
+
+        let implied = check_filter(&indexes, &doc!{ "owner": "bob", "active": true }, None);
+        assert!(implied.uses_index("active_owner"));
+        let in_and = check_filter(&indexes, &doc!{
+            "$and": [{ "owner": "bob" }, { "active": true }],
+        }, None);
+        assert!(in_and.uses_index("active_owner"));
+        let not_implied = check_filter(&indexes, &doc!{ "owner": "bob" }, None);
+        assert!(not_implied.is_collection_scan());
+
+        let present = check_filter(&indexes, &doc!{ "email": "bob@example.com" }, None);
+        assert!(present.uses_index("email"));
+        let missing = check_filter(&indexes, &doc!{ "email": null }, None);
+        assert!(missing.is_collection_scan());
+        let not_equal = check_filter(&indexes, &doc!{ "email": { "$ne": "x" } }, None);
+        assert!(not_equal.is_collection_scan());
+        let sort_only = check_filter(&indexes, &doc!{}, Some(&doc!{ "email": 1 }));
+        assert!(sort_only.is_collection_scan());
+    }
+
+    #[test]
+    fn default_names() {
+        assert_eq!(default_index_name(&doc!{ "a": 1, "b": -1 }), "a_1_b_-1");
+        assert_eq!(default_index_name(&doc!{ "loc": "2dsphere" }), "loc_2dsphere");
+    }
+}
@@ -2,17 +2,52 @@
 
 use std::iter::FromIterator;
 use std::marker::PhantomData;
+use std::time::{ Duration, Instant };
 use std::fmt::{ self, Write };
 use serde::Deserialize;
 use bson::{ Bson, Document, from_bson };
-use crate::error::{ Error, ErrorKind, Result, ResultExt };
+use crate::error::{ Error, ErrorKind, ServerError, Result, ResultExt };
+
+/// A transform applied to each raw document returned by a `Cursor`, which can
+/// be stored in a `Cursor` regardless of the type of the operation it came from.
+///
+/// This is what the `transformer(&self)` method of the
+/// [`Query`](../ops/trait.Query.html#method.transformer),
+/// [`Pipeline`](../ops/trait.Pipeline.html#method.transformer) and
+/// [`FindAndUpdate`](../ops/trait.FindAndUpdate.html#method.transformer)
+/// traits returns. Unlike the static `transform()` function of these traits,
+/// it has access to the operation, so it can depend on parameters only known
+/// at runtime, e.g. a locale or the requested fields. Since the returned
+/// cursors may outlive the operation, the transform must own a copy of the
+/// parameters it needs.
+///
+/// When the transformer is `None`, the raw documents are deserialized as-is,
+/// without calling any transform. This is what plain `Document` queries and
+/// most built-in operations do.
+pub type BoxedTransform = Box<dyn Fn(Document) -> Result<Bson> + Send + Sync>;
+
+/// An empty poll of a tailable cursor returning faster than this means that
+/// the driver didn't wait for the server, because the server has already
+/// closed the cursor. A live await-data cursor is kept waiting by the server
+/// for `maxAwaitTimeMS`, which is one second by default.
+const MIN_AWAIT_TIME: Duration = Duration::from_millis(10);
 
 /// A typed wrapper around the MongoDB `Cursor` type.
-pub struct Cursor<T> {
+///
+/// The type of the transform `F` defaults to `BoxedTransform`, which is
+/// what the cursors returned by `Collection` hold, so they can be named
+/// simply as `Cursor<T>`. `into_boxed()` turns a cursor with any other
+/// kind of transform into such a `Cursor<T>`. A cursor without a transform
+/// deserializes the returned documents directly.
+pub struct Cursor<T, F = BoxedTransform> {
     /// The underlying MongoDB cursor.
     inner: mongodb::Cursor,
-    /// The function applied to each returned `Document` before deserialization.
-    transform: fn(Document) -> Result<Bson>,
+    /// The function applied to each returned `Document` before
+    /// deserialization, if any.
+    transform: Option<F>,
+    /// Whether the cursor waits for new documents instead of ending
+    /// once it has yielded all currently available ones.
+    tailable: bool,
     /// Just here so that the type parameter is used.
     _marker: PhantomData<T>,
 }
@@ -24,14 +59,56 @@ impl<T> Cursor<T> where T: for<'a> Deserialize<'a> {
     pub fn from_cursor_and_transform(
         inner: mongodb::Cursor,
         transform: fn(Document) -> Result<Bson>,
+    ) -> Self {
+        Cursor::from_cursor_and_transformer(inner, Box::new(transform))
+    }
+}
+
+impl<T, F> Cursor<T, F>
+    where T: for<'a> Deserialize<'a>,
+          F: Fn(Document) -> Result<Bson>,
+{
+    /// Creates a strongly-typed cursor from an untyped MongoDB cursor
+    /// and a transformation function, which may capture state, e.g.
+    /// the operation which produced the cursor.
+    #[doc(hidden)]
+    pub fn from_cursor_and_transformer(inner: mongodb::Cursor, transform: F) -> Self {
+        Cursor::from_cursor_and_optional_transformer(inner, Some(transform))
+    }
+
+    /// Creates a strongly-typed cursor from an untyped MongoDB cursor and
+    /// an optional transformation function. Without one, the documents are
+    /// deserialized as-is.
+    pub(crate) fn from_cursor_and_optional_transformer(
+        inner: mongodb::Cursor,
+        transform: Option<F>,
     ) -> Self {
         Cursor {
             inner,
             transform,
+            tailable: false,
             _marker: PhantomData,
         }
     }
 
+    /// Erases the type of the transform by boxing it.
+    pub fn into_boxed(self) -> Cursor<T> where F: Send + Sync + 'static {
+        Cursor {
+            inner: self.inner,
+            transform: self.transform.map(|f| Box::new(f) as BoxedTransform),
+            tailable: self.tailable,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Makes the iterator implementation of this cursor block until new
+    /// documents become available, instead of ending. This only makes sense
+    /// for a tailable, await-data cursor on a capped collection. Iteration
+    /// still ends once the server closes the cursor.
+    pub(crate) fn into_tailable(self) -> Self {
+        Cursor { tailable: true, ..self }
+    }
+
     /// Reads the remaining documents available in the current batch.
     pub fn next_batch<C: FromIterator<T>>(&mut self) -> Result<C> {
         self.inner
@@ -58,16 +135,21 @@ impl<T> Cursor<T> where T: for<'a> Deserialize<'a> {
         // For some reason, the driver hands us back an `Ok(Document)` even if
         // the document itself represents an error. We catch this here.
         if let Some(Bson::String(mut errmsg)) = doc.remove("$err") {
+            doc.insert("errmsg", errmsg.clone());
+            let server_error = ServerError::from_document(&doc);
+
             if let Ok(code) = doc.get_i32("code") {
                 write!(errmsg, " (code: {})", code).ok();
             } else if let Ok(code) = doc.get_i64("code") {
                 write!(errmsg, " (code: {})", code).ok();
             }
 
-            return Err(Error::new(ErrorKind::MongoDbError, errmsg));
+            let kind = server_error.kind_or(ErrorKind::MongoDbError);
+
+            return Err(Error::new(kind, errmsg).with_context::<ServerError>(server_error));
         }
 
-        (self.transform)(doc).and_then(|b| from_bson(b).map_err(From::from))
+        transform_and_deserialize(self.transform.as_ref(), doc)
     }
 
     /// Transforms and tries to deserialize a vector of documents.
@@ -80,21 +162,50 @@ impl<T> Cursor<T> where T: for<'a> Deserialize<'a> {
     }
 }
 
-impl<T> Iterator for Cursor<T> where T: for<'a> Deserialize<'a> {
+/// Applies `transform` to a raw document, if there is one, then deserializes
+/// the result. Without a transform, the document is deserialized directly,
+/// saving the call and the conversion of its result.
+pub(crate) fn transform_and_deserialize<T, F>(transform: Option<&F>, doc: Document) -> Result<T>
+    where T: for<'a> Deserialize<'a>,
+          F: Fn(Document) -> Result<Bson> + ?Sized,
+{
+    let bson = match transform {
+        Some(transform) => transform(doc)?,
+        None => Bson::Document(doc),
+    };
+
+    from_bson(bson).map_err(From::from)
+}
+
+impl<T, F> Iterator for Cursor<T, F>
+    where T: for<'a> Deserialize<'a>,
+          F: Fn(Document) -> Result<Bson>,
+{
     type Item = Result<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner
-            .next()
-            .map(|result| {
-                result
-                    .chain("can't step Cursor")
-                    .and_then(|doc| self.transform_and_deserialize_one(doc))
-            })
+        loop {
+            let started = Instant::now();
+
+            match self.inner.next() {
+                Some(result) => return Some(
+                    result
+                        .chain("can't step Cursor")
+                        .and_then(|doc| self.transform_and_deserialize_one(doc))
+                ),
+                // An await-data cursor only returns empty-handed after the
+                // server has waited for new documents, so just wait again.
+                // Each such round trip blocks on the server, so this doesn't
+                // spin. If the poll didn't block, the server has closed the
+                // cursor, and it would never yield anything again.
+                None if self.tailable && started.elapsed() >= MIN_AWAIT_TIME => continue,
+                None => return None,
+            }
+        }
     }
 }
 
-impl<T> fmt::Debug for Cursor<T> where T: for<'a> Deserialize<'a> {
+impl<T, F> fmt::Debug for Cursor<T, F> where T: for<'a> Deserialize<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Cursor").finish()
     }
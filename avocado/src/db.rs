@@ -1,11 +1,13 @@
 //! Represents a MongoDB database.
 
-use mongodb::Database;
+use bson::{ Bson, Document };
+use mongodb::{ Database, CommandType };
 use crate::{
     coll::Collection,
     doc::Doc,
     ext::DocumentExt,
-    error::{ ErrorKind, Result, ResultExt },
+    bsn::BsonExt,
+    error::{ Error, ErrorKind, Result, ResultExt },
 };
 
 #[cfg(feature = "schema_validation")]
@@ -28,17 +30,15 @@ impl DatabaseExt for Database{
 
     /// Creates a fresh, empty collection. **Drops any existing collection
     /// with the same name.** Recreates the collection with the `$jsonSchema`
-    /// validator based on the `BsonSchema` impl of the document type. Also
+    /// validator based on the `BsonSchema` impl of the document type, as a
+    /// capped collection if `T::capped()` specifies size limits, or as a
+    /// time-series collection if `T::time_series()` specifies a layout. Also
     /// creates indexes specified via the `T::indexes()` method.
     #[cfg(feature = "schema_validation")]
     fn empty_collection<T>(&self) -> Result<Collection<T>>
         where T: Doc + BsonSchema,
               Uid<T>: BsonSchema,
     {
-        use bson::Bson;
-        use crate::bsn::BsonExt;
-        use crate::error::Error;
-
         self.drop_collection(T::NAME).chain("error dropping collection")?;
 
         // Add the `_id` field's spec to the top-level document's BSON schema.
@@ -65,32 +65,28 @@ impl DatabaseExt for Database{
             schema.insert("properties", properties);
             schema
         };
-        let command = doc! {
-            "create": T::NAME,
+        create_collection::<T>(self, doc!{
             "validator": { "$jsonSchema": schema },
-        };
-        let reply = self.command(command, CommandType::CreateCollection, None)?;
-        let err = || Error::new(
-            ErrorKind::MongoDbError,
-            format!("couldn't create {}: {}", T::NAME, reply)
-        );
-        let success = reply.get("ok").and_then(Bson::try_as_bool).ok_or_else(&err)?;
-
-        if success {
-            let coll = self.existing_collection();
-            coll.create_indexes()?;
-            Ok(coll)
-        } else {
-            Err(err())
-        }
+        })?;
+
+        let coll = self.existing_collection();
+        coll.create_indexes()?;
+        Ok(coll)
     }
 
     /// Creates a fresh, empty collection. **Drops any existing collection
     /// with the same name.** Recreates the collection **without** the BSON
-    /// schema validator. Also creates indexes specified via the `T::indexes()`
-    /// method.
+    /// schema validator, but capped or as a time series if `T::capped()` or
+    /// `T::time_series()` says so.
+    /// Also creates indexes specified via the `T::indexes()` method.
     fn empty_collection_novalidate<T: Doc>(&self) -> Result<Collection<T>> {
         self.drop_collection(T::NAME).chain("error dropping collection")?;
+
+        // Ordinary collections are created implicitly upon the first insertion.
+        if T::capped().is_some() || T::time_series().is_some() {
+            create_collection::<T>(self, Document::new())?;
+        }
+
         let coll = self.existing_collection();
         coll.create_indexes()?;
         Ok(coll)
@@ -98,3 +94,44 @@ impl DatabaseExt for Database{
 }
 
 impl<T: Database> DatabaseExt for T {}
+
+/// Creates the collection of `T` using the `create` command, with the
+/// additional collection options in `options`. Also makes the collection
+/// capped or a time series if `T::capped()` or `T::time_series()` says so.
+fn create_collection<T: Doc>(db: &Database, options: Document) -> Result<()> {
+    let mut command = doc!{ "create": T::NAME };
+
+    if let Some(capped) = T::capped() {
+        command.insert("capped", true);
+        command.insert("size", capped.size);
+
+        if let Some(max) = capped.max {
+            command.insert("max", max);
+        }
+    }
+
+    if let Some(time_series) = T::time_series() {
+        command.insert("timeseries", time_series.to_document());
+
+        if let Some(seconds) = time_series.expire_after_seconds {
+            command.insert("expireAfterSeconds", seconds);
+        }
+    }
+
+    for (key, value) in options {
+        command.insert(key, value);
+    }
+
+    let reply = db.command(command, CommandType::CreateCollection, None)?;
+    let err = || Error::new(
+        ErrorKind::MongoDbError,
+        format!("couldn't create {}: {}", T::NAME, reply)
+    );
+    let success = reply.get("ok").and_then(Bson::try_as_bool).ok_or_else(&err)?;
+
+    if success {
+        Ok(())
+    } else {
+        Err(err())
+    }
+}
@@ -6,20 +6,28 @@ use mongodb::{
         IndexModel,
         FindOptions,
         CountOptions,
-        WriteConcern,
         DistinctOptions,
         AggregateOptions,
         InsertManyOptions,
         FindOneAndUpdateOptions,
     },
 };
-use crate::uid::Uid;
+use crate::{
+    uid::Uid,
+    timeseries::TimeSeries,
+    sequence::Sequence,
+    options::{ Capped, ReadOptions, UpdateOptions, ReplaceOptions, DeleteOptions },
+    error::Result,
+};
 
 /// Implemented by top-level (direct collection member) documents only.
 /// These types always have an associated top-level name and an `_id` field.
 pub trait Doc: Serialize + for<'a> Deserialize<'a> {
     /// The type of the unique IDs for the document. A good default choice
     /// is `ObjectId`. TODO(H2CO3): make it default to `ObjectId` (#29661).
+    ///
+    /// It may also be a struct, for compound keys stored as an embedded
+    /// `_id` document; see the documentation of `Uid` for caveats.
     type Id: Eq + Serialize + for <'a> Deserialize<'a>;
 
     /// The name of the collection within the database.
@@ -39,6 +47,37 @@ pub trait Doc: Serialize + for<'a> Deserialize<'a> {
         Vec::new()
     }
 
+    /// Returns the size limits of the collection if it is a capped one.
+    /// `DatabaseExt::empty_collection()` creates capped collections with
+    /// these limits. Defaults to `None`, i.e. an ordinary collection.
+    fn capped() -> Option<Capped> {
+        None
+    }
+
+    /// Returns the layout of the collection if it is a time-series one.
+    /// `DatabaseExt::empty_collection()` creates time-series collections
+    /// accordingly. Defaults to `None`, i.e. an ordinary collection.
+    fn time_series() -> Option<TimeSeries> {
+        None
+    }
+
+    /// Returns how sequential IDs are allocated for this type, if it uses
+    /// them. `Collection::insert_one()` and `insert_many()` then fill in
+    /// missing `_id`s from the sequence. Defaults to `None`, i.e. no
+    /// sequential IDs.
+    fn sequence() -> Option<Sequence> {
+        None
+    }
+
+    /// Generates a new unique ID on the client side, e.g. using one of the
+    /// generators in the [`idgen`](../idgen/index.html) module. It's used by
+    /// `Collection::insert_one()` and `insert_many()` for filling in missing
+    /// `_id`s of types which don't use sequential IDs. Defaults to `None`,
+    /// i.e. missing IDs are generated by the server.
+    fn generate_id() -> Result<Option<Uid<Self>>> {
+        Ok(None)
+    }
+
     /// Options for a count-only query.
     fn count_options() -> CountOptions {
         Default::default()
@@ -59,26 +98,38 @@ pub trait Doc: Serialize + for<'a> Deserialize<'a> {
         Default::default()
     }
 
+    /// Default read preference and read concern of queries, counts,
+    /// `distinct` and aggregations on collections of this type.
+    fn read_options() -> ReadOptions {
+        Default::default()
+    }
+
     /// Options for single and batch insertions.
     fn insert_options() -> InsertManyOptions {
         Default::default()
     }
 
     /// Options for a delete operation.
-    fn delete_options() -> WriteConcern {
+    fn delete_options() -> DeleteOptions {
         Default::default()
     }
 
     /// Options for a (strictly non-upsert) update operation.
-    fn update_options() -> WriteConcern {
+    fn update_options() -> UpdateOptions {
         Default::default()
     }
 
     /// Options for upserting.
-    fn upsert_options() -> WriteConcern {
+    fn upsert_options() -> UpdateOptions {
         Default::default()
     }
 
+    /// Options for replacing and upserting entire documents. Defaults to
+    /// the options of updates, as returned by `update_options()`.
+    fn replace_options() -> ReplaceOptions {
+        Self::update_options().into()
+    }
+
     /// Options for find-and-update operations.
     fn find_and_update_options() -> FindOneAndUpdateOptions {
         Default::default()
@@ -5,7 +5,7 @@ use std::error;
 use std::result;
 use std::ops::Deref;
 use std::borrow::Cow;
-use bson::ValueAccessError;
+use bson::{ Bson, Document, ValueAccessError };
 use backtrace::Backtrace;
 use typemap::{ DebugMap, Key };
 
@@ -25,6 +25,11 @@ pub trait ErrorExt: error::Error {
     /// Structured error kind.
     fn kind(&self) -> ErrorKind;
 
+    /// Details of the error reported by the MongoDB server, if any.
+    fn server_error(&self) -> Option<ServerError> {
+        self.reason().and_then(ErrorExt::server_error)
+    }
+
     /// Until subtrait coercions are implemented, this helper method
     /// should return the receiver as an `&std::error::Error` trait object.
     fn as_std_error(&self) -> &(dyn error::Error + 'static);
@@ -101,6 +106,9 @@ pub enum ErrorKind {
     /// This numerical value can't be represented in BSON (because,
     /// for example, it exceeds the range of `i64`)
     BsonNumberRepr,
+    /// A document is larger than the maximal size of a BSON document that
+    /// MongoDB can store, so it wasn't even sent to the server.
+    DocumentTooLarge,
     /// A field with the specified key was not found in the BSON document.
     MissingDocumentField,
     /// A field with the specified key was found in the BSON document,
@@ -123,6 +131,24 @@ pub enum ErrorKind {
     IntConversionOverflow,
     /// There was an error in the BSON schema for a type.
     BsonSchema,
+    /// A GeoJSON geometry was malformed, e.g. a polygon ring had too few points.
+    InvalidGeometry,
+    /// A write violated a unique index. The name of the index and the
+    /// duplicate key are available from `Error::server_error()`.
+    DuplicateKey,
+    /// A write was rejected by the schema validator of the collection. The
+    /// reason is available from `Error::server_error()`, if the server
+    /// reported it.
+    DocumentValidation,
+    /// A write conflicted with a concurrent operation, e.g. in a transaction.
+    WriteConflict,
+    /// An operation or a write concern exceeded its time limit.
+    Timeout,
+    /// The client is not authenticated or not authorized for the operation.
+    Unauthorized,
+    /// A write (or a primary-only read) was sent to a replica set member
+    /// which is not, or no longer, the primary.
+    NotPrimary,
 }
 
 impl ErrorKind {
@@ -135,6 +161,7 @@ impl ErrorKind {
             BsonEncoding              => "BSON encoding error",
             BsonDecoding              => "BSON decoding error",
             BsonNumberRepr            => "number not i64 nor f64",
+            DocumentTooLarge          => "document too large",
             MissingDocumentField      => "document field not found",
             IllTypedDocumentField     => "document field of unexpected type",
             MissingId                 => "missing unique identifier",
@@ -145,8 +172,37 @@ impl ErrorKind {
             IntConversionUnderflow    => "integer conversion underflowed",
             IntConversionOverflow     => "integer conversion overflowed",
             BsonSchema                => "error in BSON schema",
+            InvalidGeometry           => "invalid GeoJSON geometry",
+            DuplicateKey              => "duplicate key",
+            DocumentValidation        => "document failed validation",
+            WriteConflict             => "write conflict",
+            Timeout                   => "operation timed out",
+            Unauthorized              => "unauthorized",
+            NotPrimary                => "not primary",
         }
     }
+
+    /// Returns the kind of server errors with the given code, or `None` if
+    /// the code doesn't belong to any of the specifically handled classes.
+    /// ```
+    /// # extern crate avocado;
+    /// #
+    /// # use avocado::error::ErrorKind;
+    /// #
+    /// # fn main() {
+    /// #
+    /// assert_eq!(ErrorKind::from_server_code(11000), Some(ErrorKind::DuplicateKey));
+    /// assert_eq!(ErrorKind::from_server_code(10107), Some(ErrorKind::NotPrimary));
+    /// assert_eq!(ErrorKind::from_server_code(2), None);
+    /// #
+    /// # }
+    /// ```
+    pub fn from_server_code(code: i32) -> Option<Self> {
+        SERVER_ERROR_CLASSES
+            .iter()
+            .find(|&&(_, codes)| codes.contains(&code))
+            .map(|&(kind, _)| kind)
+    }
 }
 
 impl fmt::Display for ErrorKind {
@@ -155,6 +211,154 @@ impl fmt::Display for ErrorKind {
     }
 }
 
+/// Server error codes signalling a network problem, a failover, or a shutdown.
+/// Operations failing with these codes may succeed when retried.
+const TRANSIENT_ERROR_CODES: &[i32] = &[
+    6,     // HostUnreachable
+    7,     // HostNotFound
+    89,    // NetworkTimeout
+    91,    // ShutdownInProgress
+    189,   // PrimarySteppedDown
+    262,   // ExceededTimeLimit
+    9001,  // SocketException
+    10107, // NotMaster
+    11600, // InterruptedAtShutdown
+    11602, // InterruptedDueToReplStateChange
+    13435, // NotMasterNoSlaveOk
+    13436, // NotMasterOrSecondary
+];
+
+/// Server error codes belonging to the error kinds that are distinguished
+/// from generic MongoDB errors.
+const SERVER_ERROR_CLASSES: &[(ErrorKind, &[i32])] = &[
+    (ErrorKind::DuplicateKey, &[
+        11000, // DuplicateKey
+        11001, // legacy DuplicateKey on update
+        12582, // legacy DuplicateKey on insert
+    ]),
+    (ErrorKind::DocumentValidation, &[
+        121,   // DocumentValidationFailure
+    ]),
+    (ErrorKind::WriteConflict, &[
+        112,   // WriteConflict
+    ]),
+    (ErrorKind::Timeout, &[
+        50,    // MaxTimeMSExpired
+        64,    // WriteConcernFailed, i.e. `wtimeout` expired
+        89,    // NetworkTimeout
+        262,   // ExceededTimeLimit
+    ]),
+    (ErrorKind::Unauthorized, &[
+        13,    // Unauthorized
+        18,    // AuthenticationFailed
+    ]),
+    (ErrorKind::NotPrimary, &[
+        189,   // PrimarySteppedDown
+        10107, // NotMaster
+        11602, // InterruptedDueToReplStateChange
+        13435, // NotMasterNoSlaveOk
+        13436, // NotMasterOrSecondary
+    ]),
+];
+
+/// The legacy code at the beginning of server error messages about
+/// duplicate keys, which is sometimes the only indication of the error.
+const DUPLICATE_KEY_MESSAGE_PREFIX: &str = "E11000";
+
+/// Details of an error reported by the MongoDB server. It is also used as
+/// a context key for attaching such details to an `Error`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerError {
+    /// The numeric error code.
+    pub code: i32,
+    /// The error message.
+    pub message: String,
+    /// For duplicate key errors, the name of the violated unique index.
+    pub index: Option<String>,
+    /// For duplicate key errors, the duplicate key. It is a document of the
+    /// indexed fields if the server reported it as such (`keyValue`), or a
+    /// string with the key as it appears in the error message otherwise.
+    pub key: Option<Bson>,
+    /// Additional information about the error (`errInfo`), e.g. the reason
+    /// of a document validation failure, if the server reported it.
+    pub details: Option<Document>,
+}
+
+impl ServerError {
+    /// Creates the details of an error from its code and its message,
+    /// extracting the index and the key of duplicate key errors from the
+    /// message.
+    pub fn new<S: Into<String>>(code: i32, message: S) -> Self {
+        let mut server_error = ServerError {
+            code,
+            message: message.into(),
+            index: None,
+            key: None,
+            details: None,
+        };
+
+        if ErrorKind::from_server_code(code) == Some(ErrorKind::DuplicateKey) {
+            let (index, key) = parse_duplicate_key(&server_error.message);
+            server_error.index = index;
+            server_error.key = key;
+        }
+
+        server_error
+    }
+
+    /// Extracts the details of an error from an element of the
+    /// `writeErrors` array, or from the `writeConcernError` document,
+    /// of the reply to a write command.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn from_document(error: &Document) -> Self {
+        let code = match error.get("code") {
+            Some(&Bson::I32(code)) => code,
+            Some(&Bson::I64(code)) => code as i32,
+            Some(&Bson::FloatingPoint(code)) => code as i32,
+            _ => 0,
+        };
+        let message = error.get_str("errmsg").unwrap_or("unknown error");
+        let mut server_error = ServerError::new(code, message);
+
+        if let Ok(key) = error.get_document("keyValue") {
+            server_error.key = Some(key.clone().into());
+        }
+        if let Ok(details) = error.get_document("errInfo") {
+            server_error.details = Some(details.clone());
+        }
+
+        server_error
+    }
+
+    /// Returns the kind of this error: one of the specific server error
+    /// kinds if the code belongs to one of them, or `fallback` otherwise.
+    pub fn kind_or(&self, fallback: ErrorKind) -> ErrorKind {
+        ErrorKind::from_server_code(self.code).unwrap_or(fallback)
+    }
+}
+
+impl Key for ServerError {
+    type Value = ServerError;
+}
+
+/// Extracts the index name and the key from a duplicate key error message,
+/// which looks like `E11000 duplicate key error collection: db.coll index:
+/// email_1 dup key: { email: "x@example.com" }`.
+fn parse_duplicate_key(message: &str) -> (Option<String>, Option<Bson>) {
+    let index = message
+        .find(" index: ")
+        .map(|start| &message[start + " index: ".len()..])
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(String::from);
+    let key = message
+        .find(" dup key: ")
+        .map(|start| message[start + " dup key: ".len()..].trim())
+        .map(Bson::from);
+
+    (index, key)
+}
+
 /// The central error type for Avocado.
 #[derive(Debug)]
 pub struct Error {
@@ -264,6 +468,82 @@ impl Error {
         self.set_context::<K>(value);
         self
     }
+
+    /// Returns `true` if the error is likely to be transient, i.e. if simply
+    /// retrying the failed operation has a chance of succeeding. This is the
+    /// case for network errors and for server errors indicating a failover
+    /// or a shutdown in progress. All other errors are considered fatal.
+    ///
+    /// The whole chain of causes is inspected.
+    /// ```
+    /// # extern crate avocado;
+    /// #
+    /// # use avocado::error::{ Error, ErrorKind };
+    /// #
+    /// # fn main() {
+    /// #
+    /// let error = Error::new(ErrorKind::MissingId, "not transient");
+    /// assert!(!error.is_transient());
+    /// #
+    /// # }
+    /// ```
+    pub fn is_transient(&self) -> bool {
+        let mut error: Option<&dyn ErrorExt> = Some(self);
+
+        while let Some(current) = error {
+            let std_error = current.as_std_error();
+
+            if let Some(driver_error) = std_error.downcast_ref::<mongodb::error::Error>() {
+                match *driver_error {
+                    mongodb::error::Error::IoError(_) => return true,
+                    mongodb::error::Error::CodedError(code) => {
+                        if TRANSIENT_ERROR_CODES.contains(&(code as i32)) {
+                            return true;
+                        }
+                    }
+                    _ => {}
+                }
+            } else if let Some(write_error) = std_error.downcast_ref::<mongodb::error::WriteError>() {
+                if TRANSIENT_ERROR_CODES.contains(&write_error.code) {
+                    return true;
+                }
+            }
+
+            error = current.reason();
+        }
+
+        false
+    }
+
+    /// Returns `true` if the error was caused by a write violating a unique
+    /// index, e.g. an insertion or an upsert with an already-existing `_id`.
+    pub(crate) fn is_duplicate_key(&self) -> bool {
+        self.kind == ErrorKind::DuplicateKey
+    }
+
+    /// Returns the details of the error reported by the MongoDB server, if
+    /// the error (or any of its causes) originates from the server.
+    /// ```
+    /// # extern crate avocado;
+    /// #
+    /// # use avocado::error::{ Error, ErrorKind, ServerError };
+    /// #
+    /// # fn main() {
+    /// #
+    /// let message = "E11000 duplicate key error collection: shop.users \
+    ///                index: email_1 dup key: { email: \"x@example.com\" }";
+    /// let error = Error::new(ErrorKind::DuplicateKey, "can't insert user")
+    ///     .with_context::<ServerError>(ServerError::new(11000, message));
+    /// let details = error.server_error().unwrap();
+    ///
+    /// assert_eq!(details.index.unwrap(), "email_1");
+    /// assert_eq!(details.key.unwrap().as_str(), Some(r#"{ email: "x@example.com" }"#));
+    /// #
+    /// # }
+    /// ```
+    pub fn server_error(&self) -> Option<ServerError> {
+        ErrorExt::server_error(self)
+    }
 }
 
 impl ErrorExt for Error {
@@ -280,6 +560,12 @@ impl ErrorExt for Error {
         self.kind
     }
 
+    fn server_error(&self) -> Option<ServerError> {
+        self.context::<ServerError>()
+            .cloned()
+            .or_else(|| self.reason().and_then(ErrorExt::server_error))
+    }
+
     fn as_std_error(&self) -> &(dyn error::Error + 'static) {
         self
     }
@@ -359,14 +645,124 @@ impl_error_type! { serde_json::Error,       JsonTranscoding,    "JSON transcodin
 impl_error_type! { bson::EncoderError,      BsonEncoding,       "BSON encoding error" }
 impl_error_type! { bson::DecoderError,      BsonDecoding,       "BSON decoding error" }
 impl_error_type! { bson::oid::Error,        ObjectIdGeneration, "ObjectId generation error" }
-impl_error_type! { mongodb::error::Error,   MongoDbError,       "MongoDB error" }
-impl_error_type! {
+
+/// Implementing `ErrorExt` and `From` boilerplate for errors coming from
+/// the server, classified according to their error code.
+macro_rules! impl_server_error_type {
+    ($ty:path, $kind:ident, $message:expr, |$error:ident| $server_error:expr) => {
+        impl From<$ty> for Error {
+            fn from(error: $ty) -> Self {
+                Self::with_cause($message, error)
+            }
+        }
+
+        impl ErrorExt for $ty {
+            fn kind(&self) -> ErrorKind {
+                self.server_error().map_or(
+                    ErrorKind::$kind,
+                    |server_error| server_error.kind_or(ErrorKind::$kind),
+                )
+            }
+
+            fn server_error(&self) -> Option<ServerError> {
+                let $error = self;
+                $server_error
+            }
+
+            fn as_std_error(&self) -> &(dyn error::Error + 'static) {
+                self
+            }
+        }
+    }
+}
+
+impl_server_error_type! {
+    mongodb::error::Error,
+    MongoDbError,
+    "MongoDB error",
+    |error| match *error {
+        mongodb::error::Error::CodedError(code) => {
+            Some(ServerError::new(code as i32, error.to_string()))
+        }
+        // Some server errors only make it through as a message, but
+        // duplicate key errors can still be recognized by their prefix.
+        _ => {
+            let message = error.to_string();
+
+            message
+                .find(DUPLICATE_KEY_MESSAGE_PREFIX)
+                .map(|start| ServerError::new(11000, &message[start..]))
+        }
+    }
+}
+impl_server_error_type! {
     mongodb::error::WriteError,
     MongoDbWriteException,
-    "MongoDB write exception"
+    "MongoDB write exception",
+    |error| Some(ServerError::new(error.code, error.message.as_str()))
 }
-impl_error_type! {
+impl_server_error_type! {
     mongodb::error::BulkWriteError,
     MongoDbBulkWriteException,
-    "MongoDB bulk write error"
+    "MongoDB bulk write error",
+    |error| Some(ServerError::new(error.code, error.message.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_error_from_write_error_document() {
+        let duplicate = ServerError::from_document(&doc!{
+            "index": 0,
+            "code": 11000,
+            "errmsg": "E11000 duplicate key error collection: shop.users index: email_1 dup key: { email: \"x@example.com\" }",
+            "keyPattern": { "email": 1 },
+            "keyValue": { "email": "x@example.com" },
+        });
+
+        assert_eq!(ErrorKind::from_server_code(duplicate.code), Some(ErrorKind::DuplicateKey));
+        assert_eq!(duplicate.index, Some(String::from("email_1")));
+        assert_eq!(duplicate.key, Some(bson!({ "email": "x@example.com" })));
+        assert_eq!(duplicate.details, None);
+
+        let invalid = ServerError::from_document(&doc!{
+            "index": 0,
+            "code": 121,
+            "errmsg": "Document failed validation",
+            "errInfo": { "failingDocumentId": 1 },
+        });
+
+        assert_eq!(invalid.kind_or(ErrorKind::MongoDbWriteException), ErrorKind::DocumentValidation);
+        assert_eq!(invalid.index, None);
+        assert_eq!(invalid.key, None);
+        assert_eq!(invalid.details, Some(doc!{ "failingDocumentId": 1 }));
+
+        let unknown = ServerError::from_document(&doc!{ "code": 2_i64 });
+
+        assert_eq!(unknown.message, "unknown error");
+        assert_eq!(unknown.kind_or(ErrorKind::MongoDbWriteException), ErrorKind::MongoDbWriteException);
+    }
+
+    #[test]
+    fn server_error_classes_are_disjoint() {
+        for (i, &(_, codes)) in SERVER_ERROR_CLASSES.iter().enumerate() {
+            for &(_, other) in &SERVER_ERROR_CLASSES[i + 1..] {
+                assert!(codes.iter().all(|code| !other.contains(code)));
+            }
+        }
+    }
+
+    #[test]
+    fn server_error_details_in_cause_chain() {
+        let server_error = ServerError::new(112, "WriteConflict");
+        let cause = Error::new(ErrorKind::WriteConflict, "conflict")
+            .with_context::<ServerError>(server_error.clone());
+        let error = Error::with_cause("transaction failed", cause);
+
+        assert_eq!(error.kind(), ErrorKind::WriteConflict);
+        assert_eq!(error.server_error(), Some(server_error));
+        assert_eq!(Error::new(ErrorKind::MissingId, "no server").server_error(), None);
+    }
 }
@@ -0,0 +1,347 @@
+//! Typed representation of the output of the `explain` command.
+//!
+//! The `Collection::explain_find()`, `Collection::explain_count()` and
+//! `Collection::explain_aggregate()` methods describe how the server would
+//! execute a query, a count or a pipeline, which makes it possible to
+//! check e.g. whether an index is used, in integration tests:
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! # use avocado::explain::Verbosity;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[index(name = "by_email", keys(email = "ascending"))]
+//! struct User {
+//!     _id: Uid<User>,
+//!     email: String,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let users: Collection<User> = db.existing_collection();
+//! let plan = users.explain_find(
+//!     doc!{ "email": "alice@example.com" },
+//!     Verbosity::ExecutionStats,
+//! )?;
+//!
+//! assert!(plan.uses_index("by_email"));
+//! assert!(!plan.is_collection_scan());
+//! assert!(plan.docs_examined() <= Some(1));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+use bson::{ Bson, Document, from_bson };
+use crate::error::{ Error, ErrorKind, Result, ResultExt };
+
+/// How much information the `explain` command should return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Verbosity {
+    /// Only the plan chosen by the query planner, without executing it.
+    QueryPlanner,
+    /// The chosen plan, along with statistics about its execution.
+    ExecutionStats,
+    /// Like `ExecutionStats`, but also for the rejected plans.
+    AllPlansExecution,
+}
+
+impl Verbosity {
+    /// Returns the name of this verbosity level as understood by MongoDB.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verbosity::QueryPlanner => "queryPlanner",
+            Verbosity::ExecutionStats => "executionStats",
+            Verbosity::AllPlansExecution => "allPlansExecution",
+        }
+    }
+}
+
+impl Default for Verbosity {
+    fn default() -> Self {
+        Verbosity::ExecutionStats
+    }
+}
+
+impl From<Verbosity> for Bson {
+    fn from(verbosity: Verbosity) -> Self {
+        Bson::from(verbosity.as_str())
+    }
+}
+
+/// A stage of a query plan, e.g. `IXSCAN`, `FETCH` or `COLLSCAN`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanStage {
+    /// The name of the stage.
+    pub stage: String,
+    /// For index scans, the name of the index used.
+    #[serde(default)]
+    pub index_name: Option<String>,
+    /// For index scans, the keys of the index used.
+    #[serde(default)]
+    pub key_pattern: Option<Document>,
+    /// For scans, the direction: `"forward"` or `"backward"`.
+    #[serde(default)]
+    pub direction: Option<String>,
+    /// The single input stage, if any.
+    #[serde(default)]
+    input_stage: Option<Box<PlanStage>>,
+    /// Multiple input stages, e.g. for `OR`.
+    #[serde(default)]
+    input_stages: Vec<PlanStage>,
+}
+
+impl PlanStage {
+    /// Returns the stages this stage receives its input from.
+    pub fn inputs(&self) -> Vec<&PlanStage> {
+        self.input_stage
+            .iter()
+            .map(AsRef::as_ref)
+            .chain(&self.input_stages)
+            .collect()
+    }
+
+    /// Returns this stage and all of its (transitive) inputs, depth-first.
+    pub fn flatten(&self) -> Vec<&PlanStage> {
+        let mut stages = vec![self];
+
+        for input in self.inputs() {
+            stages.extend(input.flatten());
+        }
+
+        stages
+    }
+}
+
+/// Statistics about the execution of the winning plan.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionStats {
+    /// The number of documents returned.
+    #[serde(default)]
+    pub n_returned: u64,
+    /// The total time spent executing the query, in milliseconds.
+    #[serde(default)]
+    pub execution_time_millis: u64,
+    /// The number of index keys scanned.
+    #[serde(default)]
+    pub total_keys_examined: u64,
+    /// The number of documents scanned.
+    #[serde(default)]
+    pub total_docs_examined: u64,
+}
+
+/// The query plan of an explained query, count or pipeline.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainPlan {
+    /// The plan chosen by the query planner.
+    pub winning_plan: PlanStage,
+    /// The alternative plans considered and rejected by the query planner.
+    pub rejected_plans: Vec<PlanStage>,
+    /// Execution statistics, unless the verbosity was `QueryPlanner`.
+    pub execution_stats: Option<ExecutionStats>,
+    /// The complete, raw reply of the server.
+    pub raw: Document,
+}
+
+impl ExplainPlan {
+    /// Parses the reply of the `explain` command. For aggregations, the plan
+    /// of the initial `$cursor` stage (the part of the pipeline that is
+    /// executed by the query system) is returned.
+    pub fn from_raw(raw: Document) -> Result<Self> {
+        let explained = find_explained_query(&raw).ok_or_else(|| Error::new(
+            ErrorKind::MissingDocumentField,
+            "explain output contains no query plan"
+        ))?;
+        let planner = explained.get_document("queryPlanner")?;
+        let winning_plan = parse_plan(planner.get_document("winningPlan")?)?;
+        let rejected_plans = match planner.get_array("rejectedPlans") {
+            Ok(plans) => plans
+                .iter()
+                .map(|bson| match *bson {
+                    Bson::Document(ref plan) => parse_plan(plan),
+                    _ => Err(Error::new(
+                        ErrorKind::IllTypedDocumentField,
+                        "rejected plan is not a document"
+                    )),
+                })
+                .collect::<Result<_>>()?,
+            Err(_) => Vec::new(),
+        };
+        let execution_stats = match explained.get_document("executionStats") {
+            Ok(stats) => Some(
+                from_bson(stats.clone().into()).chain("can't parse execution stats")?
+            ),
+            Err(_) => None,
+        };
+
+        Ok(ExplainPlan { winning_plan, rejected_plans, execution_stats, raw })
+    }
+
+    /// Returns the names of the stages of the winning plan, depth-first.
+    pub fn stages(&self) -> Vec<&str> {
+        self.winning_plan
+            .flatten()
+            .into_iter()
+            .map(|stage| stage.stage.as_str())
+            .collect()
+    }
+
+    /// Returns the names of the indexes used by the winning plan.
+    pub fn indexes_used(&self) -> Vec<&str> {
+        self.winning_plan
+            .flatten()
+            .into_iter()
+            .filter_map(|stage| stage.index_name.as_ref().map(String::as_str))
+            .collect()
+    }
+
+    /// Returns `true` if the winning plan uses the index with the given name.
+    pub fn uses_index(&self, name: &str) -> bool {
+        self.indexes_used().contains(&name)
+    }
+
+    /// Returns `true` if the winning plan scans the whole collection.
+    pub fn is_collection_scan(&self) -> bool {
+        self.stages().contains(&"COLLSCAN")
+    }
+
+    /// Returns the number of documents examined, if execution stats are known.
+    pub fn docs_examined(&self) -> Option<u64> {
+        self.execution_stats.map(|stats| stats.total_docs_examined)
+    }
+
+    /// Returns the number of index keys examined, if execution stats are known.
+    pub fn keys_examined(&self) -> Option<u64> {
+        self.execution_stats.map(|stats| stats.total_keys_examined)
+    }
+
+    /// Returns the number of documents returned, if execution stats are known.
+    pub fn n_returned(&self) -> Option<u64> {
+        self.execution_stats.map(|stats| stats.n_returned)
+    }
+
+    /// Returns the execution time, if execution stats are known.
+    pub fn execution_time(&self) -> Option<Duration> {
+        self.execution_stats.map(|stats| Duration::from_millis(stats.execution_time_millis))
+    }
+}
+
+/// Finds the document containing `queryPlanner` in the output of `explain`.
+/// For finds and counts, this is the top-level document. For aggregations,
+/// it may be the top-level document too, if the whole pipeline could be
+/// executed by the query system; otherwise it's in the `$cursor` stage.
+fn find_explained_query(raw: &Document) -> Option<&Document> {
+    if raw.contains_key("queryPlanner") {
+        return Some(raw);
+    }
+
+    for bson in raw.get_array("stages").ok()? {
+        if let Bson::Document(ref stage) = *bson {
+            if let Ok(cursor) = stage.get_document("$cursor") {
+                return Some(cursor);
+            }
+        }
+    }
+
+    None
+}
+
+/// Parses a plan, unwrapping it from the `queryPlan` field if necessary,
+/// as it's done by newer servers using the slot-based execution engine.
+fn parse_plan(raw: &Document) -> Result<PlanStage> {
+    let plan = raw.get_document("queryPlan").unwrap_or(raw);
+    from_bson(plan.clone().into()).chain("can't parse query plan")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorExt;
+    use super::*;
+
+    #[test]
+    fn parse_find_explain() -> Result<()> {
+        let raw = doc!{
+            "queryPlanner": {
+                "namespace": "test.User",
+                "winningPlan": {
+                    "stage": "FETCH",
+                    "inputStage": {
+                        "stage": "IXSCAN",
+                        "keyPattern": { "email": 1 },
+                        "indexName": "by_email",
+                        "direction": "forward",
+                    },
+                },
+                "rejectedPlans": [
+                    { "stage": "COLLSCAN", "direction": "forward" },
+                ],
+            },
+            "executionStats": {
+                "nReturned": 1,
+                "executionTimeMillis": 3,
+                "totalKeysExamined": 1,
+                "totalDocsExamined": 1_i64,
+            },
+            "ok": 1.0,
+        };
+        let plan = ExplainPlan::from_raw(raw)?;
+
+        assert_eq!(plan.stages(), ["FETCH", "IXSCAN"]);
+        assert_eq!(plan.indexes_used(), ["by_email"]);
+        assert!(plan.uses_index("by_email"));
+        assert!(!plan.is_collection_scan());
+        assert_eq!(plan.rejected_plans.len(), 1);
+        assert_eq!(plan.docs_examined(), Some(1));
+        assert_eq!(plan.keys_examined(), Some(1));
+        assert_eq!(plan.execution_time(), Some(Duration::from_millis(3)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_aggregate_explain() -> Result<()> {
+        let raw = doc!{
+            "stages": [
+                {
+                    "$cursor": {
+                        "queryPlanner": {
+                            "winningPlan": {
+                                "queryPlan": {
+                                    "stage": "OR",
+                                    "inputStages": [
+                                        { "stage": "IXSCAN", "indexName": "a_1" },
+                                        { "stage": "COLLSCAN" },
+                                    ],
+                                },
+                            },
+                        },
+                    },
+                },
+                { "$group": { "_id": "$a" } },
+            ],
+        };
+        let plan = ExplainPlan::from_raw(raw)?;
+
+        assert_eq!(plan.stages(), ["OR", "IXSCAN", "COLLSCAN"]);
+        assert!(plan.is_collection_scan());
+        assert!(plan.uses_index("a_1"));
+        assert!(plan.execution_stats.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_plan_is_error() {
+        let error = ExplainPlan::from_raw(doc!{ "ok": 1.0 }).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MissingDocumentField);
+    }
+}
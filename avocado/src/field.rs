@@ -0,0 +1,370 @@
+//! Statically-typed paths to the fields of documents.
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::hash::Hash;
+use std::collections::{ VecDeque, BTreeSet, HashSet };
+use std::fmt::{ Debug, Formatter, Result as FmtResult };
+use serde::Deserialize;
+use bson::{ Bson, Document, UtcDateTime, oid::ObjectId };
+#[cfg(feature = "raw_uuid")]
+use uuid::Uuid;
+use crate::{
+    doc::Doc,
+    uid::Uid,
+};
+
+/// A path to a (possibly embedded) field of type `V` within a value of
+/// type `T`. It is rendered in MongoDB "dot notation", e.g. `"foo.bar"`.
+///
+/// When `#[derive(Doc)]` is applied to a type together with the attribute
+/// `#[avocado(fields)]`, one associated constant of this type is generated
+/// for each serialized field, named after the field itself. The path of the
+/// generated constant respects `#[serde(rename = "...")]` and
+/// `#[serde(rename_all = "...")]`. Fields that are skipped or flattened
+/// don't get an associated constant.
+///
+/// Since the type of the field is part of the type of the path, a `Field`
+/// can be used directly as a [`Distinct`](../ops/trait.Distinct.html)
+/// query, in which case the type of the returned values is inferred from
+/// the type of the field via [`DistinctValue`](trait.DistinctValue.html).
+/// The elements of array fields are returned as separate values:
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// # #[macro_use]
+/// # extern crate avocado_derive;
+/// # extern crate avocado;
+/// #
+/// # use std::collections::BTreeSet;
+/// # use avocado::prelude::*;
+/// #
+/// #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+/// #[avocado(fields)]
+/// struct Employee {
+///     #[serde(rename = "_id")]
+///     id: Uid<Employee>,
+///     #[serde(rename = "fullName")]
+///     name: String,
+///     age: u32,
+///     skills: Vec<String>,
+/// }
+///
+/// # fn main() -> AvocadoResult<()> {
+/// # let client = Client::with_uri("mongodb://localhost:27017/")?;
+/// # let db = client.db("avocado_example_db");
+/// let employees: Collection<Employee> = db.existing_collection();
+///
+/// assert_eq!(Employee::name.path(), "fullName");
+///
+/// let ages: Vec<u32> = employees.distinct(Employee::age)?;
+/// let names: BTreeSet<String> = employees.distinct(Employee::name)?;
+/// let skills: BTreeSet<String> = employees.distinct(Employee::skills)?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub struct Field<T, V> {
+    /// The dot-separated path of the field.
+    path: Cow<'static, str>,
+    /// Just here so that the type parameters are used.
+    _marker: PhantomData<(T, V)>,
+}
+
+impl<T, V> Field<T, V> {
+    /// Creates a field path from its dot-separated string representation.
+    ///
+    /// The correspondence between the path and the type of the field is
+    /// **not** checked; it's the responsibility of the caller.
+    pub const fn new(path: &'static str) -> Self {
+        Field {
+            path: Cow::Borrowed(path),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the dot-separated string representation of this path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Consumes the field and returns its dot-separated path.
+    pub fn into_path(self) -> Cow<'static, str> {
+        self.path
+    }
+
+    /// Appends a path relative to the embedded document at `self`.
+    /// ```
+    /// # extern crate avocado;
+    /// #
+    /// # use avocado::field::Field;
+    /// #
+    /// struct Address { zip: u32 }
+    /// struct Person { address: Address }
+    ///
+    /// # fn main() {
+    /// let address: Field<Person, Address> = Field::new("address");
+    /// let zip: Field<Address, u32> = Field::new("zip");
+    ///
+    /// assert_eq!(address.join(zip).path(), "address.zip");
+    /// # }
+    /// ```
+    pub fn join<W>(self, inner: Field<V, W>) -> Field<T, W> {
+        Field {
+            path: Cow::Owned(format!("{}.{}", self.path, inner.path)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Prefixes the path with the identifier of an array filter. The result
+    /// is meant to be used as a key in the array filters of
+    /// `UpdateOptions`, where it refers to a field of the array elements
+    /// bound to the identifier. See `filtered()` for an example.
+    pub fn with_identifier(self, identifier: &str) -> ArrayFilterPath<T, V> {
+        ArrayFilterPath {
+            path: format!("{}.{}", identifier, self.path),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, V> Field<T, Vec<V>> {
+    /// Refers to the individual elements of an array-valued field.
+    ///
+    /// MongoDB implicitly traverses arrays when matching and when collecting
+    /// distinct values, so the path itself doesn't change; only the type of
+    /// the value it refers to does.
+    pub fn each(self) -> Field<T, V> {
+        self.element("")
+    }
+
+    /// Refers to the first element of the array that matched the filter of
+    /// an update, using the positional operator `$`.
+    /// ```
+    /// # extern crate avocado;
+    /// #
+    /// # use avocado::field::Field;
+    /// #
+    /// struct Item { qty: u32 }
+    /// struct Order { items: Vec<Item> }
+    ///
+    /// # fn main() {
+    /// let items: Field<Order, Vec<Item>> = Field::new("items");
+    /// let qty: Field<Item, u32> = Field::new("qty");
+    ///
+    /// assert_eq!(items.first_matched().join(qty).path(), "items.$.qty");
+    /// # }
+    /// ```
+    pub fn first_matched(self) -> Field<T, V> {
+        self.element(".$")
+    }
+
+    /// Refers to all elements of the array in an update, using the
+    /// all positional operator `$[]`.
+    /// ```
+    /// # extern crate avocado;
+    /// #
+    /// # use avocado::field::Field;
+    /// #
+    /// struct Item { qty: u32 }
+    /// struct Order { items: Vec<Item> }
+    ///
+    /// # fn main() {
+    /// let items: Field<Order, Vec<Item>> = Field::new("items");
+    /// let qty: Field<Item, u32> = Field::new("qty");
+    ///
+    /// assert_eq!(items.all_elements().join(qty).path(), "items.$[].qty");
+    /// # }
+    /// ```
+    pub fn all_elements(self) -> Field<T, V> {
+        self.element(".$[]")
+    }
+
+    /// Refers to the elements of the array that match the array filter
+    /// bound to `identifier`, using the filtered positional operator
+    /// `$[<identifier>]`. The identifier must start with a lowercase
+    /// letter and contain only alphanumeric characters.
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate bson;
+    /// # extern crate avocado;
+    /// #
+    /// # use avocado::field::Field;
+    /// # use avocado::options::UpdateOptions;
+    /// #
+    /// struct Item { sku: String, qty: u32 }
+    /// struct Order { items: Vec<Item> }
+    ///
+    /// # fn main() {
+    /// let items: Field<Order, Vec<Item>> = Field::new("items");
+    /// let sku: Field<Item, String> = Field::new("sku");
+    /// let qty: Field<Item, u32> = Field::new("qty");
+    ///
+    /// let update = doc!{
+    ///     "$inc": { (items.filtered("restocked").join(qty).path()): 10 }
+    /// };
+    /// let options = UpdateOptions::default().array_filters(vec![
+    ///     doc!{ (sku.with_identifier("restocked").path()): { "$in": ["A1", "B2"] } },
+    /// ]);
+    ///
+    /// assert_eq!(update, doc!{ "$inc": { "items.$[restocked].qty": 10 } });
+    /// assert_eq!(options.array_filters, vec![
+    ///     doc!{ "restocked.sku": { "$in": ["A1", "B2"] } },
+    /// ]);
+    /// # }
+    /// ```
+    pub fn filtered(self, identifier: &str) -> Field<T, V> {
+        self.element(&format!(".$[{}]", identifier))
+    }
+
+    /// Helper for the element accessors: appends `suffix` to the path.
+    fn element(self, suffix: &str) -> Field<T, V> {
+        let path = if suffix.is_empty() {
+            self.path
+        } else {
+            Cow::Owned(format!("{}{}", self.path, suffix))
+        };
+
+        Field {
+            path,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A path to a field of type `V` within the array elements of type `T`
+/// bound to the identifier of an array filter, as returned by
+/// `Field::with_identifier()`. It is rendered as `"<identifier>.<path>"`.
+///
+/// Unlike a `Field`, it is only meaningful as a key in an array filter,
+/// so it can't be joined, nor used as a query path, e.g. for `distinct()`.
+pub struct ArrayFilterPath<T, V> {
+    /// The path of the field, prefixed with the identifier.
+    path: String,
+    /// Just here so that the type parameters are used.
+    _marker: PhantomData<(T, V)>,
+}
+
+impl<T, V> ArrayFilterPath<T, V> {
+    /// Returns the string representation of this path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Consumes the path and returns its string representation.
+    pub fn into_path(self) -> String {
+        self.path
+    }
+}
+
+/// The type of the individual values that the `distinct` command returns
+/// for a field of type `Self`. It's the type of the field itself, except
+/// for arrays and other sequences, since the server returns their elements
+/// as separate values.
+///
+/// Implement it with `type Value = Self;` for your own embedded types in
+/// order to use fields of those types as `Distinct` queries.
+pub trait DistinctValue {
+    /// The type of a single distinct value.
+    type Value: for<'a> Deserialize<'a>;
+}
+
+/// Implements `DistinctValue` for types which are returned as-is.
+macro_rules! impl_distinct_value_scalar {
+    ($($ty:ty),*) => {$(
+        impl DistinctValue for $ty {
+            type Value = Self;
+        }
+    )*}
+}
+
+impl_distinct_value_scalar!{
+    bool, i8, i16, i32, i64, u8, u16, u32, u64, f32, f64, char, String,
+    Bson, Document, ObjectId, UtcDateTime
+}
+
+#[cfg(feature = "raw_uuid")]
+impl_distinct_value_scalar!{ Uuid }
+
+impl<T: Doc> DistinctValue for Uid<T> {
+    type Value = Self;
+}
+
+impl<V: DistinctValue> DistinctValue for Option<V> {
+    type Value = Option<V::Value>;
+}
+
+impl<V: DistinctValue> DistinctValue for Box<V> {
+    type Value = V::Value;
+}
+
+impl<V> DistinctValue for Vec<V> where V: for<'a> Deserialize<'a> {
+    type Value = V;
+}
+
+impl<V> DistinctValue for VecDeque<V> where V: for<'a> Deserialize<'a> {
+    type Value = V;
+}
+
+impl<V> DistinctValue for BTreeSet<V> where V: for<'a> Deserialize<'a> {
+    type Value = V;
+}
+
+impl<V> DistinctValue for HashSet<V> where V: Eq + Hash + for<'a> Deserialize<'a> {
+    type Value = V;
+}
+
+// Manual impls of common traits follow, for more relaxed trait bounds.
+
+impl<T, V> Clone for Field<T, V> {
+    fn clone(&self) -> Self {
+        Field {
+            path: self.path.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, V> PartialEq for Field<T, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl<T, V> Eq for Field<T, V> {}
+
+impl<T, V> Debug for Field<T, V> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_tuple("Field").field(&self.path).finish()
+    }
+}
+
+impl<T, V> AsRef<str> for Field<T, V> {
+    fn as_ref(&self) -> &str {
+        self.path()
+    }
+}
+
+impl<T, V> Clone for ArrayFilterPath<T, V> {
+    fn clone(&self) -> Self {
+        ArrayFilterPath {
+            path: self.path.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, V> PartialEq for ArrayFilterPath<T, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl<T, V> Eq for ArrayFilterPath<T, V> {}
+
+impl<T, V> Debug for ArrayFilterPath<T, V> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_tuple("ArrayFilterPath").field(&self.path).finish()
+    }
+}
@@ -0,0 +1,769 @@
+//! GeoJSON geometries and geospatial query operators.
+//!
+//! The geometry types serialize as valid GeoJSON objects, so they can be
+//! stored in fields covered by a `2dsphere` index, and they can be used as
+//! the arguments of the geospatial query operators, e.g.:
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate bson;
+//! # extern crate avocado;
+//! #
+//! # use avocado::geo::{ Point, Near };
+//! #
+//! # fn main() {
+//! let home = Point::new(19.04, 47.49);
+//! let filter = doc!{
+//!     "location": Near::sphere(home).max_distance(1000.0),
+//! };
+//! assert_eq!(filter, doc!{
+//!     "location": {
+//!         "$nearSphere": {
+//!             "$geometry": {
+//!                 "type": "Point",
+//!                 "coordinates": [19.04, 47.49],
+//!             },
+//!             "$maxDistance": 1000.0,
+//!         }
+//!     }
+//! });
+//! # }
+//! ```
+
+use std::fmt;
+use bson::{ Bson, Document, to_bson };
+use serde::{
+    ser::{ Serialize, Serializer, SerializeStruct },
+    de::{ Deserialize, Deserializer, Error as DeError },
+};
+use crate::{
+    doc::Doc,
+    ops::Pipeline,
+    ext::DocumentExt,
+    error::{ Error, ErrorKind, Result },
+};
+
+/// A single position, given by its longitude and latitude (in this order,
+/// as mandated by GeoJSON). For `2d` indexes, these are the `x` and `y`
+/// coordinates in the plane.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Point {
+    /// Longitude, or `x` coordinate.
+    pub lng: f64,
+    /// Latitude, or `y` coordinate.
+    pub lat: f64,
+}
+
+impl Point {
+    /// Creates a point from its longitude and latitude.
+    pub fn new(lng: f64, lat: f64) -> Self {
+        Point { lng, lat }
+    }
+
+    /// Returns the GeoJSON position `[lng, lat]` of the point.
+    fn position(self) -> [f64; 2] {
+        [self.lng, self.lat]
+    }
+
+    /// Creates a point out of a GeoJSON position `[lng, lat]`.
+    fn from_position(position: [f64; 2]) -> Self {
+        Point::new(position[0], position[1])
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {})", self.lng, self.lat)
+    }
+}
+
+/// A curve made up of at least two points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineString {
+    /// The points of the curve.
+    points: Vec<Point>,
+}
+
+impl LineString {
+    /// Creates a line string. Returns an error if fewer than two
+    /// points are given.
+    pub fn new(points: Vec<Point>) -> Result<Self> {
+        if points.len() >= 2 {
+            Ok(LineString { points })
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidGeometry,
+                format!("a LineString needs at least 2 points, got {}", points.len())
+            ))
+        }
+    }
+
+    /// Returns the points of the curve.
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+}
+
+/// A polygon, consisting of an exterior ring and optionally some holes.
+/// Each ring is closed, i.e. its first and last points are identical.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    /// The rings; the first one is the exterior, the rest are holes.
+    rings: Vec<Vec<Point>>,
+}
+
+impl Polygon {
+    /// Creates a polygon without holes. If the ring is not closed, it is
+    /// closed automatically by appending its first point to the end.
+    /// Returns an error if the ring has fewer than three distinct points.
+    pub fn new(exterior: Vec<Point>) -> Result<Self> {
+        Self::with_holes(exterior, Vec::new())
+    }
+
+    /// Creates a polygon with holes. Each ring is closed automatically
+    /// if necessary, as in `Polygon::new()`.
+    pub fn with_holes(exterior: Vec<Point>, holes: Vec<Vec<Point>>) -> Result<Self> {
+        let rings = Some(exterior)
+            .into_iter()
+            .chain(holes)
+            .map(close_ring)
+            .collect::<Result<_>>()?;
+
+        Ok(Polygon { rings })
+    }
+
+    /// Returns the exterior ring of the polygon.
+    pub fn exterior(&self) -> &[Point] {
+        &self.rings[0]
+    }
+
+    /// Returns the interior rings (holes) of the polygon.
+    pub fn holes(&self) -> &[Vec<Point>] {
+        &self.rings[1..]
+    }
+}
+
+/// Closes a linear ring if necessary, then ensures that it has at least
+/// four positions, as required by GeoJSON.
+#[allow(clippy::float_cmp)]
+fn close_ring(mut ring: Vec<Point>) -> Result<Vec<Point>> {
+    match (ring.first().cloned(), ring.last().cloned()) {
+        (Some(first), Some(last)) if first != last => ring.push(first),
+        _ => {}
+    }
+
+    if ring.len() >= 4 {
+        Ok(ring)
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidGeometry,
+            format!("a linear ring needs at least 4 positions, got {}", ring.len())
+        ))
+    }
+}
+
+/// A collection of polygons.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MultiPolygon {
+    /// The constituent polygons.
+    polygons: Vec<Polygon>,
+}
+
+impl MultiPolygon {
+    /// Creates a multi-polygon out of individual polygons.
+    pub fn new(polygons: Vec<Polygon>) -> Self {
+        MultiPolygon { polygons }
+    }
+
+    /// Returns the constituent polygons.
+    pub fn polygons(&self) -> &[Polygon] {
+        &self.polygons
+    }
+}
+
+/// Any of the supported GeoJSON geometries. Useful as the argument
+/// of `$geoWithin` or `$geoIntersects`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Geometry {
+    /// A single point.
+    Point(Point),
+    /// A curve.
+    LineString(LineString),
+    /// A polygon, possibly with holes.
+    Polygon(Polygon),
+    /// A collection of polygons.
+    MultiPolygon(MultiPolygon),
+}
+
+/// Describes how a geometry is represented as a GeoJSON object.
+trait GeoJson: Sized {
+    /// The value of the `type` field.
+    const TYPE: &'static str;
+
+    /// The type of the `coordinates` field.
+    type Coordinates: Serialize + for<'a> Deserialize<'a>;
+
+    /// Returns the coordinates of the geometry.
+    fn coordinates(&self) -> Self::Coordinates;
+
+    /// Creates and validates the geometry from its coordinates.
+    fn from_coordinates(coordinates: Self::Coordinates) -> Result<Self>;
+}
+
+impl GeoJson for Point {
+    const TYPE: &'static str = "Point";
+
+    type Coordinates = [f64; 2];
+
+    fn coordinates(&self) -> Self::Coordinates {
+        self.position()
+    }
+
+    fn from_coordinates(coordinates: Self::Coordinates) -> Result<Self> {
+        Ok(Point::from_position(coordinates))
+    }
+}
+
+impl GeoJson for LineString {
+    const TYPE: &'static str = "LineString";
+
+    type Coordinates = Vec<[f64; 2]>;
+
+    fn coordinates(&self) -> Self::Coordinates {
+        self.points.iter().cloned().map(Point::position).collect()
+    }
+
+    fn from_coordinates(coordinates: Self::Coordinates) -> Result<Self> {
+        LineString::new(coordinates.into_iter().map(Point::from_position).collect())
+    }
+}
+
+impl GeoJson for Polygon {
+    const TYPE: &'static str = "Polygon";
+
+    type Coordinates = Vec<Vec<[f64; 2]>>;
+
+    fn coordinates(&self) -> Self::Coordinates {
+        self.rings
+            .iter()
+            .map(|ring| ring.iter().cloned().map(Point::position).collect())
+            .collect()
+    }
+
+    fn from_coordinates(coordinates: Self::Coordinates) -> Result<Self> {
+        let mut rings = coordinates.into_iter().map(
+            |ring| ring.into_iter().map(Point::from_position).collect()
+        );
+        let exterior = rings.next().ok_or_else(|| Error::new(
+            ErrorKind::InvalidGeometry, "a Polygon needs an exterior ring"
+        ))?;
+
+        Polygon::with_holes(exterior, rings.collect())
+    }
+}
+
+impl GeoJson for MultiPolygon {
+    const TYPE: &'static str = "MultiPolygon";
+
+    type Coordinates = Vec<Vec<Vec<[f64; 2]>>>;
+
+    fn coordinates(&self) -> Self::Coordinates {
+        self.polygons.iter().map(GeoJson::coordinates).collect()
+    }
+
+    fn from_coordinates(coordinates: Self::Coordinates) -> Result<Self> {
+        coordinates
+            .into_iter()
+            .map(Polygon::from_coordinates)
+            .collect::<Result<_>>()
+            .map(MultiPolygon::new)
+    }
+}
+
+/// The raw GeoJSON representation of a geometry, used for deserialization.
+#[derive(Deserialize)]
+struct RawGeoJson<C> {
+    /// The name of the geometry type.
+    #[serde(rename = "type")]
+    kind: String,
+    /// The coordinates of the geometry, in a type-dependent structure.
+    coordinates: C,
+}
+
+/// Implementing `Serialize`, `Deserialize` and `From<_> for Bson` for
+/// GeoJSON geometry types.
+macro_rules! impl_geojson_serde {
+    ($($ty:ident),*) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+                let mut state = serializer.serialize_struct(stringify!($ty), 2)?;
+                state.serialize_field("type", Self::TYPE)?;
+                state.serialize_field("coordinates", &self.coordinates())?;
+                state.end()
+            }
+        }
+
+        impl<'a> Deserialize<'a> for $ty {
+            fn deserialize<D: Deserializer<'a>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+                let raw = RawGeoJson::deserialize(deserializer)?;
+
+                if raw.kind == Self::TYPE {
+                    Self::from_coordinates(raw.coordinates).map_err(D::Error::custom)
+                } else {
+                    Err(D::Error::custom(format!(
+                        "expected GeoJSON type `{}`, found `{}`", Self::TYPE, raw.kind
+                    )))
+                }
+            }
+        }
+
+        /// See the explanation for `BsonType` as to why this impl is possible.
+        impl From<$ty> for Bson {
+            fn from(geometry: $ty) -> Self {
+                to_bson(&geometry).unwrap_or_default()
+            }
+        }
+
+        impl From<$ty> for Geometry {
+            fn from(geometry: $ty) -> Self {
+                Geometry::$ty(geometry)
+            }
+        }
+    )*}
+}
+
+impl_geojson_serde! { Point, LineString, Polygon, MultiPolygon }
+
+/// See the explanation for `BsonType` as to why this impl is possible.
+impl From<Geometry> for Bson {
+    fn from(geometry: Geometry) -> Self {
+        to_bson(&geometry).unwrap_or_default()
+    }
+}
+
+/// The `$near` and `$nearSphere` query operators: matches documents
+/// in the order of their proximity to a point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Near {
+    /// The point around which documents are sought.
+    center: Point,
+    /// Minimal distance from the center, in meters.
+    min_distance: Option<f64>,
+    /// Maximal distance from the center, in meters.
+    max_distance: Option<f64>,
+    /// Whether to use `$nearSphere` instead of `$near`.
+    spherical: bool,
+}
+
+impl Near {
+    /// Creates a `$near` query around the given point.
+    pub fn new(center: Point) -> Self {
+        Near {
+            center,
+            min_distance: None,
+            max_distance: None,
+            spherical: false,
+        }
+    }
+
+    /// Creates a `$nearSphere` query around the given point.
+    pub fn sphere(center: Point) -> Self {
+        Near { spherical: true, ..Near::new(center) }
+    }
+
+    /// Only matches documents at least this far (in meters) from the center.
+    pub fn min_distance(self, meters: f64) -> Self {
+        Near { min_distance: Some(meters), ..self }
+    }
+
+    /// Only matches documents at most this far (in meters) from the center.
+    pub fn max_distance(self, meters: f64) -> Self {
+        Near { max_distance: Some(meters), ..self }
+    }
+}
+
+impl From<Near> for Bson {
+    fn from(near: Near) -> Self {
+        let mut spec = doc!{ "$geometry": near.center };
+
+        if let Some(meters) = near.min_distance {
+            spec.insert("$minDistance", meters);
+        }
+        if let Some(meters) = near.max_distance {
+            spec.insert("$maxDistance", meters);
+        }
+
+        let mut operator = Document::new();
+        operator.insert(if near.spherical { "$nearSphere" } else { "$near" }, spec);
+        operator.into()
+    }
+}
+
+/// The `$geoWithin` query operator: matches documents with a geometry
+/// entirely contained by the given shape.
+/// ```
+/// # #[macro_use]
+/// # extern crate bson;
+/// # extern crate avocado;
+/// #
+/// # use avocado::geo::{ Point, Polygon, GeoWithin };
+/// # use avocado::error::Result;
+/// #
+/// # fn main() -> Result<()> {
+/// let triangle = Polygon::new(vec![
+///     Point::new(0.0, 0.0),
+///     Point::new(3.0, 6.0),
+///     Point::new(6.0, 1.0),
+/// ])?;
+/// let filter = doc!{ "location": GeoWithin::from(triangle) };
+///
+/// assert_eq!(filter, doc!{
+///     "location": {
+///         "$geoWithin": {
+///             "$geometry": {
+///                 "type": "Polygon",
+///                 "coordinates": [[[0.0, 0.0], [3.0, 6.0], [6.0, 1.0], [0.0, 0.0]]],
+///             }
+///         }
+///     }
+/// });
+/// # Ok(())
+/// # }
+/// ```
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoWithin {
+    /// Within a GeoJSON polygon or multi-polygon.
+    Geometry(Geometry),
+    /// Within a circle on the sphere, given by its center and its radius
+    /// **in radians**.
+    CenterSphere(Point, f64),
+}
+
+impl<G: Into<Geometry>> From<G> for GeoWithin {
+    fn from(geometry: G) -> Self {
+        GeoWithin::Geometry(geometry.into())
+    }
+}
+
+impl From<GeoWithin> for Bson {
+    fn from(within: GeoWithin) -> Self {
+        let spec = match within {
+            GeoWithin::Geometry(geometry) => doc!{ "$geometry": geometry },
+            GeoWithin::CenterSphere(center, radius) => doc!{
+                "$centerSphere": [ center.position().to_vec(), radius ]
+            },
+        };
+
+        bson!({ "$geoWithin": spec })
+    }
+}
+
+/// The `$geoIntersects` query operator: matches documents with a geometry
+/// that intersects the given one.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoIntersects(pub Geometry);
+
+impl<G: Into<Geometry>> From<G> for GeoIntersects {
+    fn from(geometry: G) -> Self {
+        GeoIntersects(geometry.into())
+    }
+}
+
+impl From<GeoIntersects> for Bson {
+    fn from(intersects: GeoIntersects) -> Self {
+        bson!({
+            "$geoIntersects": {
+                "$geometry": intersects.0
+            }
+        })
+    }
+}
+
+/// The name of the field holding the computed distance in the output of
+/// the `$geoNear` stage.
+const DISTANCE_FIELD: &str = "avocado_geo_distance";
+
+/// A `$geoNear` aggregation: returns documents ordered by their distance
+/// from a point, along with the distance itself, as `WithDistance<T>`.
+///
+/// The collection must have exactly one geospatial index, or the indexed
+/// field must be selected explicitly using `key()`.
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// # #[macro_use]
+/// # extern crate avocado_derive;
+/// # extern crate avocado;
+/// #
+/// # use avocado::prelude::*;
+/// # use avocado::geo::{ Point, GeoNear };
+/// #
+/// #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+/// #[index(keys(location = "2dsphere"))]
+/// struct Cafe {
+///     #[serde(rename = "_id")]
+///     id: Uid<Cafe>,
+///     name: String,
+///     location: Point,
+/// }
+///
+/// # fn main() -> AvocadoResult<()> {
+/// # let client = Client::with_uri("mongodb://localhost:27017/")?;
+/// # let db = client.db("avocado_example_db");
+/// let cafes: Collection<Cafe> = db.existing_collection();
+/// let here = Point::new(19.04, 47.49);
+///
+/// for result in cafes.aggregate(GeoNear::new(here).max_distance(500.0).limit(10))? {
+///     let cafe = result?;
+///     println!("{} is {} meters away", cafe.value.name, cafe.distance);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoNear {
+    /// The point around which documents are sought.
+    near: Point,
+    /// Whether to use spherical geometry.
+    spherical: bool,
+    /// Minimal distance from the center.
+    min_distance: Option<f64>,
+    /// Maximal distance from the center.
+    max_distance: Option<f64>,
+    /// Multiplier applied to all returned distances.
+    distance_multiplier: Option<f64>,
+    /// Additional filter on the documents.
+    query: Option<Document>,
+    /// The geospatially-indexed field to use.
+    key: Option<String>,
+    /// Maximal number of documents to return.
+    limit: Option<usize>,
+}
+
+impl GeoNear {
+    /// Creates a spherical `$geoNear` aggregation around the given point.
+    pub fn new(near: Point) -> Self {
+        GeoNear {
+            near,
+            spherical: true,
+            min_distance: None,
+            max_distance: None,
+            distance_multiplier: None,
+            query: None,
+            key: None,
+            limit: None,
+        }
+    }
+
+    /// Uses planar instead of spherical geometry, for `2d` indexes.
+    pub fn planar(self) -> Self {
+        GeoNear { spherical: false, ..self }
+    }
+
+    /// Only returns documents at least this far from the point.
+    pub fn min_distance(self, distance: f64) -> Self {
+        GeoNear { min_distance: Some(distance), ..self }
+    }
+
+    /// Only returns documents at most this far from the point.
+    pub fn max_distance(self, distance: f64) -> Self {
+        GeoNear { max_distance: Some(distance), ..self }
+    }
+
+    /// Multiplies every returned distance by this factor, e.g. for
+    /// converting radians to kilometers.
+    pub fn distance_multiplier(self, factor: f64) -> Self {
+        GeoNear { distance_multiplier: Some(factor), ..self }
+    }
+
+    /// Only returns documents matching this filter as well.
+    pub fn query(self, filter: Document) -> Self {
+        GeoNear { query: Some(filter), ..self }
+    }
+
+    /// Selects the geospatially-indexed field to compute distances for.
+    pub fn key<S: Into<String>>(self, field: S) -> Self {
+        GeoNear { key: Some(field.into()), ..self }
+    }
+
+    /// Returns at most this many documents.
+    pub fn limit(self, n: usize) -> Self {
+        GeoNear { limit: Some(n), ..self }
+    }
+
+    /// Returns the raw `$geoNear` stage, for use in custom pipelines.
+    /// The computed distance is put in the field `distance_field`.
+    pub fn stage(&self, distance_field: &str) -> Document {
+        let mut spec = doc!{
+            "near": self.near,
+            "distanceField": distance_field,
+            "spherical": self.spherical,
+        };
+
+        if let Some(distance) = self.min_distance {
+            spec.insert("minDistance", distance);
+        }
+        if let Some(distance) = self.max_distance {
+            spec.insert("maxDistance", distance);
+        }
+        if let Some(factor) = self.distance_multiplier {
+            spec.insert("distanceMultiplier", factor);
+        }
+        if let Some(ref filter) = self.query {
+            spec.insert("query", filter.clone());
+        }
+        if let Some(ref key) = self.key {
+            spec.insert("key", key.as_str());
+        }
+
+        doc!{ "$geoNear": spec }
+    }
+}
+
+impl<T: Doc> Pipeline<T> for GeoNear {
+    type Output = WithDistance<T>;
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn stages(&self) -> Vec<Document> {
+        let mut stages = vec![self.stage(DISTANCE_FIELD)];
+
+        if let Some(n) = self.limit {
+            stages.push(doc!{ "$limit": n as i64 });
+        }
+
+        stages
+    }
+
+    fn transform(mut raw: Document) -> Result<Bson> {
+        let distance = raw.remove_number(DISTANCE_FIELD)?;
+
+        Ok(bson!({
+            "distance": distance,
+            "value": raw,
+        }))
+    }
+}
+
+/// A value along with its distance from the center of a `GeoNear` query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WithDistance<T> {
+    /// The distance of the document from the center.
+    pub distance: f64,
+    /// The document itself.
+    pub value: T,
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{ Bson, from_bson, to_bson };
+    use crate::error::{ ErrorExt, ErrorKind, Result };
+    use super::*;
+
+    #[test]
+    fn geometries_round_trip() -> Result<()> {
+        let point = Point::new(-73.97, 40.77);
+        let line = LineString::new(vec![point, Point::new(-73.95, 40.80)])?;
+        let polygon = Polygon::with_holes(
+            vec![
+                Point::new(0.0, 0.0),
+                Point::new(10.0, 0.0),
+                Point::new(10.0, 10.0),
+                Point::new(0.0, 10.0),
+            ],
+            vec![
+                vec![
+                    Point::new(2.0, 2.0),
+                    Point::new(3.0, 2.0),
+                    Point::new(3.0, 3.0),
+                ],
+            ],
+        )?;
+        let multi = MultiPolygon::new(vec![polygon.clone()]);
+
+        assert_eq!(to_bson(&point)?, bson!({
+            "type": "Point",
+            "coordinates": [-73.97, 40.77],
+        }));
+        assert_eq!(to_bson(&line)?, bson!({
+            "type": "LineString",
+            "coordinates": [[-73.97, 40.77], [-73.95, 40.80]],
+        }));
+        assert_eq!(polygon.exterior().len(), 5);
+        assert_eq!(polygon.holes()[0].len(), 4);
+
+        assert_eq!(from_bson::<Point>(to_bson(&point)?)?, point);
+        assert_eq!(from_bson::<LineString>(to_bson(&line)?)?, line);
+        assert_eq!(from_bson::<Polygon>(to_bson(&polygon)?)?, polygon);
+        assert_eq!(from_bson::<MultiPolygon>(to_bson(&multi)?)?, multi);
+        assert_eq!(from_bson::<Geometry>(to_bson(&polygon)?)?,
+                   Geometry::Polygon(polygon));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_geometries_rejected() {
+        assert_eq!(LineString::new(vec![Point::default()]).unwrap_err().kind(),
+                   ErrorKind::InvalidGeometry);
+        assert_eq!(Polygon::new(vec![Point::new(0.0, 0.0), Point::new(1.0, 1.0)])
+                   .unwrap_err()
+                   .kind(),
+                   ErrorKind::InvalidGeometry);
+
+        let mistyped = bson!({ "type": "LineString", "coordinates": [1.0, 2.0] });
+        let unclosed = bson!({ "type": "Polygon", "coordinates": [[[0.0, 0.0]]] });
+
+        assert!(from_bson::<Point>(mistyped)
+                .unwrap_err()
+                .to_string()
+                .contains("expected GeoJSON type `Point`, found `LineString`"));
+        assert!(from_bson::<Polygon>(unclosed).is_err());
+    }
+
+    #[test]
+    fn geo_near_transform() -> Result<()> {
+        let near = GeoNear::new(Point::new(1.0, 2.0)).max_distance(5.0).limit(3);
+        let stages = <GeoNear as Pipeline<Dummy>>::stages(&near);
+        let raw = doc!{ "_id": 1, "avocado_geo_distance": 4.5 };
+
+        assert_eq!(stages.len(), 2);
+        assert_eq!(stages[1], doc!{ "$limit": 3_i64 });
+        assert_eq!(<GeoNear as Pipeline<Dummy>>::transform(raw)?, bson!({
+            "distance": 4.5,
+            "value": { "_id": 1 },
+        }));
+
+        Ok(())
+    }
+
+    /// A document type just for instantiating generic `GeoNear` methods.
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Dummy {
+        /// The unique ID.
+        _id: crate::uid::Uid<Dummy>,
+    }
+
+    impl Doc for Dummy {
+        type Id = i32;
+
+        const NAME: &'static str = "Dummy";
+
+        fn id(&self) -> Option<&crate::uid::Uid<Self>> {
+            Some(&self._id)
+        }
+
+        fn set_id(&mut self, id: crate::uid::Uid<Self>) {
+            self._id = id;
+        }
+    }
+
+    #[test]
+    fn bson_literal_forms() {
+        let point = Bson::from(Point::new(1.0, 2.0));
+        assert_eq!(point, bson!({ "type": "Point", "coordinates": [1.0, 2.0] }));
+    }
+}
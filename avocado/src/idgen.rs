@@ -0,0 +1,267 @@
+//! Client-side generation of unique IDs.
+//!
+//! An `IdGenerator` produces new raw IDs without a round trip to the
+//! database. When a document type returns IDs from `Doc::generate_id()`,
+//! `Collection::insert_one()` and `insert_many()` use it for filling in the
+//! `_id` of documents in which it's missing (or `null`, e.g. a `None`-valued
+//! `Option<Uid<T>>`). When deriving `Doc`, this is achieved by specifying
+//! the path of the generator type as `#[avocado(id_generator = "...")]`.
+//!
+//! The built-in generators all produce IDs which are ordered by the time of
+//! their creation, which makes them a good fit for B-tree indexes:
+//! * `Ulid` generates [ULIDs](https://github.com/ulid/spec), 26-character
+//!   strings which are monotonically increasing within the process;
+//! * `Snowflake` generates 64-bit integers, made up of a timestamp, a node ID
+//!   and a per-millisecond sequence number, similar to Twitter's Snowflake IDs;
+//! * `UuidV7` generates version 7 UUIDs, with the `raw_uuid` feature.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[id_type = "String"]
+//! #[avocado(id_generator = "avocado::idgen::Ulid")]
+//! struct Order {
+//!     #[serde(rename = "_id")]
+//!     id: Option<Uid<Order>>,
+//!     total: f64,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let orders: Collection<Order> = db.existing_collection();
+//! let id = orders.insert_one(&Order { id: None, total: 9.99 })?;
+//!
+//! assert_eq!(id.as_ref().len(), 26);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::SystemTime;
+use crate::{
+    utils::{ unix_millis, random_u64, lock },
+    error::Result,
+};
+
+#[cfg(feature = "raw_uuid")]
+use uuid::Uuid;
+
+/// Generates unique raw IDs on the client side.
+#[allow(clippy::module_name_repetitions)]
+pub trait IdGenerator {
+    /// The type of the generated IDs. It must be convertible to the raw
+    /// `Doc::Id` type of the documents it's used for.
+    type Id;
+
+    /// Generates a new ID, different from all others previously generated.
+    fn generate() -> Result<Self::Id>;
+}
+
+/// The alphabet of the Crockford Base32 encoding used by ULIDs.
+const CROCKFORD_BASE32: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The number of random bits in a ULID.
+const ULID_RANDOM_BITS: u32 = 80;
+
+/// Generates [ULIDs](https://github.com/ulid/spec): 48 bits of Unix time
+/// in milliseconds, followed by 80 random bits, encoded as 26 characters of
+/// Crockford's Base32. IDs generated in the same millisecond by the same
+/// process are consecutive, so they are strictly increasing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Ulid;
+
+lazy_static! {
+    /// The last ULID generated, as a timestamp and a random part.
+    static ref LAST_ULID: Mutex<(u64, u128)> = Mutex::new((0, 0));
+}
+
+impl IdGenerator for Ulid {
+    type Id = String;
+
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn generate() -> Result<String> {
+        let random_mask = (1_u128 << ULID_RANDOM_BITS) - 1;
+        let mut last = lock(&LAST_ULID);
+        let millis = unix_millis(SystemTime::now()) as u64;
+
+        // If the clock hasn't advanced (or it went backwards), the random
+        // part of the previous ULID is incremented, overflowing into the
+        // timestamp in the (extremely unlikely) case it's all 1s.
+        *last = if millis > last.0 {
+            let random = (u128::from(random_u64()) << 16) ^ u128::from(random_u64());
+            (millis, random & random_mask)
+        } else if last.1 < random_mask {
+            (last.0, last.1 + 1)
+        } else {
+            (last.0 + 1, 0)
+        };
+
+        let value = (u128::from(last.0) << ULID_RANDOM_BITS) | last.1;
+        let encoded = (0..26)
+            .map(|i| char::from(CROCKFORD_BASE32[((value >> (125 - 5 * i)) & 31) as usize]))
+            .collect();
+
+        Ok(encoded)
+    }
+}
+
+/// The start of the timestamps in Snowflake IDs, 2020-01-01T00:00:00Z.
+const SNOWFLAKE_EPOCH_MILLIS: i64 = 1_577_836_800_000;
+
+/// The number of bits of the node ID in a Snowflake ID.
+const SNOWFLAKE_NODE_BITS: u32 = 10;
+
+/// The number of bits of the sequence number in a Snowflake ID.
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+
+/// Generates 64-bit Snowflake IDs: 41 bits of time in milliseconds since
+/// 2020-01-01, followed by a 10-bit node ID and a 12-bit sequence number,
+/// which counts the IDs generated by the node in the same millisecond.
+///
+/// Node IDs must be unique among the processes generating IDs for the same
+/// collection; set it using `Snowflake::set_node_id()` at startup. It's
+/// random by default, which is only good enough for a couple of processes.
+/// If more than 4096 IDs are requested within a millisecond, the timestamp
+/// runs ahead of the clock, so IDs are still strictly increasing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Snowflake;
+
+lazy_static! {
+    /// The node ID of this process.
+    static ref SNOWFLAKE_NODE: AtomicUsize = AtomicUsize::new(random_node_id());
+
+    /// The timestamp and the sequence number of the last Snowflake ID.
+    static ref LAST_SNOWFLAKE: Mutex<(i64, i64)> = Mutex::new((0, 0));
+}
+
+/// Returns a random Snowflake node ID.
+#[allow(clippy::cast_possible_truncation)]
+fn random_node_id() -> usize {
+    (random_u64() as usize) & ((1 << SNOWFLAKE_NODE_BITS) - 1)
+}
+
+impl Snowflake {
+    /// Sets the node ID of this process. Only the lowest 10 bits are used.
+    pub fn set_node_id(node: u16) {
+        let mask = (1 << SNOWFLAKE_NODE_BITS) - 1;
+        SNOWFLAKE_NODE.store(usize::from(node) & mask, Ordering::SeqCst);
+    }
+
+    /// Returns the node ID of this process.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn node_id() -> u16 {
+        SNOWFLAKE_NODE.load(Ordering::SeqCst) as u16
+    }
+}
+
+impl IdGenerator for Snowflake {
+    type Id = i64;
+
+    fn generate() -> Result<i64> {
+        let sequence_mask = (1 << SNOWFLAKE_SEQUENCE_BITS) - 1;
+        let mut last = lock(&LAST_SNOWFLAKE);
+        let millis = unix_millis(SystemTime::now()) - SNOWFLAKE_EPOCH_MILLIS;
+
+        *last = if millis > last.0 {
+            (millis, 0)
+        } else if last.1 < sequence_mask {
+            (last.0, last.1 + 1)
+        } else {
+            (last.0 + 1, 0)
+        };
+
+        let node = i64::from(Self::node_id());
+        let shift = SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS;
+
+        Ok((last.0 << shift) | (node << SNOWFLAKE_SEQUENCE_BITS) | last.1)
+    }
+}
+
+/// Generates version 7 UUIDs: 48 bits of Unix time in milliseconds,
+/// followed by the version, 74 random bits, and the variant.
+#[cfg(feature = "raw_uuid")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct UuidV7;
+
+#[cfg(feature = "raw_uuid")]
+impl IdGenerator for UuidV7 {
+    type Id = Uuid;
+
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+    fn generate() -> Result<Uuid> {
+        // A v4 UUID already has the right variant and 122 random bits.
+        let mut bytes = *Uuid::new_v4().as_bytes();
+        let millis = unix_millis(SystemTime::now()) as u64;
+
+        for (i, byte) in bytes.iter_mut().take(6).enumerate() {
+            *byte = (millis >> (40 - 8 * i)) as u8;
+        }
+
+        bytes[6] = 0x70 | (bytes[6] & 0x0f);
+
+        Ok(Uuid::from_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ulid_format_and_order() -> Result<()> {
+        let ids = (0..1000).map(|_| Ulid::generate()).collect::<Result<Vec<_>>>()?;
+
+        for id in &ids {
+            assert_eq!(id.len(), 26);
+            assert!(id.bytes().all(|c| CROCKFORD_BASE32.contains(&c)));
+            // The first character only encodes the top 3 bits.
+            assert!(id.as_bytes()[0] <= b'7');
+        }
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn snowflake_layout_and_order() -> Result<()> {
+        Snowflake::set_node_id(0x7ab);
+        assert_eq!(Snowflake::node_id(), 0x3ab);
+
+        let ids = (0..10_000).map(|_| Snowflake::generate()).collect::<Result<Vec<_>>>()?;
+
+        for &id in &ids {
+            assert!(id > 0);
+            assert_eq!((id >> SNOWFLAKE_SEQUENCE_BITS) & 0x3ff, 0x3ab);
+        }
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "raw_uuid")]
+    #[test]
+    fn uuid_v7_layout() -> Result<()> {
+        let before = unix_millis(SystemTime::now());
+        let id = UuidV7::generate()?;
+        let after = unix_millis(SystemTime::now());
+        let bytes = id.as_bytes();
+        let millis = bytes[..6].iter().fold(0, |acc, &byte| (acc << 8) | i64::from(byte));
+
+        assert_eq!(id.get_version_num(), 7);
+        assert_eq!(bytes[8] & 0xc0, 0x80);
+        assert!(before <= millis && millis <= after);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+//! Hooks for observing every operation performed on a `Collection`.
+//!
+//! An `Instrument` receives an `Event` after each operation, describing
+//! the operation, the collection, the filter, update and other documents
+//! (e.g. an aggregation pipeline or the inserted documents), the time it took, the number of affected documents, and the error,
+//! if any. It can be installed for a single collection handle using
+//! `Collection::with_instrument()`, or globally using `set_global()`;
+//! the former takes precedence.
+//!
+//! Ready-made instruments are provided for the `log` and `tracing`
+//! crates, behind the features of the same name.
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use std::sync::Arc;
+//! # use std::time::Duration;
+//! # use avocado::prelude::*;
+//! # use avocado::instrument::{ self, Instrument, Event };
+//! #
+//! # #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! # struct Order {
+//! #     _id: Uid<Order>,
+//! # }
+//! #
+//! /// Reports operations that took longer than 100 milliseconds.
+//! struct SlowOps;
+//!
+//! impl Instrument for SlowOps {
+//!     fn redacts_documents(&self) -> bool {
+//!         true
+//!     }
+//!
+//!     fn record(&self, event: &Event) {
+//!         if event.duration > Duration::from_millis(100) {
+//!             eprintln!("slow {}::{}: {:?}", event.collection, event.operation, event.filter);
+//!         }
+//!     }
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! instrument::set_global(Arc::new(SlowOps));
+//!
+//! let orders: Collection<Order> = db.existing_collection();
+//! let pending = orders.count(doc!{ "status": "pending" })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::{ Arc, RwLock };
+use std::time::{ Duration, Instant };
+use bson::{ Bson, Document };
+use crate::error::{ Error, Result };
+
+/// Receives a description of every operation performed on a collection.
+pub trait Instrument: Send + Sync {
+    /// Whether the values in the reported documents should be replaced
+    /// by a placeholder before being passed to `record()`, e.g. because they
+    /// may contain personal data. Keys and operators are kept intact.
+    /// Defaults to `false`.
+    fn redacts_documents(&self) -> bool {
+        false
+    }
+
+    /// Called after an operation finished, either successfully or not.
+    fn record(&self, event: &Event);
+}
+
+/// Describes a single operation performed on a collection.
+#[derive(Debug, Clone)]
+pub struct Event<'a> {
+    /// The name of the `Collection` method, e.g. `"find_one"`.
+    pub operation: &'static str,
+    /// The name of the collection, i.e. `T::NAME`.
+    pub collection: &'static str,
+    /// The filter of the operation, if it has one.
+    pub filter: Option<Document>,
+    /// The update, upsert or replacement document, if the operation has one.
+    pub update: Option<Document>,
+    /// The other documents sent by the operation: the stages of an
+    /// aggregation pipeline, the inserted documents, the keys of the
+    /// created indexes, or the explained command.
+    pub documents: Vec<Document>,
+    /// The wall-clock time the operation took, including retries.
+    pub duration: Duration,
+    /// The number of documents affected by the operation, as far as known.
+    pub counts: Counts,
+    /// The error, if the operation failed.
+    pub error: Option<&'a Error>,
+}
+
+/// The number of documents affected by an operation. A count is `None`
+/// if it's not known or doesn't make sense for the operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Counts {
+    /// The number of documents matched by the filter.
+    pub matched: Option<usize>,
+    /// The number of documents modified by an update.
+    pub modified: Option<usize>,
+    /// The number of documents inserted, including upserts.
+    pub inserted: Option<usize>,
+    /// The number of documents deleted.
+    pub deleted: Option<usize>,
+}
+
+impl Counts {
+    /// Only the number of matched documents is known.
+    pub fn matched(n: usize) -> Self {
+        Counts { matched: Some(n), ..Counts::default() }
+    }
+
+    /// The number of matched and modified documents is known.
+    pub fn updated(matched: usize, modified: usize) -> Self {
+        Counts {
+            matched: Some(matched),
+            modified: Some(modified),
+            ..Counts::default()
+        }
+    }
+
+    /// Only the number of inserted documents is known.
+    pub fn inserted(n: usize) -> Self {
+        Counts { inserted: Some(n), ..Counts::default() }
+    }
+
+    /// Only the number of deleted documents is known.
+    pub fn deleted(n: usize) -> Self {
+        Counts { deleted: Some(n), ..Counts::default() }
+    }
+}
+
+/// The documents sent by an operation, as reported in an `Event`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Documents {
+    /// The filter of the operation, if it has one.
+    pub filter: Option<Document>,
+    /// The update, upsert or replacement document, if any.
+    pub update: Option<Document>,
+    /// Any other documents, see `Event::documents`.
+    pub others: Vec<Document>,
+}
+
+impl Documents {
+    /// Only other documents, without a filter or an update.
+    pub fn others(others: Vec<Document>) -> Self {
+        Documents { others, ..Documents::default() }
+    }
+}
+
+impl From<(Option<Document>, Option<Document>)> for Documents {
+    fn from((filter, update): (Option<Document>, Option<Document>)) -> Self {
+        Documents { filter, update, others: Vec::new() }
+    }
+}
+
+lazy_static! {
+    /// The instrument used by collections without their own.
+    static ref GLOBAL: RwLock<Option<Arc<dyn Instrument>>> = RwLock::new(None);
+}
+
+/// Installs an instrument for every collection that doesn't have its own.
+/// Returns the previously installed global instrument, if any.
+pub fn set_global(instrument: Arc<dyn Instrument>) -> Option<Arc<dyn Instrument>> {
+    replace_global(Some(instrument))
+}
+
+/// Removes the global instrument, returning it if there was one.
+pub fn clear_global() -> Option<Arc<dyn Instrument>> {
+    replace_global(None)
+}
+
+/// Returns the currently installed global instrument, if any.
+pub fn global() -> Option<Arc<dyn Instrument>> {
+    match GLOBAL.read() {
+        Ok(guard) => guard.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// Swaps the global instrument for another one.
+fn replace_global(instrument: Option<Arc<dyn Instrument>>) -> Option<Arc<dyn Instrument>> {
+    // The lock only guards a pointer, which can't be left in an
+    // inconsistent state by a panicking thread, so ignore poisoning.
+    let mut guard = match GLOBAL.write() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    std::mem::replace(&mut *guard, instrument)
+}
+
+/// Replaces every value in the document by a placeholder, recursively,
+/// keeping only the keys (field names and operators).
+/// ```
+/// # #[macro_use]
+/// # extern crate bson;
+/// # extern crate avocado;
+/// #
+/// # use avocado::instrument::redact;
+/// #
+/// # fn main() {
+/// let filter = doc!{ "email": "alice@example.com", "age": { "$gte": 18 } };
+/// assert_eq!(redact(&filter), doc!{ "email": "?", "age": { "$gte": "?" } });
+/// # }
+/// ```
+pub fn redact(document: &Document) -> Document {
+    document
+        .iter()
+        .map(|(key, value)| (key.clone(), redact_value(value)))
+        .collect()
+}
+
+/// Helper for `redact()`.
+fn redact_value(value: &Bson) -> Bson {
+    match *value {
+        Bson::Document(ref document) => Bson::Document(redact(document)),
+        Bson::Array(ref array) => Bson::Array(array.iter().map(redact_value).collect()),
+        _ => Bson::from("?"),
+    }
+}
+
+/// Runs `op`, measures how long it takes, and reports it to `instrument`.
+/// The documents and the counts are only computed if there's an instrument.
+pub(crate) fn observe<R, D, X, C, F>(
+    instrument: Option<Arc<dyn Instrument>>,
+    operation: &'static str,
+    collection: &'static str,
+    documents: D,
+    counts: C,
+    op: F,
+) -> Result<R>
+    where D: FnOnce() -> X,
+          X: Into<Documents>,
+          C: FnOnce(&R) -> Counts,
+          F: FnOnce() -> Result<R>,
+{
+    let hook = match instrument {
+        Some(hook) => hook,
+        None => return op(),
+    };
+
+    let start = Instant::now();
+    let result = op();
+    let duration = start.elapsed();
+
+    let Documents { mut filter, mut update, others: mut documents } = documents().into();
+
+    if hook.redacts_documents() {
+        filter = filter.as_ref().map(redact);
+        update = update.as_ref().map(redact);
+        documents = documents.iter().map(redact).collect();
+    }
+
+    let (counts, error) = match result {
+        Ok(ref value) => (counts(value), None),
+        Err(ref error) => (Counts::default(), Some(error)),
+    };
+
+    hook.record(&Event {
+        operation,
+        collection,
+        filter,
+        update,
+        documents,
+        duration,
+        counts,
+        error,
+    });
+
+    result
+}
+
+/// Formats an optional document for the logging adapters.
+#[cfg(any(feature = "log", feature = "tracing"))]
+fn display_document(document: &Option<Document>) -> String {
+    document.as_ref().map_or_else(String::new, ToString::to_string)
+}
+
+/// Formats the other documents of an event for the logging adapters.
+#[cfg(any(feature = "log", feature = "tracing"))]
+fn display_documents(documents: &[Document]) -> String {
+    let documents: Vec<_> = documents.iter().map(ToString::to_string).collect();
+    format!("[{}]", documents.join(", "))
+}
+
+/// An instrument that logs every operation using the `log` crate, with
+/// the target `avocado`. Failed operations are logged at the `Error` level.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy)]
+pub struct Logger {
+    /// The level at which successful operations are logged.
+    level: log::Level,
+    /// Whether documents are redacted.
+    redact: bool,
+}
+
+#[cfg(feature = "log")]
+impl Logger {
+    /// Creates a logger which logs successful operations at the given level.
+    pub fn new(level: log::Level) -> Self {
+        Logger { level, redact: false }
+    }
+
+    /// Makes the logger redact filter and update documents.
+    pub fn redacted(self) -> Self {
+        Logger { redact: true, ..self }
+    }
+}
+
+#[cfg(feature = "log")]
+impl Default for Logger {
+    fn default() -> Self {
+        Logger::new(log::Level::Debug)
+    }
+}
+
+#[cfg(feature = "log")]
+impl Instrument for Logger {
+    fn redacts_documents(&self) -> bool {
+        self.redact
+    }
+
+    fn record(&self, event: &Event) {
+        let level = if event.error.is_some() { log::Level::Error } else { self.level };
+
+        log::log!(
+            target: "avocado",
+            level,
+            "{}::{} took {:?}; filter: {}, update: {}, documents: {}, counts: {:?}{}",
+            event.collection,
+            event.operation,
+            event.duration,
+            display_document(&event.filter),
+            display_document(&event.update),
+            display_documents(&event.documents),
+            event.counts,
+            event.error.map_or_else(String::new, |error| format!(", error: {}", error)),
+        );
+    }
+}
+
+/// An instrument that emits a `tracing` event for every operation, with
+/// the target `avocado`. Failed operations are reported at the `ERROR`
+/// level, successful ones at the `DEBUG` level.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tracer {
+    /// Whether documents are redacted.
+    redact: bool,
+}
+
+#[cfg(feature = "tracing")]
+impl Tracer {
+    /// Creates a tracer that doesn't redact documents.
+    pub fn new() -> Self {
+        Tracer::default()
+    }
+
+    /// Makes the tracer redact filter and update documents.
+    pub fn redacted(self) -> Self {
+        Tracer { redact: true }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Instrument for Tracer {
+    fn redacts_documents(&self) -> bool {
+        self.redact
+    }
+
+    fn record(&self, event: &Event) {
+        let duration_us = event.duration.as_secs() * 1_000_000
+                        + u64::from(event.duration.subsec_micros());
+        let filter = display_document(&event.filter);
+        let update = display_document(&event.update);
+        let documents = display_documents(&event.documents);
+        let Counts { matched, modified, inserted, deleted } = event.counts;
+
+        if let Some(error) = event.error {
+            tracing::error!(
+                target: "avocado",
+                operation = event.operation,
+                collection = event.collection,
+                duration_us,
+                filter = filter.as_str(),
+                update = update.as_str(),
+                documents = documents.as_str(),
+                error = %error,
+                "operation failed"
+            );
+        } else {
+            tracing::debug!(
+                target: "avocado",
+                operation = event.operation,
+                collection = event.collection,
+                duration_us,
+                filter = filter.as_str(),
+                update = update.as_str(),
+                documents = documents.as_str(),
+                matched = ?matched,
+                modified = ?modified,
+                inserted = ?inserted,
+                deleted = ?deleted,
+                "operation finished"
+            );
+        }
+    }
+}
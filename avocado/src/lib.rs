@@ -125,6 +125,8 @@
 //!     and the same holds for `Product`. When deriving `Doc`, it is controlled
 //!     by the `#[id_type = "..."]` attribute on the struct declaration. If you
 //!     don't specify this attribute, the raw ID type will default to `ObjectId`.
+//!     It can also be a struct implementing `Serialize` and `Deserialize`, in
+//!     which case the `_id` is an embedded document, i.e. a compound key.
 //!   * the `NAME` associated constant describes and identifies the collection
 //!     of values of this type.
 //!
@@ -148,6 +150,29 @@
 //! A `#[derive]`d `Doc` trait will only implement those `..._options()` methods
 //! which are specified in the `#[options(fn_name = "path", ...)]` attribute.
 //! The implementation of the other methods will be left in the default state.
+//! The value returned by the specified function is converted into the return
+//! type of the method using `From`, so for instance `update_options` may be
+//! implemented by a function returning either an
+//! [`UpdateOptions`](options/struct.UpdateOptions.html) or a bare `WriteConcern`.
+//!
+//! Further collection-level settings are specified using the `#[avocado(...)]`
+//! attribute. For instance, `#[avocado(fields)]` generates an associated
+//! constant of type [`Field`](field/struct.Field.html) for each serialized
+//! field, named after the field, which can be used as a statically-typed
+//! path, e.g. as a `Distinct` query. `#[avocado(capped(size = 1048576,
+//! max = 1000))]` implements `Doc::capped()`, making the collection a capped
+//! one of at most 1 MiB and 1000 documents (`max` is optional), which can be
+//! followed using `Collection::tail()`. Similarly, `#[avocado(time_series(
+//! time_field = "ts", meta_field = "sensor", granularity = "minutes",
+//! expire_after_seconds = 86400))]` implements `Doc::time_series()`, making
+//! the collection a [time-series](timeseries/index.html) one (all settings
+//! but `time_field` are optional). Finally, `#[avocado(sequence(step = 1,
+//! batch = 100))]` implements `Doc::sequence()`, so that missing IDs are
+//! filled in from a [sequence](sequence/index.html) upon insertion (`step`
+//! and `batch` are optional, and so are the parentheses). Alternatively,
+//! `#[avocado(id_generator = "avocado::idgen::Ulid")]` implements
+//! `Doc::generate_id()` using the given [`IdGenerator`](idgen/index.html),
+//! so that missing IDs are generated on the client side.
 //!
 //! ### Deriving `Doc` with indexes
 //!
@@ -493,6 +518,11 @@
 //! the task of the `transform(raw: Document) -> Result<Bson>` method on these
 //! traits, e.g. [`Query::transform()`](ops/trait.Query.html#method.transform)
 //! or [`Pipeline::transform()`](ops/trait.Pipeline.html#method.transform).
+//! If the transform depends on the value of the query itself, e.g. on the
+//! fields it requested, override the `transformer(&self)` method of the trait
+//! instead, which returns `transform()` by default, boxed. If it returns
+//! `None`, the raw documents are deserialized directly. See
+//! [`BoxedTransform`](cursor/type.BoxedTransform.html) for the details.
 //!
 //! For the quick, painless, and idiomatic implementation of these methods,
 //! the [`DocumentExt`](ext/trait.DocumentExt.html) trait is provided. This
@@ -601,7 +631,10 @@
 //!
 //! Some of the methods returning an error associate extra structured data with
 //! the error object. An example is `Collection::insert_many()` which returns
-//! the IDs of the successfully-inserted documents even if an error occurs.
+//! the IDs of the successfully-inserted documents even if an error occurs,
+//! as well as the reason why each of the other documents was rejected.
+//! (If such partial success is expected, `Collection::insert_many_unordered()`
+//! returns the same information without treating it as an error.)
 //!
 //! The error context, if any, is documented separately for each individual
 //! method that may produce such an augmented error. The existence of the error
@@ -699,19 +732,38 @@ extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
 extern crate backtrace;
+#[macro_use]
+extern crate lazy_static;
 
 #[cfg(feature = "schema_validation")]
 extern crate magnet_schema;
 #[cfg(feature = "raw_uuid")]
 extern crate uuid;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
 pub mod db;
 pub mod coll;
 pub mod cursor;
 pub mod doc;
 pub mod uid;
+pub mod field;
 pub mod ops;
+pub mod options;
 pub mod literal;
+pub mod geo;
+pub mod timeseries;
+pub mod queue;
+pub mod lock;
+pub mod sequence;
+pub mod idgen;
+pub mod text;
+pub mod retry;
+pub mod instrument;
+pub mod explain;
+pub mod coverage;
 pub mod error;
 pub mod ext;
 pub mod prelude;
@@ -0,0 +1,454 @@
+//! Distributed locks with automatic expiry, stored in MongoDB.
+//!
+//! Each lock is a document in the `Locks` collection, identified by the
+//! name of the lock as its `_id`, so that at most one holder can exist at
+//! any time. A lock is only held for a limited time (its TTL) unless it is
+//! renewed, so a crashed holder can't block others forever.
+//!
+//! Since an expired holder might not notice in time that it has lost its
+//! lock (e.g. because of a long GC pause or a network partition), every
+//! acquisition is assigned a *fencing token*, a number which is strictly
+//! greater than that of every earlier acquisition of the same lock. When
+//! the protected resource is written, passing the token along allows the
+//! resource to reject writes from holders that have been superseded.
+//!
+//! Whether a lock has expired is always decided using the clock of the
+//! database server, so the clocks of the holders don't need to agree; this
+//! requires MongoDB 4.2 or later. Call `Collection::<LockRecord>::create_indexes()`
+//! once, in order to have the records of locks that have been expired for
+//! over an hour deleted by a TTL index. The last fencing token of each lock
+//! is kept in the `LockFences` collection instead, which has no TTL index,
+//! so that fencing tokens never restart when a record is deleted.
+//! ```no_run
+//! # extern crate avocado;
+//! #
+//! # use std::time::Duration;
+//! # use avocado::prelude::*;
+//! # use avocado::lock::{ Lock, LockRecord };
+//! #
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let locks: Collection<LockRecord> = db.existing_collection();
+//! let ttl = Duration::from_secs(60);
+//!
+//! if let Some(mut lock) = Lock::acquire(&locks, "nightly-report", ttl, Duration::from_secs(5))? {
+//!     println!("generating report with fencing token {}", lock.fence());
+//!     // ...long-running work, periodically calling:
+//!     lock.renew()?;
+//!     // ...
+//!     lock.release()?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::thread;
+use std::time::{ Duration, SystemTime, Instant, UNIX_EPOCH };
+use bson::{ Bson, Document, UtcDateTime, oid::ObjectId };
+use mongodb::options::{ FindOneAndUpdateOptions, ReturnDocument, IndexModel };
+use crate::{
+    coll::Collection,
+    doc::Doc,
+    uid::Uid,
+    ops::{ Update, Upsert, FindAndUpdate },
+    cursor::BoxedTransform,
+    utils::{ bson_date, duration_millis },
+    error::{ Error, ErrorKind, Result },
+};
+
+/// The time to wait for between two attempts at acquiring a lock.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The time after its expiry that the record of a lock is deleted. It's
+/// measured by the clock of the holder, so it leaves room for clock skew.
+const RECORD_TTL_SECS: i32 = 3600;
+
+/// The record of a lock in the `Locks` collection.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockRecord {
+    /// The name of the lock.
+    #[serde(rename = "_id")]
+    pub name: Uid<LockRecord>,
+    /// Identifies the current (or the last) acquisition of the lock.
+    /// `None` if the lock has never been acquired.
+    pub owner: Option<ObjectId>,
+    /// The fencing token of the current (or the last) acquisition.
+    pub fence: i64,
+    /// The time at which the lock was last acquired or renewed,
+    /// according to the clock of the database server.
+    pub renewed_at: UtcDateTime,
+    /// The number of milliseconds after `renewed_at` that the lock expires.
+    pub ttl_ms: i64,
+    /// The time at which the lock expires unless renewed, according to the
+    /// clock of the holder. Only used for deleting the records of expired
+    /// locks.
+    pub expires_at: UtcDateTime,
+}
+
+impl Doc for LockRecord {
+    type Id = String;
+
+    const NAME: &'static str = "Locks";
+
+    fn id(&self) -> Option<&Uid<Self>> {
+        Some(&self.name)
+    }
+
+    fn set_id(&mut self, id: Uid<Self>) {
+        self.name = id;
+    }
+
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            IndexModel {
+                keys: doc!{ "expires_at": 1 },
+                options: Some(doc!{ "expireAfterSeconds": RECORD_TTL_SECS }),
+            },
+        ]
+    }
+}
+
+/// The last fencing token handed out for a lock, stored in the `LockFences`
+/// collection. Unlike the records of locks, these are never deleted.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockFence {
+    /// The name of the lock.
+    #[serde(rename = "_id")]
+    pub name: Uid<LockFence>,
+    /// The last fencing token handed out.
+    pub fence: i64,
+}
+
+impl Doc for LockFence {
+    type Id = String;
+
+    const NAME: &'static str = "LockFences";
+
+    fn id(&self) -> Option<&Uid<Self>> {
+        Some(&self.name)
+    }
+
+    fn set_id(&mut self, id: Uid<Self>) {
+        self.name = id;
+    }
+}
+
+/// A held lock. It is **not** released automatically when dropped; it
+/// expires at the end of its TTL instead, unless released explicitly.
+#[derive(Debug)]
+pub struct Lock<'a> {
+    /// The collection of lock records.
+    records: &'a Collection<LockRecord>,
+    /// The name of the lock.
+    name: String,
+    /// Identifies this acquisition of the lock.
+    owner: ObjectId,
+    /// The fencing token of this acquisition.
+    fence: i64,
+    /// How long the lock is held for after acquiring or renewing it.
+    ttl: Duration,
+    /// The time at which the lock expires unless renewed.
+    expires_at: SystemTime,
+}
+
+impl<'a> Lock<'a> {
+    /// Acquires the lock with the given name for the duration of `ttl`, if
+    /// it is not held by anyone else at the moment. Returns `None` otherwise.
+    pub fn try_acquire(
+        records: &'a Collection<LockRecord>,
+        name: &str,
+        ttl: Duration,
+    ) -> Result<Option<Self>> {
+        let start = SystemTime::now();
+
+        match records.upsert_one(Register { name: name.into() }) {
+            Ok(_) => {}
+            // Someone else has just created the record concurrently.
+            Err(ref error) if error.is_duplicate_key() => {}
+            Err(error) => return Err(error),
+        }
+
+        let take = Take {
+            name: name.into(),
+            owner: ObjectId::new()?,
+            ttl_ms: duration_millis(ttl),
+            expires_at: bson_date(start + ttl),
+        };
+        let owner = take.owner.clone();
+
+        if !records.update_one(take)?.matched {
+            return Ok(None);
+        }
+
+        // The fencing token is only allocated once the lock has been taken,
+        // and only handed out if the lock is still held by then, so tokens
+        // increase in the order of successful acquisitions, even if one of
+        // them is delayed and superseded in between.
+        let fence = next_fence(records, name)?;
+        let stamp = Stamp {
+            filter: doc!{ "_id": name, "owner": owner.clone() },
+            fence,
+        };
+
+        if !records.update_one(stamp)?.matched {
+            return Ok(None);
+        }
+
+        Ok(Some(Lock {
+            records,
+            name: name.into(),
+            owner,
+            fence,
+            ttl,
+            expires_at: start + ttl,
+        }))
+    }
+
+    /// Acquires the lock with the given name for the duration of `ttl`,
+    /// waiting at most `timeout` for its current holder to release it or
+    /// for it to expire. Returns `None` if the lock couldn't be acquired.
+    pub fn acquire(
+        records: &'a Collection<LockRecord>,
+        name: &str,
+        ttl: Duration,
+        timeout: Duration,
+    ) -> Result<Option<Self>> {
+        let start = Instant::now();
+
+        loop {
+            if let Some(lock) = Self::try_acquire(records, name, ttl)? {
+                return Ok(Some(lock));
+            }
+
+            let elapsed = start.elapsed();
+
+            if elapsed >= timeout {
+                return Ok(None);
+            }
+
+            thread::sleep(POLL_INTERVAL.min(timeout - elapsed));
+        }
+    }
+
+    /// Returns the name of the lock.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the fencing token of this acquisition of the lock. It is
+    /// strictly greater than the token of every earlier acquisition.
+    pub fn fence(&self) -> i64 {
+        self.fence
+    }
+
+    /// Returns the time at which the lock expires unless renewed. This is
+    /// measured from before the request that acquired or renewed the lock
+    /// was sent, so the lock never expires earlier on the server.
+    pub fn expires_at(&self) -> SystemTime {
+        self.expires_at
+    }
+
+    /// Returns `true` if the lock has expired, in which case it might
+    /// already be held by someone else.
+    pub fn is_expired(&self) -> bool {
+        SystemTime::now() >= self.expires_at
+    }
+
+    /// Extends the lock by its TTL, counting from now. Returns `false` if
+    /// the lock has expired and has been acquired by someone else since.
+    pub fn renew(&mut self) -> Result<bool> {
+        let start = SystemTime::now();
+        let renewal = Extend {
+            filter: self.filter(),
+            ttl_ms: duration_millis(self.ttl),
+            expires_at: bson_date(start + self.ttl),
+        };
+        let result = self.records.update_one(renewal)?;
+
+        if result.matched {
+            self.expires_at = start + self.ttl;
+        }
+
+        Ok(result.matched)
+    }
+
+    /// Releases the lock, making it available to others immediately.
+    /// Returns `false` if the lock has expired and has been acquired by
+    /// someone else in the meantime.
+    pub fn release(self) -> Result<bool> {
+        let release = Extend {
+            filter: self.filter(),
+            ttl_ms: 0,
+            expires_at: bson_date(SystemTime::now()),
+        };
+
+        self.records.update_one(release).map(|result| result.matched)
+    }
+
+    /// Matches the record of the lock as long as it's held by this acquisition.
+    fn filter(&self) -> Document {
+        doc!{ "_id": self.name.as_str(), "owner": self.owner.clone() }
+    }
+}
+
+/// Allocates the next fencing token of the lock with the given name.
+fn next_fence(records: &Collection<LockRecord>, name: &str) -> Result<i64> {
+    let fences = records.sibling::<LockFence>();
+    let advance = Advance { name };
+    let advanced = match fences.find_one_and_update(advance) {
+        // Someone else has just created the counter concurrently.
+        Err(ref error) if error.is_duplicate_key() => fences.find_one_and_update(advance),
+        result => result,
+    };
+
+    advanced?.map(|counter| counter.fence).ok_or_else(|| Error::new(
+        ErrorKind::MissingDocumentField,
+        format!("fencing token of lock {} not found after upsert", name)
+    ))
+}
+
+/// Creates the record of a lock that is not held, if necessary. The record
+/// may never have existed, or it may have been deleted after it expired.
+#[derive(Debug)]
+struct Register {
+    /// The name of the lock.
+    name: String,
+}
+
+impl Upsert<LockRecord> for Register {
+    fn filter(&self) -> Document {
+        doc!{ "_id": self.name.as_str() }
+    }
+
+    fn upsert(&self) -> Document {
+        doc!{
+            "$setOnInsert": {
+                "owner": Bson::Null,
+                "fence": 0_i64,
+                "renewed_at": bson_date(UNIX_EPOCH),
+                "ttl_ms": 0_i64,
+                "expires_at": bson_date(UNIX_EPOCH),
+            }
+        }
+    }
+}
+
+/// Acquires a lock that has expired, according to the clock of the server.
+#[derive(Debug)]
+struct Take {
+    /// The name of the lock.
+    name: String,
+    /// Identifies the new acquisition.
+    owner: ObjectId,
+    /// The TTL of the new acquisition.
+    ttl_ms: i64,
+    /// The expiry of the new acquisition, according to the local clock.
+    expires_at: Bson,
+}
+
+impl Update<LockRecord> for Take {
+    fn filter(&self) -> Document {
+        doc!{
+            "_id": self.name.as_str(),
+            "$expr": {
+                "$lte": [
+                    { "$add": [ "$renewed_at", "$ttl_ms" ] },
+                    "$$NOW",
+                ]
+            },
+        }
+    }
+
+    fn update(&self) -> Document {
+        doc!{
+            "$set": {
+                "owner": self.owner.clone(),
+                "ttl_ms": self.ttl_ms,
+                "expires_at": self.expires_at.clone(),
+            },
+            "$currentDate": { "renewed_at": true },
+        }
+    }
+}
+
+/// Records the fencing token of an acquisition, as long as it still holds
+/// the lock.
+#[derive(Debug)]
+struct Stamp {
+    /// Matches the lock as long as it's held by the same acquisition.
+    filter: Document,
+    /// The fencing token of the acquisition.
+    fence: i64,
+}
+
+impl Update<LockRecord> for Stamp {
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+
+    fn update(&self) -> Document {
+        doc!{ "$set": { "fence": self.fence } }
+    }
+}
+
+/// Atomically increments the fencing token of a lock, creating its counter
+/// if it doesn't exist yet.
+#[derive(Debug, Clone, Copy)]
+struct Advance<'a> {
+    /// The name of the lock.
+    name: &'a str,
+}
+
+impl<'a> FindAndUpdate<LockFence> for Advance<'a> {
+    type Output = LockFence;
+
+    fn filter(&self) -> Document {
+        doc!{ "_id": self.name }
+    }
+
+    fn update(&self) -> Document {
+        doc!{ "$inc": { "fence": 1_i64 } }
+    }
+
+    fn options(&self) -> FindOneAndUpdateOptions {
+        FindOneAndUpdateOptions {
+            upsert: Some(true),
+            return_document: Some(ReturnDocument::After),
+            ..Default::default()
+        }
+    }
+
+    fn transformer(&self) -> Option<BoxedTransform> {
+        None
+    }
+}
+
+/// Restarts the TTL of a held lock, according to the clock of the server.
+#[derive(Debug)]
+struct Extend {
+    /// Matches the lock as long as it's held by the same acquisition.
+    filter: Document,
+    /// The new TTL, counting from now. 0 releases the lock.
+    ttl_ms: i64,
+    /// The new expiry, according to the local clock.
+    expires_at: Bson,
+}
+
+impl Update<LockRecord> for Extend {
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+
+    fn update(&self) -> Document {
+        doc!{
+            "$set": {
+                "ttl_ms": self.ttl_ms,
+                "expires_at": self.expires_at.clone(),
+            },
+            "$currentDate": { "renewed_at": true },
+        }
+    }
+}
@@ -6,13 +6,15 @@ use bson::{ Bson, Document };
 use mongodb::options::{
     FindOptions,
     CountOptions,
-    WriteConcern,
     DistinctOptions,
     AggregateOptions,
     FindOneAndUpdateOptions,
 };
 use crate::{
     doc::Doc,
+    field::{ Field, DistinctValue },
+    cursor::BoxedTransform,
+    options::{ ReadOptions, UpdateOptions, DeleteOptions },
     error::Result,
 };
 
@@ -28,6 +30,12 @@ pub trait Count<T: Doc>: Debug {
     fn options(&self) -> CountOptions {
         T::count_options()
     }
+
+    /// Read preference and read concern for this query. Defaults to none,
+    /// i.e. those of the collection handle are used.
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions::default()
+    }
 }
 
 /// A query for returning the distinct values of a field.
@@ -35,8 +43,9 @@ pub trait Distinct<T: Doc>: Debug {
     /// The type of the field of which the distinct values will be returned.
     type Output: for<'a> Deserialize<'a>;
 
-    /// The name of the field of which the distinct values will be returned.
-    const FIELD: &'static str;
+    /// The name or dot-separated path of the field of which the distinct
+    /// values will be returned.
+    fn field(&self) -> &str;
 
     /// Optional filter restricting which values are taken into account.
     /// Defaults to no filtering.
@@ -57,6 +66,12 @@ pub trait Distinct<T: Doc>: Debug {
     fn options(&self) -> DistinctOptions {
         T::distinct_options()
     }
+
+    /// Read preference and read concern for this query. Defaults to none,
+    /// i.e. those of the collection handle are used.
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions::default()
+    }
 }
 
 /// An aggregation pipeline.
@@ -76,10 +91,26 @@ pub trait Pipeline<T: Doc>: Debug {
         Ok(raw.into())
     }
 
+    /// Transform applied to each returned raw document, which, unlike
+    /// `transform()`, has access to `self`. See
+    /// [`BoxedTransform`](../cursor/type.BoxedTransform.html) for details.
+    ///
+    /// The default implementation returns `Self::transform()`. Return `None`
+    /// if the raw documents should be deserialized as they are.
+    fn transformer(&self) -> Option<BoxedTransform> {
+        Some(Box::new(Self::transform as fn(Document) -> Result<Bson>))
+    }
+
     /// Options for this pipeline.
     fn options(&self) -> AggregateOptions {
         T::aggregate_options()
     }
+
+    /// Read preference and read concern for this pipeline. Defaults to none,
+    /// i.e. those of the collection handle are used.
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions::default()
+    }
 }
 
 /// A regular query (`find_one()` or `find_many()`) operation.
@@ -103,10 +134,26 @@ pub trait Query<T: Doc>: Debug {
         Ok(raw.into())
     }
 
+    /// Transform applied to each returned raw document, which, unlike
+    /// `transform()`, has access to `self`. See
+    /// [`BoxedTransform`](../cursor/type.BoxedTransform.html) for details.
+    ///
+    /// The default implementation returns `Self::transform()`. Return `None`
+    /// if the raw documents should be deserialized as they are.
+    fn transformer(&self) -> Option<BoxedTransform> {
+        Some(Box::new(Self::transform as fn(Document) -> Result<Bson>))
+    }
+
     /// Options for this query.
     fn options(&self) -> FindOptions {
         T::query_options()
     }
+
+    /// Read preference and read concern for this query. Defaults to none,
+    /// i.e. those of the collection handle are used.
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions::default()
+    }
 }
 
 /// An update (but not an upsert) operation.
@@ -117,8 +164,8 @@ pub trait Update<T: Doc>: Debug {
     /// The update to perform on matching documents.
     fn update(&self) -> Document;
 
-    /// Options for this update operation.
-    fn options(&self) -> WriteConcern {
+    /// Options for this update operation, including the array filters.
+    fn options(&self) -> UpdateOptions {
         T::update_options()
     }
 }
@@ -131,8 +178,8 @@ pub trait Upsert<T: Doc>: Debug {
     /// The upsert to perform on matching documents.
     fn upsert(&self) -> Document;
 
-    /// Options for this upsert operation.
-    fn options(&self) -> WriteConcern {
+    /// Options for this upsert operation, including the array filters.
+    fn options(&self) -> UpdateOptions {
         T::upsert_options()
     }
 }
@@ -143,7 +190,7 @@ pub trait Delete<T: Doc>: Debug {
     fn filter(&self) -> Document;
 
     /// Writing options for this deletion operation.
-    fn options(&self) -> WriteConcern {
+    fn options(&self) -> DeleteOptions {
         T::delete_options()
     }
 }
@@ -170,10 +217,30 @@ pub trait FindAndUpdate<T: Doc>: Debug {
         Ok(raw.into())
     }
 
+    /// Transform applied to the returned raw document, which, unlike
+    /// `transform()`, has access to `self`. See
+    /// [`BoxedTransform`](../cursor/type.BoxedTransform.html) for details.
+    ///
+    /// The default implementation returns `Self::transform()`. Return `None`
+    /// if the raw document should be deserialized as it is.
+    fn transformer(&self) -> Option<BoxedTransform> {
+        Some(Box::new(Self::transform as fn(Document) -> Result<Bson>))
+    }
+
     /// Options for this query-and-update operation.
     fn options(&self) -> FindOneAndUpdateOptions {
         T::find_and_update_options()
     }
+
+    /// Options of the update which `FindOneAndUpdateOptions` can't express:
+    /// array filters, a collation, an index hint, and the bypassing of
+    /// document validation. If set, the write concern takes precedence
+    /// over that of `options()`.
+    ///
+    /// The default implementation sets none of them.
+    fn update_options(&self) -> UpdateOptions {
+        UpdateOptions::default()
+    }
 }
 
 /////////////////////////////////////////////
@@ -192,6 +259,10 @@ impl<T: Doc> Query<T> for Document {
     fn filter(&self) -> Document {
         self.clone()
     }
+
+    fn transformer(&self) -> Option<BoxedTransform> {
+        None
+    }
 }
 
 impl<T: Doc> Delete<T> for Document {
@@ -200,6 +271,30 @@ impl<T: Doc> Delete<T> for Document {
     }
 }
 
+/// A typed field path is a distinct query over all documents, yielding
+/// values of the type of the field, or of its elements if it's an array.
+impl<T: Doc, V: DistinctValue> Distinct<T> for Field<T, V> {
+    type Output = V::Value;
+
+    fn field(&self) -> &str {
+        self.path()
+    }
+}
+
+/// A typed field path paired with a filter document is a distinct query
+/// restricted to the documents matching the filter.
+impl<T: Doc, V: DistinctValue> Distinct<T> for (Field<T, V>, Document) {
+    type Output = V::Value;
+
+    fn field(&self) -> &str {
+        self.0.path()
+    }
+
+    fn filter(&self) -> Document {
+        self.1.clone()
+    }
+}
+
 impl<T: Doc, Q: Count<T>> Count<T> for &Q {
     fn filter(&self) -> Document {
         (**self).filter()
@@ -208,12 +303,18 @@ impl<T: Doc, Q: Count<T>> Count<T> for &Q {
     fn options(&self) -> CountOptions {
         (**self).options()
     }
+
+    fn read_options(&self) -> ReadOptions {
+        (**self).read_options()
+    }
 }
 
 impl<T: Doc, Q: Distinct<T>> Distinct<T> for &Q {
     type Output = Q::Output;
 
-    const FIELD: &'static str = Q::FIELD;
+    fn field(&self) -> &str {
+        (**self).field()
+    }
 
     fn filter(&self) -> Document {
         (**self).filter()
@@ -226,6 +327,10 @@ impl<T: Doc, Q: Distinct<T>> Distinct<T> for &Q {
     fn options(&self) -> DistinctOptions {
         (**self).options()
     }
+
+    fn read_options(&self) -> ReadOptions {
+        (**self).read_options()
+    }
 }
 
 impl<T: Doc, P: Pipeline<T>> Pipeline<T> for &P {
@@ -239,9 +344,17 @@ impl<T: Doc, P: Pipeline<T>> Pipeline<T> for &P {
         P::transform(doc)
     }
 
+    fn transformer(&self) -> Option<BoxedTransform> {
+        (**self).transformer()
+    }
+
     fn options(&self) -> AggregateOptions {
         (**self).options()
     }
+
+    fn read_options(&self) -> ReadOptions {
+        (**self).read_options()
+    }
 }
 
 impl<T: Doc, Q: Query<T>> Query<T> for &Q {
@@ -255,9 +368,17 @@ impl<T: Doc, Q: Query<T>> Query<T> for &Q {
         Q::transform(doc)
     }
 
+    fn transformer(&self) -> Option<BoxedTransform> {
+        (**self).transformer()
+    }
+
     fn options(&self) -> FindOptions {
         (**self).options()
     }
+
+    fn read_options(&self) -> ReadOptions {
+        (**self).read_options()
+    }
 }
 
 impl<T: Doc, U: Update<T>> Update<T> for &U {
@@ -269,7 +390,7 @@ impl<T: Doc, U: Update<T>> Update<T> for &U {
         (**self).update()
     }
 
-    fn options(&self) -> WriteConcern {
+    fn options(&self) -> UpdateOptions {
         (**self).options()
     }
 }
@@ -283,7 +404,7 @@ impl<T: Doc, U: Upsert<T>> Upsert<T> for &U {
         (**self).upsert()
     }
 
-    fn options(&self) -> WriteConcern {
+    fn options(&self) -> UpdateOptions {
         (**self).options()
     }
 }
@@ -293,7 +414,7 @@ impl<T: Doc, Q: Delete<T>> Delete<T> for &Q {
         (**self).filter()
     }
 
-    fn options(&self) -> WriteConcern {
+    fn options(&self) -> DeleteOptions {
         (**self).options()
     }
 }
@@ -313,7 +434,15 @@ impl<T: Doc, U: FindAndUpdate<T>> FindAndUpdate<T> for &U {
         U::transform(raw)
     }
 
+    fn transformer(&self) -> Option<BoxedTransform> {
+        (**self).transformer()
+    }
+
     fn options(&self) -> FindOneAndUpdateOptions {
         (**self).options()
     }
+
+    fn update_options(&self) -> UpdateOptions {
+        (**self).update_options()
+    }
 }
@@ -0,0 +1,264 @@
+//! Options that the driver's option types can't express.
+//!
+//! `ReadOptions` select the read preference and the read concern of queries,
+//! counts, `distinct` and aggregations, either for all operations on a
+//! collection (`Doc::read_options()`, `Collection::with_read_preference()`,
+//! `Collection::with_read_concern()`) or for a single operation.
+//!
+//! Besides the write concern, updates, replacements and deletions can be
+//! configured with a collation, an index hint, and (where it makes sense)
+//! the bypassing of document validation. Updates can also select the array
+//! elements that filtered positional operators refer to with array filters.
+//! Every options type can be created
+//! from a bare `WriteConcern`, which makes it possible to keep using the
+//! same functions in `#[options(...)]` derive attributes as before.
+//! ```
+//! # #[macro_use]
+//! # extern crate bson;
+//! # extern crate mongodb;
+//! # extern crate avocado;
+//! #
+//! # use mongodb::options::{ WriteConcern, ReadPreference, ReadConcern };
+//! # use avocado::options::{ UpdateOptions, Hint };
+//! #
+//! # fn main() {
+//! let options = UpdateOptions::from(WriteConcern::new())
+//!     .collation(doc!{ "locale": "fr", "strength": 1 })
+//!     .hint(Hint::Name(String::from("by_name")))
+//!     .bypass_document_validation(true);
+//!
+//! assert_eq!(options.collation, Some(doc!{ "locale": "fr", "strength": 1 }));
+//! # }
+//! ```
+
+use bson::{ Bson, Document };
+use mongodb::options::{ WriteConcern, ReadPreference, ReadConcern };
+
+/// The index a write operation should use for finding the affected documents.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hint {
+    /// The name of the index.
+    Name(String),
+    /// The key specification of the index.
+    Keys(Document),
+}
+
+impl From<Hint> for Bson {
+    fn from(hint: Hint) -> Self {
+        match hint {
+            Hint::Name(name) => Bson::String(name),
+            Hint::Keys(keys) => Bson::Document(keys),
+        }
+    }
+}
+
+/// The size limits of a capped collection, as returned by `Doc::capped()`.
+/// Once either limit is reached, inserting a document removes the oldest
+/// one, so a capped collection works like a circular buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Capped {
+    /// The maximal size of the collection in bytes.
+    pub size: i64,
+    /// The maximal number of documents in the collection, if any.
+    pub max: Option<i64>,
+}
+
+/// The read preference and read concern of read operations. Unset options
+/// fall back to those of the collection handle the operation is run on.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadOptions {
+    /// Which members of a replica set the operation may be routed to.
+    pub read_preference: Option<ReadPreference>,
+    /// The consistency and isolation level of the data being read.
+    pub read_concern: Option<ReadConcern>,
+}
+
+impl ReadOptions {
+    /// Sets the read preference.
+    pub fn read_preference(self, read_preference: ReadPreference) -> Self {
+        ReadOptions { read_preference: Some(read_preference), ..self }
+    }
+
+    /// Sets the read concern.
+    pub fn read_concern(self, read_concern: ReadConcern) -> Self {
+        ReadOptions { read_concern: Some(read_concern), ..self }
+    }
+
+    /// Returns `true` if neither the read preference nor the read concern
+    /// is set, i.e. if the defaults of the collection handle apply as-is.
+    pub fn is_empty(&self) -> bool {
+        self.read_preference.is_none() && self.read_concern.is_none()
+    }
+}
+
+impl From<ReadPreference> for ReadOptions {
+    fn from(read_preference: ReadPreference) -> Self {
+        ReadOptions { read_preference: Some(read_preference), read_concern: None }
+    }
+}
+
+impl From<ReadConcern> for ReadOptions {
+    fn from(read_concern: ReadConcern) -> Self {
+        ReadOptions { read_preference: None, read_concern: Some(read_concern) }
+    }
+}
+
+/// Options for updates and upserts.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpdateOptions {
+    /// The write concern of the operation.
+    pub write_concern: Option<WriteConcern>,
+    /// Language-specific rules for comparing strings in the filter.
+    pub collation: Option<Document>,
+    /// The index to use for finding the documents to update.
+    pub hint: Option<Hint>,
+    /// Whether the updated documents may violate the schema validator.
+    pub bypass_document_validation: Option<bool>,
+    /// Conditions selecting the array elements that the filtered positional
+    /// operators `$[<identifier>]` in the update refer to, see
+    /// [`Field::filtered()`](../field/struct.Field.html#method.filtered).
+    pub array_filters: Vec<Document>,
+}
+
+impl UpdateOptions {
+    /// Sets the array filters, each keyed by paths starting with one of the
+    /// identifiers, see
+    /// [`Field::with_identifier()`](../field/struct.Field.html#method.with_identifier).
+    pub fn array_filters(self, array_filters: Vec<Document>) -> Self {
+        UpdateOptions { array_filters, ..self }
+    }
+}
+
+/// Options for replacing whole documents, e.g. `Collection::replace_entity()`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplaceOptions {
+    /// The write concern of the operation.
+    pub write_concern: Option<WriteConcern>,
+    /// Language-specific rules for comparing strings in the filter.
+    pub collation: Option<Document>,
+    /// The index to use for finding the document to replace.
+    pub hint: Option<Hint>,
+    /// Whether the replacement may violate the schema validator.
+    pub bypass_document_validation: Option<bool>,
+}
+
+/// Options for deletions.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeleteOptions {
+    /// The write concern of the operation.
+    pub write_concern: Option<WriteConcern>,
+    /// Language-specific rules for comparing strings in the filter.
+    pub collation: Option<Document>,
+    /// The index to use for finding the documents to delete.
+    pub hint: Option<Hint>,
+}
+
+/// Implements the builder methods, the conversion from a bare write concern,
+/// and the rendering into a write command, for an options type.
+macro_rules! impl_write_options {
+    ($ty:ident { $($bypass:ident)* } $([$filters:ident])*) => {
+        impl $ty {
+            /// Sets the write concern.
+            pub fn write_concern(self, write_concern: WriteConcern) -> Self {
+                $ty { write_concern: Some(write_concern), ..self }
+            }
+
+            /// Sets the collation used for matching the filter.
+            pub fn collation(self, collation: Document) -> Self {
+                $ty { collation: Some(collation), ..self }
+            }
+
+            /// Sets the index used for finding the affected documents.
+            pub fn hint(self, hint: Hint) -> Self {
+                $ty { hint: Some(hint), ..self }
+            }
+
+            $(
+                /// Sets whether the written documents may violate the
+                /// schema validator of the collection.
+                pub fn $bypass(self, bypass: bool) -> Self {
+                    $ty { $bypass: Some(bypass), ..self }
+                }
+            )*
+
+            /// Returns `true` if the driver's own methods can carry out the
+            /// operation, i.e. if nothing but the write concern is set.
+            pub(crate) fn is_write_concern_only(&self) -> bool {
+                self.collation.is_none()
+                    && self.hint.is_none()
+                    $(&& self.$bypass.is_none())*
+                    $(&& self.$filters.is_empty())*
+            }
+
+            /// Adds the per-statement options (collation, hint and array
+            /// filters) to an element of the `updates` or `deletes` array
+            /// of a command, or to a `findAndModify` command.
+            pub(crate) fn extend_statement(&self, statement: &mut Document) {
+                if let Some(ref collation) = self.collation {
+                    statement.insert("collation", collation.clone());
+                }
+                if let Some(ref hint) = self.hint {
+                    statement.insert("hint", hint.clone());
+                }
+                $(
+                    if !self.$filters.is_empty() {
+                        statement.insert("arrayFilters", self.$filters.clone());
+                    }
+                )*
+            }
+
+            /// Adds the per-command options (write concern, validation)
+            /// to an `update`, `delete` or `findAndModify` command.
+            pub(crate) fn extend_command(&self, command: &mut Document) {
+                if let Some(ref write_concern) = self.write_concern {
+                    command.insert("writeConcern", write_concern.to_bson());
+                }
+                $(
+                    if let Some(bypass) = self.$bypass {
+                        command.insert("bypassDocumentValidation", bypass);
+                    }
+                )*
+            }
+        }
+
+        impl From<WriteConcern> for $ty {
+            fn from(write_concern: WriteConcern) -> Self {
+                $ty { write_concern: Some(write_concern), ..$ty::default() }
+            }
+        }
+    }
+}
+
+impl_write_options! { UpdateOptions { bypass_document_validation } [array_filters] }
+impl_write_options! { ReplaceOptions { bypass_document_validation } }
+impl_write_options! { DeleteOptions {} }
+
+/// By default, replacements use the options of updates, except for the
+/// array filters, which only make sense with update operators.
+impl From<UpdateOptions> for ReplaceOptions {
+    fn from(options: UpdateOptions) -> Self {
+        ReplaceOptions {
+            write_concern: options.write_concern,
+            collation: options.collation,
+            hint: options.hint,
+            bypass_document_validation: options.bypass_document_validation,
+        }
+    }
+}
+
+/// A replacement is carried out by an update command, with the same options.
+impl From<ReplaceOptions> for UpdateOptions {
+    fn from(options: ReplaceOptions) -> Self {
+        UpdateOptions {
+            write_concern: options.write_concern,
+            collation: options.collation,
+            hint: options.hint,
+            bypass_document_validation: options.bypass_document_validation,
+            array_filters: Vec::new(),
+        }
+    }
+}
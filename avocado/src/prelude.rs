@@ -3,10 +3,13 @@
 
 pub use crate::{
     db::DatabaseExt,
-    coll::{ Collection, InsertManyErrorContext },
+    coll::{ Collection, InsertManyErrorContext, InsertFailure, PartialInsert },
     doc::Doc,
     uid::Uid,
+    field::{ Field, DistinctValue },
+    retry::RetryPolicy,
     ops::*,
+    options::{ ReadOptions, UpdateOptions, ReplaceOptions, DeleteOptions, Hint },
     ext::*,
     literal::{ IndexType, Order, BsonType },
     error::Error as AvocadoError,
@@ -19,5 +22,6 @@ pub use mongodb::{
     options::{
         IndexModel, FindOptions,
         FindOneAndUpdateOptions, ReturnDocument,
+        ReadPreference, ReadConcern,
     },
 };
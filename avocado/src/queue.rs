@@ -0,0 +1,561 @@
+//! A durable job queue stored in a MongoDB collection.
+//!
+//! A `Queue<J>` keeps jobs of type `J` in the collection of `J`, along with
+//! some bookkeeping data in the `_queue` field of each document. Jobs are
+//! handed out to workers in order of decreasing priority, then increasing
+//! scheduled time, by atomically acquiring a *lease* on them, which makes
+//! them invisible to other workers until the visibility timeout expires.
+//!
+//! A worker acknowledges a successfully processed job using `Queue::ack()`,
+//! which removes it from the queue, or reports a failure using `Queue::nack()`,
+//! which makes it available again after the backoff delay prescribed by the
+//! retry policy of the queue. If a worker crashes while holding a lease, the
+//! job simply becomes available again once the lease expires. Jobs that have
+//! failed or expired `max_attempts()` times are moved to the shared
+//! `DeadLetters` collection.
+//!
+//! Since the `_queue` field is not part of `J`, the collection of `J` must
+//! not be created with a schema validator that forbids additional fields.
+//! `Queue::new()` creates an index on `_queue.priority` (descending) and
+//! `_queue.visible_at` in the collection of `J`, matching the order in which
+//! jobs are leased, so that leases can be acquired efficiently.
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use std::time::Duration;
+//! # use avocado::prelude::*;
+//! # use avocado::queue::{ Queue, Schedule };
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! struct Email {
+//!     _id: Uid<Email>,
+//!     to: String,
+//! }
+//!
+//! # fn send(_: &Email) -> Result<(), String> { Ok(()) }
+//! #
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let queue = Queue::new(db.existing_collection(), db.existing_collection())?
+//!     .visibility_timeout(Duration::from_secs(60));
+//!
+//! let email = Email { _id: Uid::new_oid()?, to: String::from("a@example.com") };
+//! queue.enqueue_with(&email, Schedule::default().priority(10))?;
+//!
+//! while let Some(lease) = queue.lease()? {
+//!     match send(lease.job()) {
+//!         Ok(()) => { queue.ack(&lease)?; }
+//!         Err(message) => { queue.nack(&lease, &message)?; }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{ Duration, SystemTime };
+use bson::{ Bson, Document, oid::ObjectId };
+use mongodb::options::{ FindOneAndUpdateOptions, ReturnDocument, IndexModel };
+use crate::{
+    coll::Collection,
+    doc::Doc,
+    uid::Uid,
+    ops::{ Query, Update, Upsert, FindAndUpdate },
+    bsn::serialize_entity,
+    utils::bson_date,
+    retry::RetryPolicy,
+    error::{ Error, ErrorKind, ErrorExt, Result },
+};
+
+/// A durable, prioritized job queue backed by the collection of `J`.
+#[derive(Debug)]
+pub struct Queue<J: Doc> {
+    /// The collection holding the pending jobs.
+    jobs: Collection<J>,
+    /// The collection holding the jobs that failed too many times.
+    dead_letters: Collection<DeadLetter<J>>,
+    /// How long a leased job stays invisible to other workers.
+    visibility_timeout: Duration,
+    /// The number of attempts and the delay between them.
+    retry: RetryPolicy,
+}
+
+impl<J: Doc> Queue<J> {
+    /// Creates a queue storing its jobs in `jobs` and the jobs that failed
+    /// too many times in `dead_letters`. By default, leases expire after 30
+    /// seconds, and jobs are attempted at most 5 times, with the backoff of
+    /// `RetryPolicy::new()` between attempts.
+    ///
+    /// Also creates the index used for acquiring leases in `jobs`, if it
+    /// doesn't exist yet.
+    pub fn new(jobs: Collection<J>, dead_letters: Collection<DeadLetter<J>>) -> Result<Self> {
+        jobs.create_index_models(|| vec![
+            IndexModel {
+                keys: doc!{ "_queue.priority": -1, "_queue.visible_at": 1 },
+                options: None,
+            },
+        ])?;
+
+        Ok(Queue {
+            jobs,
+            dead_letters,
+            visibility_timeout: Duration::from_secs(30),
+            retry: RetryPolicy::new(5),
+        })
+    }
+
+    /// Sets how long a leased job stays invisible to other workers. This
+    /// should be comfortably longer than the time it takes to process a job.
+    pub fn visibility_timeout(self, timeout: Duration) -> Self {
+        Queue { visibility_timeout: timeout, ..self }
+    }
+
+    /// Sets the maximal number of attempts per job, and the delay before a
+    /// failed job becomes available again. Only `max_attempts()` and
+    /// `backoff()` of the policy are used; failed jobs are always retried.
+    pub fn retry_policy(self, retry: RetryPolicy) -> Self {
+        Queue { retry, ..self }
+    }
+
+    /// Adds a job to the queue with the default priority, runnable at once.
+    pub fn enqueue(&self, job: &J) -> Result<bool> {
+        self.enqueue_with(job, Schedule::default())
+    }
+
+    /// Adds a job to the queue with the given priority and run time.
+    ///
+    /// Enqueueing is idempotent: if a job with the same `_id` is already in
+    /// the queue, it is left intact, and `false` is returned. If the job has
+    /// no `_id` field, a new `ObjectId` is generated for it.
+    pub fn enqueue_with(&self, job: &J, schedule: Schedule) -> Result<bool> {
+        let mut document = serialize_entity(job)?;
+        let id = match document.remove("_id") {
+            Some(Bson::Null) | None => Bson::ObjectId(ObjectId::new()?),
+            Some(id) => id,
+        };
+        document.insert("_queue", doc!{
+            "priority": schedule.priority,
+            "visible_at": bson_date(schedule.run_at.unwrap_or_else(SystemTime::now)),
+            "attempts": 0,
+            "lease": Bson::Null,
+            "last_error": Bson::Null,
+        });
+
+        let result = self.jobs.upsert_one(Enqueue { id, document })?;
+
+        Ok(result.upserted_id.is_some())
+    }
+
+    /// Acquires a lease on the available job with the highest priority, or
+    /// returns `None` if no jobs are available at the moment.
+    ///
+    /// Jobs whose previous leases all expired without an `ack()` or `nack()`,
+    /// e.g. because the workers holding them crashed, are moved to the dead
+    /// letters once they have been leased `max_attempts()` times.
+    pub fn lease(&self) -> Result<Option<Lease<J>>> {
+        loop {
+            let now = SystemTime::now();
+            let acquire = Acquire {
+                now: bson_date(now),
+                until: bson_date(now + self.visibility_timeout),
+                token: ObjectId::new()?,
+            };
+            let token = acquire.token.clone();
+            let leased: Leased<J> = match self.jobs.find_one_and_update(acquire)? {
+                Some(leased) => leased,
+                None => return Ok(None),
+            };
+
+            if leased.attempts > self.retry.max_attempts() {
+                self.bury(leased.id, token, "lease expired too many times")?;
+                continue;
+            }
+
+            return Ok(Some(Lease {
+                job: leased.job,
+                id: leased.id,
+                token,
+                attempts: leased.attempts,
+            }));
+        }
+    }
+
+    /// Acknowledges that a job was processed successfully, and removes it
+    /// from the queue. Returns `false` if the lease has expired in the
+    /// meantime and the job was leased by another worker.
+    pub fn ack(&self, lease: &Lease<J>) -> Result<bool> {
+        self.jobs.delete_one(lease.filter())
+    }
+
+    /// Reports that processing a job failed. If the job has attempts left,
+    /// it becomes available again after the backoff delay of the retry
+    /// policy; otherwise, it is moved to the dead letters.
+    pub fn nack(&self, lease: &Lease<J>, error: &str) -> Result<Nack> {
+        if lease.attempts >= self.retry.max_attempts() {
+            let id = lease.id.clone();
+            let token = lease.token.clone();
+
+            return self.bury(id, token, error).map(|buried| if buried {
+                Nack::DeadLettered
+            } else {
+                Nack::LeaseLost
+            });
+        }
+
+        let delay = self.retry.backoff(lease.attempts);
+        let release = Release {
+            filter: lease.filter(),
+            visible_at: bson_date(SystemTime::now() + delay),
+            error: error.into(),
+        };
+        let result = self.jobs.update_one(release)?;
+
+        Ok(if result.matched { Nack::Retry(delay) } else { Nack::LeaseLost })
+    }
+
+    /// Returns the number of jobs in each state.
+    pub fn stats(&self) -> Result<QueueStats> {
+        let now = bson_date(SystemTime::now());
+
+        Ok(QueueStats {
+            ready: self.jobs.count(doc!{
+                "_queue.visible_at": { "$lte": now.clone() },
+            })?,
+            leased: self.jobs.count(doc!{
+                "_queue.visible_at": { "$gt": now.clone() },
+                "_queue.lease": { "$ne": Bson::Null },
+            })?,
+            delayed: self.jobs.count(doc!{
+                "_queue.visible_at": { "$gt": now },
+                "_queue.lease": Bson::Null,
+            })?,
+            dead: self.dead_letters.count(doc!{ "queue": J::NAME })?,
+        })
+    }
+
+    /// Moves the job with the raw `_id` and the lease `token` to the dead
+    /// letters. Returns `false` if no such job was found, i.e. it was
+    /// acknowledged or re-leased.
+    ///
+    /// The dead letter is inserted before the job is deleted, so that a
+    /// failure in between leaves the job in the queue instead of losing it.
+    /// Since the `_id` of the dead letter is derived from the job and the
+    /// lease, burying the same job again doesn't duplicate it.
+    fn bury(&self, id: Bson, token: ObjectId, error: &str) -> Result<bool> {
+        let filter = doc!{ "_id": id.clone(), "_queue.lease": token.clone() };
+        let leased: Leased<J> = match self.jobs.find_one(Claim(filter.clone()))? {
+            Some(leased) => leased,
+            None => return Ok(false),
+        };
+        let letter_id = Uid::from_raw(DeadLetterId { job: id, lease: token });
+        let letter = DeadLetter {
+            id: letter_id.clone(),
+            queue: J::NAME.into(),
+            job: leased.job,
+            attempts: leased.attempts,
+            error: error.into(),
+        };
+
+        match self.dead_letters.insert_one(&letter) {
+            Ok(_) => {}
+            Err(ref err) if err.kind() == ErrorKind::DuplicateKey => {}
+            Err(err) => return Err(err),
+        }
+
+        if self.jobs.delete_one(filter)? {
+            Ok(true)
+        } else {
+            // The lease expired and the job was leased again in the meantime.
+            self.dead_letters.delete_one(doc!{ "_id": letter_id.to_bson()? }).map(|_| false)
+        }
+    }
+}
+
+/// The priority and the run time of a job.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Schedule {
+    /// Jobs with a higher priority are leased first.
+    priority: i32,
+    /// The job is not leased before this time. `None` means "now".
+    run_at: Option<SystemTime>,
+}
+
+impl Schedule {
+    /// Sets the priority. Jobs with a higher priority are leased first;
+    /// the default priority is 0.
+    pub fn priority(self, priority: i32) -> Self {
+        Schedule { priority, ..self }
+    }
+
+    /// Sets the earliest time at which the job may be leased.
+    pub fn run_at(self, time: SystemTime) -> Self {
+        Schedule { run_at: Some(time), ..self }
+    }
+
+    /// Sets the earliest time at which the job may be leased, relative to now.
+    pub fn delay(self, delay: Duration) -> Self {
+        self.run_at(SystemTime::now() + delay)
+    }
+}
+
+/// A job leased by a worker, along with the proof of ownership.
+#[derive(Debug, Clone)]
+pub struct Lease<J> {
+    /// The job itself.
+    job: J,
+    /// The raw `_id` of the job document.
+    id: Bson,
+    /// Identifies this lease among all leases of the same job.
+    token: ObjectId,
+    /// The number of times the job has been leased, including this time.
+    attempts: u32,
+}
+
+impl<J> Lease<J> {
+    /// Returns the leased job.
+    pub fn job(&self) -> &J {
+        &self.job
+    }
+
+    /// Returns the leased job by value.
+    pub fn into_job(self) -> J {
+        self.job
+    }
+
+    /// Returns the number of times the job has been leased, including this time.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Matches the job only as long as it is still held by this lease.
+    fn filter(&self) -> Document {
+        doc!{ "_id": self.id.clone(), "_queue.lease": self.token.clone() }
+    }
+}
+
+/// What happened to a job after a failure was reported via `Queue::nack()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nack {
+    /// The job will become available again after the given delay.
+    Retry(Duration),
+    /// The job ran out of attempts and was moved to the dead letters.
+    DeadLettered,
+    /// The lease has expired and the job was leased by another worker,
+    /// or it has already been acknowledged or dead-lettered.
+    LeaseLost,
+}
+
+/// The number of jobs in each state, as returned by `Queue::stats()`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct QueueStats {
+    /// Jobs that can be leased right now.
+    pub ready: usize,
+    /// Jobs currently leased by a worker.
+    pub leased: usize,
+    /// Jobs scheduled for later, or waiting for a retry.
+    pub delayed: usize,
+    /// Jobs of this queue in the dead letters.
+    pub dead: usize,
+}
+
+/// A job that failed or expired too many times. The dead letters of all
+/// queues are stored in the `DeadLetters` collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "J: Doc"))]
+pub struct DeadLetter<J: Doc> {
+    /// The `_id` of the job and the lease under which it was moved to the
+    /// dead letters.
+    #[serde(rename = "_id")]
+    pub id: Uid<DeadLetter<J>>,
+    /// The collection name of the queue that the job came from.
+    pub queue: String,
+    /// The job itself.
+    pub job: J,
+    /// The number of times the job was leased.
+    pub attempts: u32,
+    /// The error reported by the last failed attempt.
+    pub error: String,
+}
+
+impl<J: Doc> Doc for DeadLetter<J> {
+    type Id = DeadLetterId;
+
+    const NAME: &'static str = "DeadLetters";
+
+    fn id(&self) -> Option<&Uid<Self>> {
+        Some(&self.id)
+    }
+
+    fn set_id(&mut self, id: Uid<Self>) {
+        self.id = id;
+    }
+}
+
+/// The `_id` of a dead letter. A job only has one lease at a time, so the
+/// combination is unique, and it's the same every time the job is buried
+/// under the same lease.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetterId {
+    /// The raw `_id` of the job.
+    pub job: Bson,
+    /// Identifies the lease under which the job was moved to the dead letters.
+    pub lease: ObjectId,
+}
+
+/// Required by `Doc::Id`. The `_id` of a job is never a floating-point NaN
+/// in practice, so the comparison is reflexive.
+impl Eq for DeadLetterId {}
+
+/// A job, its raw `_id`, and its attempt count, as read back from the queue.
+#[derive(Debug, Deserialize)]
+struct Leased<J> {
+    /// The raw `_id` of the job document.
+    id: Bson,
+    /// The job itself, without the `_queue` field.
+    job: J,
+    /// The number of times the job has been leased.
+    attempts: u32,
+}
+
+/// Splits a queued job document into the shape expected by `Leased`.
+fn split_job(mut raw: Document) -> Result<Bson> {
+    let id = raw.get("_id").cloned().unwrap_or(Bson::Null);
+    let attempts = match raw.remove("_queue") {
+        Some(Bson::Document(meta)) => meta.get("attempts").cloned().unwrap_or(Bson::I32(0)),
+        _ => return Err(Error::new(ErrorKind::MissingDocumentField, "missing `_queue` field")),
+    };
+
+    Ok(Bson::Document(doc!{ "id": id, "job": raw, "attempts": attempts }))
+}
+
+/// Inserts a job unless one with the same `_id` is already queued.
+#[derive(Debug)]
+struct Enqueue {
+    /// The `_id` of the job.
+    id: Bson,
+    /// The job and its bookkeeping data, without the `_id`.
+    document: Document,
+}
+
+impl<J: Doc> Upsert<J> for Enqueue {
+    fn filter(&self) -> Document {
+        doc!{ "_id": self.id.clone() }
+    }
+
+    fn upsert(&self) -> Document {
+        doc!{ "$setOnInsert": self.document.clone() }
+    }
+}
+
+/// Leases the available job with the highest priority.
+#[derive(Debug)]
+struct Acquire {
+    /// The current time.
+    now: Bson,
+    /// The expiry of the lease.
+    until: Bson,
+    /// Identifies the new lease.
+    token: ObjectId,
+}
+
+impl<J: Doc> FindAndUpdate<J> for Acquire {
+    type Output = Leased<J>;
+
+    fn filter(&self) -> Document {
+        doc!{ "_queue.visible_at": { "$lte": self.now.clone() } }
+    }
+
+    fn update(&self) -> Document {
+        doc!{
+            "$set": {
+                "_queue.visible_at": self.until.clone(),
+                "_queue.lease": self.token.clone(),
+            },
+            "$inc": { "_queue.attempts": 1 },
+        }
+    }
+
+    fn transform(raw: Document) -> Result<Bson> {
+        split_job(raw)
+    }
+
+    fn options(&self) -> FindOneAndUpdateOptions {
+        FindOneAndUpdateOptions {
+            sort: Some(doc!{ "_queue.priority": -1, "_queue.visible_at": 1 }),
+            return_document: Some(ReturnDocument::After),
+            ..Default::default()
+        }
+    }
+}
+
+/// Gives up a lease, making the job available again at a later time.
+#[derive(Debug)]
+struct Release {
+    /// Matches the job held by the lease.
+    filter: Document,
+    /// The time at which the job becomes available again.
+    visible_at: Bson,
+    /// The error reported by the failed attempt.
+    error: String,
+}
+
+impl<J: Doc> Update<J> for Release {
+    fn filter(&self) -> Document {
+        self.filter.clone()
+    }
+
+    fn update(&self) -> Document {
+        doc!{
+            "$set": {
+                "_queue.visible_at": self.visible_at.clone(),
+                "_queue.lease": Bson::Null,
+                "_queue.last_error": self.error.as_str(),
+            }
+        }
+    }
+}
+
+/// Selects a job in order to remove it from the queue.
+#[derive(Debug)]
+struct Claim(Document);
+
+impl<J: Doc> Query<J> for Claim {
+    type Output = Leased<J>;
+
+    fn filter(&self) -> Document {
+        self.0.clone()
+    }
+
+    fn transform(raw: Document) -> Result<Bson> {
+        split_job(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_job_extracts_bookkeeping() -> Result<()> {
+        let raw = doc!{
+            "_id": 42,
+            "payload": "x",
+            "_queue": { "priority": 0, "attempts": 3, "lease": Bson::Null },
+        };
+
+        assert_eq!(split_job(raw)?, Bson::Document(doc!{
+            "id": 42,
+            "job": { "_id": 42, "payload": "x" },
+            "attempts": 3,
+        }));
+        assert_eq!(split_job(doc!{ "_id": 42 }).unwrap_err().kind(),
+                   ErrorKind::MissingDocumentField);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+//! Retrying operations that failed due to transient errors.
+//!
+//! A `Collection` can be configured with a `RetryPolicy`, in which case
+//! operations failing with a transient error (see `Error::is_transient()`)
+//! are re-attempted after an exponentially increasing, randomized delay.
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use std::time::Duration;
+//! # use avocado::prelude::*;
+//! # use avocado::retry::{ RetryPolicy, RetryErrorContext };
+//! #
+//! # #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! # struct Job {
+//! #     _id: Uid<Job>,
+//! # }
+//! #
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let policy = RetryPolicy::new(4)
+//!     .initial_backoff(Duration::from_millis(50))
+//!     .max_backoff(Duration::from_secs(2))
+//!     .retry_writes(true);
+//! let jobs: Collection<Job> = db.existing_collection::<Job>()
+//!     .with_retry_policy(policy);
+//!
+//! if let Err(error) = jobs.count(doc!{}) {
+//!     let attempts = error.context::<RetryErrorContext>().cloned().unwrap_or(1);
+//!     eprintln!("giving up after {} attempt(s): {}", attempts, error);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::thread;
+use std::time::Duration;
+use typemap::Key;
+use crate::{
+    utils::random_u64,
+    error::{ Error, Result },
+};
+
+/// Describes whether an operation is safe to execute more than once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    /// A read-only operation, e.g. a query, a count, or an aggregation.
+    Read,
+    /// A write that has the same effect when executed repeatedly, e.g.
+    /// replacing or deleting a document by its `_id`.
+    IdempotentWrite,
+    /// A write that might have a different effect when executed again,
+    /// e.g. an insertion or an update using `$inc` or `$push`.
+    Write,
+}
+
+/// Configures if, how many times, and how often failed operations are retried.
+///
+/// Only transient errors are retried. Read operations are always eligible
+/// for retrying, idempotent writes only if `retry_writes(true)` was set, and
+/// non-idempotent writes never are, since a transient error doesn't
+/// necessarily mean that the first attempt didn't take effect.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximal number of attempts, including the first one.
+    max_attempts: u32,
+    /// Delay before the first retry.
+    initial_backoff: Duration,
+    /// Upper limit on the delay between two attempts.
+    max_backoff: Duration,
+    /// Factor by which the delay grows after every attempt.
+    multiplier: f64,
+    /// Fraction of the delay that is randomized.
+    jitter: f64,
+    /// Whether idempotent writes may be retried too.
+    retry_writes: bool,
+}
+
+impl RetryPolicy {
+    /// A policy that makes at most `max_attempts` attempts (including the
+    /// first one), with a delay starting at 100 milliseconds and doubling
+    /// after every attempt, capped at 10 seconds, with full jitter.
+    /// Only reads are retried by default.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 1.0,
+            retry_writes: false,
+        }
+    }
+
+    /// A policy that never retries. This is the default.
+    pub fn never() -> Self {
+        Self::new(1)
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn initial_backoff(self, delay: Duration) -> Self {
+        RetryPolicy { initial_backoff: delay, ..self }
+    }
+
+    /// Sets the upper limit on the delay between two attempts.
+    pub fn max_backoff(self, delay: Duration) -> Self {
+        RetryPolicy { max_backoff: delay, ..self }
+    }
+
+    /// Sets the factor by which the delay grows after every attempt.
+    /// Values less than 1 are treated as 1, i.e. constant backoff.
+    pub fn multiplier(self, multiplier: f64) -> Self {
+        RetryPolicy { multiplier: multiplier.max(1.0), ..self }
+    }
+
+    /// Sets the fraction of the delay that is randomized, between 0 (no
+    /// randomization) and 1 (the delay is uniformly distributed between 0 and
+    /// the nominal delay). Randomization prevents many clients that failed
+    /// at the same time from retrying in lockstep.
+    pub fn jitter(self, fraction: f64) -> Self {
+        RetryPolicy { jitter: fraction.max(0.0).min(1.0), ..self }
+    }
+
+    /// Sets whether idempotent writes may be retried too.
+    pub fn retry_writes(self, retry: bool) -> Self {
+        RetryPolicy { retry_writes: retry, ..self }
+    }
+
+    /// Returns the maximal number of attempts, including the first one.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns whether operations of the given kind may be retried at all.
+    pub fn allows(&self, kind: OperationKind) -> bool {
+        match kind {
+            OperationKind::Read => true,
+            OperationKind::IdempotentWrite => self.retry_writes,
+            OperationKind::Write => false,
+        }
+    }
+
+    /// Returns the delay to wait for after the given (1-based) failed attempt.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = f64::from(attempt.saturating_sub(1));
+        let nominal = duration_as_secs_f64(self.initial_backoff) * self.multiplier.powf(exponent);
+        let capped = nominal.min(duration_as_secs_f64(self.max_backoff));
+        let randomized = capped * (1.0 - self.jitter * random_fraction());
+
+        Duration::from_nanos((randomized * 1e9) as u64)
+    }
+
+    /// Executes `op`, then retries it as long as it fails with a transient
+    /// error, its kind allows retrying, and attempts remain. If it ultimately
+    /// fails, the number of attempts made is recorded in the error context,
+    /// under the key `RetryErrorContext`.
+    pub fn run<R, F>(&self, kind: OperationKind, mut op: F) -> Result<R>
+        where F: FnMut() -> Result<R>
+    {
+        let mut attempt = 1;
+
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(error) => {
+                    if attempt < self.max_attempts
+                        && self.allows(kind)
+                        && error.is_transient()
+                    {
+                        thread::sleep(self.backoff(attempt));
+                        attempt += 1;
+                    } else {
+                        return Err(with_attempts(error, attempt));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::never()
+    }
+}
+
+/// Records the number of attempts in the error context.
+fn with_attempts(error: Error, attempts: u32) -> Error {
+    error.with_context::<RetryErrorContext>(attempts)
+}
+
+/// `Duration::as_secs_f64()` is too recent for our minimal supported rustc.
+#[allow(clippy::cast_precision_loss)]
+fn duration_as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+/// Returns a pseudo-random number in `[0, 1)`. It needn't be of high
+/// quality, it only serves to spread out retries.
+#[allow(clippy::cast_precision_loss)]
+fn random_fraction() -> f64 {
+    let bits = random_u64();
+    (bits >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// This context info is associated with an error returned by an operation
+/// executed under a `RetryPolicy`. Its value is the number of attempts made.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RetryErrorContext;
+
+impl Key for RetryErrorContext {
+    type Value = u32;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::cell::Cell;
+    use crate::error::{ Error, ErrorKind };
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_limit() {
+        let policy = RetryPolicy::new(10)
+            .initial_backoff(Duration::from_millis(10))
+            .max_backoff(Duration::from_millis(50))
+            .jitter(0.0);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(10));
+        assert_eq!(policy.backoff(2), Duration::from_millis(20));
+        assert_eq!(policy.backoff(3), Duration::from_millis(40));
+        assert_eq!(policy.backoff(4), Duration::from_millis(50));
+        assert_eq!(policy.backoff(100), Duration::from_millis(50));
+
+        let jittery = policy.jitter(1.0);
+
+        for attempt in 1..10 {
+            assert!(jittery.backoff(attempt) <= policy.backoff(attempt));
+        }
+    }
+
+    #[test]
+    fn fatal_errors_not_retried() {
+        let policy = RetryPolicy::new(5).initial_backoff(Duration::from_millis(0));
+        let calls = Cell::new(0);
+        let result: Result<()> = policy.run(OperationKind::Read, || {
+            calls.set(calls.get() + 1);
+            Err(Error::new(ErrorKind::BsonDecoding, "fatal"))
+        });
+        let error = result.unwrap_err();
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(error.context::<RetryErrorContext>(), Some(&1));
+    }
+
+    #[test]
+    fn write_kinds_respect_policy() {
+        let reads_only = RetryPolicy::new(3);
+        let with_writes = reads_only.retry_writes(true);
+
+        assert!(reads_only.allows(OperationKind::Read));
+        assert!(!reads_only.allows(OperationKind::IdempotentWrite));
+        assert!(with_writes.allows(OperationKind::IdempotentWrite));
+        assert!(!with_writes.allows(OperationKind::Write));
+        assert_eq!(RetryPolicy::default().max_attempts(), 1);
+    }
+}
@@ -0,0 +1,246 @@
+//! Sequential numeric IDs, allocated from a collection of counters.
+//!
+//! The current value of the sequence of each document type is stored in the
+//! `Counters` collection, under the name of the document type as its `_id`.
+//! Allocating an ID atomically increments the counter, so IDs are unique
+//! even across processes. In order to reduce the number of round trips,
+//! IDs can be allocated in batches, which are then handed out from memory.
+//! IDs allocated but not used, e.g. because the process exits before using
+//! up the whole batch, are lost, so sequences may contain gaps.
+//!
+//! Document types opt into sequential IDs by returning a `Sequence` from
+//! `Doc::sequence()`, or via `#[avocado(sequence(step = 1, batch = 100))]`
+//! when deriving `Doc` (both settings are optional). Their `_id` is then
+//! filled in automatically by `Collection::insert_one()` and `insert_many()`
+//! when it's missing.
+//! Sequential IDs can also be allocated explicitly, for types with an `i64`
+//! raw ID, using `Uid::next_sequence()`:
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[id_type = "i64"]
+//! #[avocado(sequence(batch = 100))]
+//! struct Ticket {
+//!     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+//!     id: Option<Uid<Ticket>>,
+//!     title: String,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let tickets: Collection<Ticket> = db.existing_collection();
+//! let first = tickets.insert_one(&Ticket { id: None, title: "broken".into() })?;
+//! let second = Uid::<Ticket>::next_sequence(&db)?;
+//!
+//! assert!(second.into_raw() > first.into_raw());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::{ Arc, Weak, Mutex };
+use bson::Document;
+use mongodb::{ Database, ClientInner };
+use mongodb::options::{ FindOneAndUpdateOptions, ReturnDocument };
+use crate::{
+    db::DatabaseExt,
+    doc::Doc,
+    uid::Uid,
+    ops::FindAndUpdate,
+    cursor::BoxedTransform,
+    utils::lock,
+    error::{ Error, ErrorKind, Result },
+};
+
+/// How the IDs of a document type are allocated from its sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sequence {
+    /// The difference between consecutive IDs. Values less than 1 are
+    /// treated as 1.
+    pub step: i64,
+    /// The number of IDs allocated from the database at once. Values less
+    /// than 1 are treated as 1, i.e. no pre-allocation.
+    pub batch: u32,
+}
+
+impl Default for Sequence {
+    /// Consecutive integers, starting from 1, allocated one by one.
+    fn default() -> Self {
+        Sequence { step: 1, batch: 1 }
+    }
+}
+
+/// The current value of the sequence of a document type, stored in the
+/// `Counters` collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Counter {
+    /// The name of the document type, i.e. `Doc::NAME`.
+    #[serde(rename = "_id")]
+    pub name: Uid<Counter>,
+    /// The last allocated ID.
+    pub value: i64,
+}
+
+impl Doc for Counter {
+    type Id = String;
+
+    const NAME: &'static str = "Counters";
+
+    fn id(&self) -> Option<&Uid<Self>> {
+        Some(&self.name)
+    }
+
+    fn set_id(&mut self, id: Uid<Self>) {
+        self.name = id;
+    }
+}
+
+/// A range of IDs allocated from the database, but not handed out yet.
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    /// The next ID to hand out.
+    next: i64,
+    /// The last ID in the range.
+    last: i64,
+    /// The difference between consecutive IDs.
+    step: i64,
+}
+
+/// The pre-allocated IDs of a single sequence. Each sequence is locked
+/// separately, so that allocating a new batch from the database only
+/// blocks the callers of the same sequence. Blocks are only ever replaced
+/// as a whole, so a panic can't leave them inconsistent.
+type Slot = Arc<Mutex<Option<Block>>>;
+
+/// The pre-allocated IDs of a sequence in a database of a given client.
+#[derive(Debug)]
+struct Entry {
+    /// The client that the IDs were allocated through. Different clients
+    /// may be connected to different deployments, so their IDs are kept
+    /// apart. The entry is removed once the client is dropped.
+    client: Weak<ClientInner>,
+    /// The name of the database.
+    db: String,
+    /// The name of the document type.
+    name: &'static str,
+    /// The IDs themselves.
+    slot: Slot,
+}
+
+lazy_static! {
+    /// The pre-allocated IDs of every sequence in use.
+    static ref BLOCKS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+}
+
+/// Returns the pre-allocated IDs of the sequence named `name` in `db`.
+fn slot(db: &Database, name: &'static str) -> Slot {
+    let mut entries = lock(&BLOCKS);
+    let matches = |entry: &&Entry| {
+        entry.name == name && entry.db == db.name && entry.client.upgrade().map_or(
+            false,
+            |client| Arc::ptr_eq(&client, &db.client)
+        )
+    };
+
+    entries.retain(|entry| entry.client.upgrade().is_some());
+
+    if let Some(entry) = entries.iter().find(matches) {
+        return entry.slot.clone();
+    }
+
+    let slot = Slot::default();
+
+    entries.push(Entry {
+        client: Arc::downgrade(&db.client),
+        db: db.name.clone(),
+        name,
+        slot: slot.clone(),
+    });
+
+    slot
+}
+
+/// Returns the next ID from the sequence named `name` in `db`, allocating
+/// a new batch from the database if the current one is used up.
+pub(crate) fn next_value(db: &Database, name: &'static str, sequence: Sequence) -> Result<i64> {
+    let step = sequence.step.max(1);
+    let batch = i64::from(sequence.batch.max(1));
+    let slot = slot(db, name);
+
+    // The lock of the sequence is held during allocation too, so that
+    // concurrent callers don't allocate several batches when a single one
+    // would be enough. Other sequences are not affected.
+    let mut block = lock(&slot);
+
+    if let Some(ref mut block) = *block {
+        if block.next <= block.last {
+            let value = block.next;
+            block.next += block.step;
+            return Ok(value);
+        }
+    }
+
+    let size = step.checked_mul(batch).ok_or_else(|| Error::new(
+        ErrorKind::IntConversionOverflow,
+        format!("batch of sequence {} overflows `i64`", name)
+    ))?;
+    let counters = db.existing_collection::<Counter>();
+    let advance = Advance { name, by: size };
+    let advanced = match counters.find_one_and_update(advance) {
+        // Another process created the counter concurrently; now it exists.
+        Err(ref error) if error.is_duplicate_key() => counters.find_one_and_update(advance),
+        result => result,
+    };
+    let last = match advanced? {
+        Some(counter) => counter.value,
+        None => return Err(Error::new(
+            ErrorKind::MissingDocumentField,
+            format!("counter of sequence {} not found after upsert", name)
+        )),
+    };
+    let first = last - size + step;
+
+    *block = Some(Block { next: first + step, last, step });
+
+    Ok(first)
+}
+
+/// Atomically advances a counter, creating it if it doesn't exist yet.
+#[derive(Debug, Clone, Copy)]
+struct Advance {
+    /// The name of the sequence.
+    name: &'static str,
+    /// The amount to increment the counter by.
+    by: i64,
+}
+
+impl FindAndUpdate<Counter> for Advance {
+    type Output = Counter;
+
+    fn filter(&self) -> Document {
+        doc!{ "_id": self.name }
+    }
+
+    fn update(&self) -> Document {
+        doc!{ "$inc": { "value": self.by } }
+    }
+
+    fn options(&self) -> FindOneAndUpdateOptions {
+        FindOneAndUpdateOptions {
+            upsert: Some(true),
+            return_document: Some(ReturnDocument::After),
+            ..Default::default()
+        }
+    }
+
+    fn transformer(&self) -> Option<BoxedTransform> {
+        None
+    }
+}
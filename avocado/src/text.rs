@@ -0,0 +1,214 @@
+//! Full-text search on top of `text` indexes.
+//!
+//! A `TextSearch<T>` is a regular query, so it can be passed to
+//! `Collection::find_one()` or `Collection::find_many()`. It builds the
+//! `$text` filter, and it projects (and optionally sorts by) the relevance
+//! score of each matching document, which is then returned as `Scored<T>`.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # extern crate avocado;
+//! #
+//! # use avocado::prelude::*;
+//! # use avocado::text::TextSearch;
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[index(keys(title = "text", body = "text"), default_language = "english")]
+//! struct Post {
+//!     _id: Uid<Post>,
+//!     title: String,
+//!     body: String,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let posts: Collection<Post> = db.existing_collection();
+//! let search = TextSearch::new("avocado -guacamole")
+//!     .language("english")
+//!     .sort_by_score()
+//!     .limit(10);
+//!
+//! for result in posts.find_many(search)? {
+//!     let post = result?;
+//!     println!("{:.2}: {}", post.score, post.value.title);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use bson::{ Bson, Document };
+use mongodb::options::FindOptions;
+use crate::{
+    doc::Doc,
+    ops::Query,
+    ext::DocumentExt,
+    error::Result,
+};
+
+/// The name of the field holding the text score in the returned documents.
+const SCORE_FIELD: &str = "avocado_text_score";
+
+/// A `$text` query, returning the matching documents along with their
+/// relevance score.
+#[allow(clippy::module_name_repetitions)]
+pub struct TextSearch<T> {
+    /// The words, `"quoted phrases"` and `-negated` terms to search for.
+    search: String,
+    /// The language determining stop words and stemming rules.
+    language: Option<String>,
+    /// Whether the search distinguishes upper and lower case letters.
+    case_sensitive: Option<bool>,
+    /// Whether the search distinguishes characters with diacritical marks.
+    diacritic_sensitive: Option<bool>,
+    /// Additional conditions on the matching documents.
+    filter: Document,
+    /// Whether to return the most relevant documents first.
+    sort_by_score: bool,
+    /// Maximal number of documents to return.
+    limit: Option<i64>,
+    /// Just so that the document type is fixed.
+    _marker: PhantomData<T>,
+}
+
+impl<T> TextSearch<T> {
+    /// Creates a text search for the given terms, using the default
+    /// language and sensitivity settings of the text index.
+    pub fn new<S: Into<String>>(search: S) -> Self {
+        TextSearch {
+            search: search.into(),
+            language: None,
+            case_sensitive: None,
+            diacritic_sensitive: None,
+            filter: Document::new(),
+            sort_by_score: false,
+            limit: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Overrides the language of the search. `"none"` turns off stemming
+    /// and stop word removal.
+    pub fn language<S: Into<String>>(self, language: S) -> Self {
+        TextSearch { language: Some(language.into()), ..self }
+    }
+
+    /// Makes the search case sensitive or insensitive.
+    pub fn case_sensitive(self, sensitive: bool) -> Self {
+        TextSearch { case_sensitive: Some(sensitive), ..self }
+    }
+
+    /// Makes the search sensitive or insensitive to diacritical marks.
+    pub fn diacritic_sensitive(self, sensitive: bool) -> Self {
+        TextSearch { diacritic_sensitive: Some(sensitive), ..self }
+    }
+
+    /// Restricts the search to documents also matching this filter.
+    pub fn filter(self, filter: Document) -> Self {
+        TextSearch { filter, ..self }
+    }
+
+    /// Returns the most relevant documents first. Any sort order specified
+    /// by `T::query_options()` is applied after the score.
+    pub fn sort_by_score(self) -> Self {
+        TextSearch { sort_by_score: true, ..self }
+    }
+
+    /// Returns at most this many documents.
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn limit(self, n: usize) -> Self {
+        TextSearch { limit: Some(n as i64), ..self }
+    }
+}
+
+impl<T: Doc> Query<T> for TextSearch<T> {
+    type Output = Scored<T>;
+
+    fn filter(&self) -> Document {
+        let mut text = doc!{ "$search": self.search.as_str() };
+
+        if let Some(ref language) = self.language {
+            text.insert("$language", language.as_str());
+        }
+        if let Some(sensitive) = self.case_sensitive {
+            text.insert("$caseSensitive", sensitive);
+        }
+        if let Some(sensitive) = self.diacritic_sensitive {
+            text.insert("$diacriticSensitive", sensitive);
+        }
+
+        let mut filter = self.filter.clone();
+        filter.insert("$text", text);
+        filter
+    }
+
+    fn transform(mut raw: Document) -> Result<Bson> {
+        let score = raw.remove_number(SCORE_FIELD)?;
+
+        Ok(bson!({
+            "score": score,
+            "value": raw,
+        }))
+    }
+
+    fn options(&self) -> FindOptions {
+        let options = T::query_options();
+        let mut projection = options.projection.unwrap_or_default();
+        projection.insert(SCORE_FIELD, doc!{ "$meta": "textScore" });
+
+        let sort = if self.sort_by_score {
+            let mut sort = doc!{ SCORE_FIELD: { "$meta": "textScore" } };
+            sort.extend(options.sort.unwrap_or_default());
+            Some(sort)
+        } else {
+            options.sort
+        };
+
+        FindOptions {
+            projection: Some(projection),
+            sort,
+            limit: self.limit.or(options.limit),
+            ..options
+        }
+    }
+}
+
+impl<T> Clone for TextSearch<T> {
+    fn clone(&self) -> Self {
+        TextSearch {
+            search: self.search.clone(),
+            language: self.language.clone(),
+            filter: self.filter.clone(),
+            _marker: PhantomData,
+            ..*self
+        }
+    }
+}
+
+impl<T> fmt::Debug for TextSearch<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TextSearch")
+            .field("search", &self.search)
+            .field("language", &self.language)
+            .field("case_sensitive", &self.case_sensitive)
+            .field("diacritic_sensitive", &self.diacritic_sensitive)
+            .field("filter", &self.filter)
+            .field("sort_by_score", &self.sort_by_score)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+/// A document matched by a `TextSearch`, along with its relevance score.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scored<T> {
+    /// The text score: the higher, the more relevant the document.
+    pub score: f64,
+    /// The document itself.
+    pub value: T,
+}
@@ -0,0 +1,543 @@
+//! Time-series collections, and aggregations for querying them.
+//!
+//! A `Doc` type is stored in a time-series collection if its
+//! `Doc::time_series()` method returns `Some`, which is most easily achieved
+//! by means of the `#[avocado(time_series(...))]` derive attribute.
+//! `DatabaseExt::empty_collection()` then creates the collection accordingly.
+//!
+//! The `Buckets` and `Window` pipelines summarize measurements over time
+//! intervals and sliding windows, respectively. The time and metadata fields
+//! are specified as statically-typed `Field`s.
+//!
+//! ```no_run
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! # #[macro_use]
+//! # extern crate avocado_derive;
+//! # #[macro_use]
+//! # extern crate bson;
+//! # extern crate avocado;
+//! #
+//! # use bson::UtcDateTime;
+//! # use avocado::prelude::*;
+//! # use avocado::timeseries::{ Buckets, Window, WindowBounds, TimeUnit };
+//! #
+//! #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+//! #[avocado(fields)]
+//! #[avocado(time_series(
+//!     time_field = "timestamp",
+//!     meta_field = "sensor",
+//!     granularity = "minutes",
+//!     expire_after_seconds = 2592000,
+//! ))]
+//! struct Reading {
+//!     _id: Uid<Reading>,
+//!     timestamp: UtcDateTime,
+//!     sensor: String,
+//!     celsius: f64,
+//! }
+//!
+//! #[derive(Debug, Deserialize)]
+//! struct HourlyMean {
+//!     start: UtcDateTime,
+//!     group: String,
+//!     mean: f64,
+//! }
+//!
+//! # fn main() -> AvocadoResult<()> {
+//! # let client = Client::with_uri("mongodb://localhost:27017/")?;
+//! # let db = client.db("avocado_example_db");
+//! let readings: Collection<Reading> = db.existing_collection();
+//!
+//! // Hourly mean temperature of each sensor
+//! let hourly = Buckets::<Reading, HourlyMean>::new(Reading::timestamp, TimeUnit::Hour, 1)
+//!     .group_by(Reading::sensor)
+//!     .accumulate("mean", doc!{ "$avg": "$celsius" });
+//!
+//! for mean in readings.aggregate(hourly)? {
+//!     let mean = mean?;
+//!     println!("{}: {} at {}", mean.group, mean.mean, *mean.start);
+//! }
+//!
+//! // Moving average of the last 10 minutes, for every reading
+//! let smoothed = Window::<Reading>::new(Reading::timestamp)
+//!     .partition_by(Reading::sensor)
+//!     .output(
+//!         "smoothed",
+//!         doc!{ "$avg": "$celsius" },
+//!         WindowBounds::Range(-10, 0, TimeUnit::Minute),
+//!     );
+//!
+//! let documents: Vec<Document> = readings.aggregate(smoothed)?.collect::<Result<_, _>>()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::marker::PhantomData;
+use std::fmt::{ Debug, Formatter, Result as FmtResult };
+use serde::Deserialize;
+use bson::{ Bson, Document };
+use crate::{
+    doc::Doc,
+    ops::Pipeline,
+    field::Field,
+    cursor::BoxedTransform,
+};
+
+/// How a time-series collection should be set up, as returned by
+/// `Doc::time_series()`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimeSeries {
+    /// The name of the field holding the date of each measurement.
+    pub time_field: &'static str,
+    /// The name of the field holding the metadata identifying the source
+    /// of the measurements (e.g. a sensor ID), if any.
+    pub meta_field: Option<&'static str>,
+    /// The typical interval between consecutive measurements of a source.
+    pub granularity: Option<Granularity>,
+    /// The number of seconds after which measurements are deleted, if any.
+    pub expire_after_seconds: Option<i64>,
+}
+
+impl TimeSeries {
+    /// Returns the value of the `timeseries` option of the `create` command.
+    pub(crate) fn to_document(self) -> Document {
+        let mut options = doc!{ "timeField": self.time_field };
+
+        if let Some(meta_field) = self.meta_field {
+            options.insert("metaField", meta_field);
+        }
+
+        if let Some(granularity) = self.granularity {
+            options.insert("granularity", granularity.as_str());
+        }
+
+        options
+    }
+}
+
+/// The typical interval between consecutive measurements from the same
+/// source, used by MongoDB for organizing the data internally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Granularity {
+    /// Measurements arrive every few seconds.
+    Seconds,
+    /// Measurements arrive every few minutes.
+    Minutes,
+    /// Measurements arrive every few hours.
+    Hours,
+}
+
+impl Granularity {
+    /// Returns the MongoDB spelling of the granularity.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Granularity::Seconds => "seconds",
+            Granularity::Minutes => "minutes",
+            Granularity::Hours   => "hours",
+        }
+    }
+}
+
+/// A unit of time for bucketing and for time-based windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeUnit {
+    /// One millisecond.
+    Millisecond,
+    /// One second.
+    Second,
+    /// One minute.
+    Minute,
+    /// One hour.
+    Hour,
+    /// One day.
+    Day,
+    /// One week, starting on Sunday.
+    Week,
+    /// One calendar month.
+    Month,
+    /// One quarter of a year.
+    Quarter,
+    /// One calendar year.
+    Year,
+}
+
+impl TimeUnit {
+    /// Returns the MongoDB spelling of the unit.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeUnit::Millisecond => "millisecond",
+            TimeUnit::Second      => "second",
+            TimeUnit::Minute      => "minute",
+            TimeUnit::Hour        => "hour",
+            TimeUnit::Day         => "day",
+            TimeUnit::Week        => "week",
+            TimeUnit::Month       => "month",
+            TimeUnit::Quarter     => "quarter",
+            TimeUnit::Year        => "year",
+        }
+    }
+}
+
+impl From<TimeUnit> for Bson {
+    fn from(unit: TimeUnit) -> Self {
+        Bson::String(unit.as_str().into())
+    }
+}
+
+/// Summarizes the documents falling into consecutive time intervals of equal
+/// length, optionally grouped by a metadata field as well.
+///
+/// Each output document has a `start` field containing the beginning of the
+/// interval, a `group` field containing the value of the metadata field (if
+/// grouping by one), and a field for each accumulator. The output is sorted
+/// by `start`, then by `group`.
+pub struct Buckets<T, O = Document> {
+    /// The path of the time field.
+    time: String,
+    /// The unit of the length of the buckets.
+    unit: TimeUnit,
+    /// The length of the buckets, in multiples of `unit`.
+    bin_size: u32,
+    /// The path of the field to group by in addition to time, if any.
+    group: Option<String>,
+    /// Restricts the documents being summarized.
+    filter: Document,
+    /// The accumulator expressions computing the output fields.
+    accumulators: Document,
+    /// Just here so that the type parameters are used.
+    _marker: PhantomData<fn() -> (T, O)>,
+}
+
+impl<T, O> Buckets<T, O> {
+    /// Creates buckets of `bin_size` units of time each, based on the date
+    /// stored in the `time` field.
+    pub fn new<V>(time: Field<T, V>, unit: TimeUnit, bin_size: u32) -> Self {
+        Buckets {
+            time: time.into_path().into_owned(),
+            unit,
+            bin_size,
+            group: None,
+            filter: Document::new(),
+            accumulators: Document::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Summarizes the documents separately for each value of `meta`,
+    /// which is usually the metadata field of the time series.
+    pub fn group_by<M>(self, meta: Field<T, M>) -> Self {
+        Buckets { group: Some(meta.into_path().into_owned()), ..self }
+    }
+
+    /// Only takes into account the documents matching `filter`, for
+    /// instance, the ones in a given time range.
+    pub fn matching(self, filter: Document) -> Self {
+        Buckets { filter, ..self }
+    }
+
+    /// Adds an output field called `name`, computed by the `$group`
+    /// accumulator expression `accumulator`, e.g. `{ "$max": "$value" }`.
+    pub fn accumulate(mut self, name: &str, accumulator: Document) -> Self {
+        self.accumulators.insert(name, accumulator);
+        self
+    }
+}
+
+impl<T, O> Debug for Buckets<T, O> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Buckets")
+            .field("time", &self.time)
+            .field("unit", &self.unit)
+            .field("bin_size", &self.bin_size)
+            .field("group", &self.group)
+            .field("filter", &self.filter)
+            .field("accumulators", &self.accumulators)
+            .finish()
+    }
+}
+
+impl<T, O> Pipeline<T> for Buckets<T, O>
+    where T: Doc,
+          O: for<'a> Deserialize<'a>,
+{
+    type Output = O;
+
+    fn stages(&self) -> Vec<Document> {
+        let mut key = doc!{
+            "start": {
+                "$dateTrunc": {
+                    "date": format!("${}", self.time),
+                    "unit": self.unit,
+                    "binSize": self.bin_size,
+                }
+            }
+        };
+        let mut fields = doc!{ "start": "$_id.start" };
+
+        if let Some(ref group) = self.group {
+            key.insert("group", format!("${}", group));
+            fields.insert("group", "$_id.group");
+        }
+
+        let mut group = doc!{ "_id": key };
+
+        for (name, accumulator) in &self.accumulators {
+            group.insert(name.as_str(), accumulator.clone());
+        }
+
+        let mut stages = Vec::with_capacity(5);
+
+        if !self.filter.is_empty() {
+            stages.push(doc!{ "$match": self.filter.clone() });
+        }
+
+        stages.push(doc!{ "$group": group });
+        stages.push(doc!{ "$sort": { "_id.start": 1, "_id.group": 1 } });
+        stages.push(doc!{ "$addFields": fields });
+        stages.push(doc!{ "$project": { "_id": 0 } });
+        stages
+    }
+
+    fn transformer(&self) -> Option<BoxedTransform> {
+        None
+    }
+}
+
+/// The documents taken into account for computing a window function,
+/// relative to the current document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WindowBounds {
+    /// A range of documents by position, e.g. `Documents(-2, 0)` for the
+    /// current document and the two preceding ones.
+    Documents(i64, i64),
+    /// A range of time relative to the time of the current document, e.g.
+    /// `Range(-10, 0, TimeUnit::Minute)` for the last 10 minutes.
+    Range(i64, i64, TimeUnit),
+}
+
+impl WindowBounds {
+    /// Returns the value of the `window` field of a window operator.
+    fn to_document(self) -> Document {
+        match self {
+            WindowBounds::Documents(lower, upper) => doc!{
+                "documents": [lower, upper]
+            },
+            WindowBounds::Range(lower, upper, unit) => doc!{
+                "range": [lower, upper],
+                "unit": unit,
+            },
+        }
+    }
+}
+
+/// Adds fields computed over a sliding window of documents to each
+/// document, e.g. moving averages or running totals, using the
+/// `$setWindowFields` stage. Documents are ordered by their time field,
+/// and windows don't span different partitions, if partitioned.
+pub struct Window<T, O = Document> {
+    /// The path of the time field.
+    time: String,
+    /// The path of the field to partition by, if any.
+    partition: Option<String>,
+    /// Restricts the documents being considered.
+    filter: Document,
+    /// The window operators computing the added fields.
+    outputs: Document,
+    /// Just here so that the type parameters are used.
+    _marker: PhantomData<fn() -> (T, O)>,
+}
+
+impl<T, O> Window<T, O> {
+    /// Creates a window pipeline ordering documents by the date stored
+    /// in the `time` field.
+    pub fn new<V>(time: Field<T, V>) -> Self {
+        Window {
+            time: time.into_path().into_owned(),
+            partition: None,
+            filter: Document::new(),
+            outputs: Document::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Computes the windows separately for each value of `meta`,
+    /// which is usually the metadata field of the time series.
+    pub fn partition_by<M>(self, meta: Field<T, M>) -> Self {
+        Window { partition: Some(meta.into_path().into_owned()), ..self }
+    }
+
+    /// Only takes into account the documents matching `filter`.
+    pub fn matching(self, filter: Document) -> Self {
+        Window { filter, ..self }
+    }
+
+    /// Adds a field called `name` to each document, computed by the window
+    /// operator `operator` (e.g. `{ "$sum": "$value" }`) over the documents
+    /// within `bounds`.
+    pub fn output(self, name: &str, mut operator: Document, bounds: WindowBounds) -> Self {
+        operator.insert("window", bounds.to_document());
+        self.output_unbounded(name, operator)
+    }
+
+    /// Adds a field called `name` to each document, computed by `operator`
+    /// without a `window` field. Operators which depend on the order of the
+    /// documents, e.g. `$rank`, `$denseRank`, `$documentNumber`, `$shift`
+    /// and `$locf`, require this; accumulators such as `$sum` are computed
+    /// over the whole partition this way.
+    pub fn output_unbounded(mut self, name: &str, operator: Document) -> Self {
+        self.outputs.insert(name, operator);
+        self
+    }
+}
+
+impl<T, O> Debug for Window<T, O> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Window")
+            .field("time", &self.time)
+            .field("partition", &self.partition)
+            .field("filter", &self.filter)
+            .field("outputs", &self.outputs)
+            .finish()
+    }
+}
+
+impl<T, O> Pipeline<T> for Window<T, O>
+    where T: Doc,
+          O: for<'a> Deserialize<'a>,
+{
+    type Output = O;
+
+    fn stages(&self) -> Vec<Document> {
+        let mut window = doc!{
+            "sortBy": { (self.time.as_str()): 1 },
+            "output": self.outputs.clone(),
+        };
+
+        if let Some(ref partition) = self.partition {
+            window.insert("partitionBy", format!("${}", partition));
+        }
+
+        let mut stages = Vec::with_capacity(2);
+
+        if !self.filter.is_empty() {
+            stages.push(doc!{ "$match": self.filter.clone() });
+        }
+
+        stages.push(doc!{ "$setWindowFields": window });
+        stages
+    }
+
+    fn transformer(&self) -> Option<BoxedTransform> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::Document;
+    use crate::{
+        doc::Doc,
+        uid::Uid,
+        field::Field,
+        ops::Pipeline,
+    };
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Reading {
+        _id: Uid<Reading>,
+        sensor: String,
+        value: f64,
+    }
+
+    impl Doc for Reading {
+        type Id = i32;
+
+        const NAME: &'static str = "Reading";
+
+        fn id(&self) -> Option<&Uid<Self>> {
+            Some(&self._id)
+        }
+
+        fn set_id(&mut self, id: Uid<Self>) {
+            self._id = id;
+        }
+    }
+
+    const TIMESTAMP: Field<Reading, i64> = Field::new("meta.ts");
+    const SENSOR: Field<Reading, String> = Field::new("sensor");
+
+    #[test]
+    fn buckets_stages() {
+        let buckets = Buckets::<Reading, Document>::new(TIMESTAMP, TimeUnit::Minute, 15)
+            .group_by(SENSOR)
+            .matching(doc!{ "value": { "$gt": 0 } })
+            .accumulate("max", doc!{ "$max": "$value" });
+
+        assert_eq!(buckets.stages(), vec![
+            doc!{ "$match": { "value": { "$gt": 0 } } },
+            doc!{
+                "$group": {
+                    "_id": {
+                        "start": {
+                            "$dateTrunc": {
+                                "date": "$meta.ts",
+                                "unit": "minute",
+                                "binSize": 15,
+                            }
+                        },
+                        "group": "$sensor",
+                    },
+                    "max": { "$max": "$value" },
+                }
+            },
+            doc!{ "$sort": { "_id.start": 1, "_id.group": 1 } },
+            doc!{ "$addFields": { "start": "$_id.start", "group": "$_id.group" } },
+            doc!{ "$project": { "_id": 0 } },
+        ]);
+    }
+
+    #[test]
+    fn window_stages() {
+        let window = Window::<Reading>::new(TIMESTAMP)
+            .partition_by(SENSOR)
+            .output("total", doc!{ "$sum": "$value" }, WindowBounds::Documents(-2, 0))
+            .output("mean", doc!{ "$avg": "$value" }, WindowBounds::Range(-1, 0, TimeUnit::Hour))
+            .output_unbounded("previous", doc!{ "$shift": { "output": "$value", "by": -1 } });
+
+        assert_eq!(window.stages(), vec![
+            doc!{
+                "$setWindowFields": {
+                    "sortBy": { "meta.ts": 1 },
+                    "output": {
+                        "total": { "$sum": "$value", "window": { "documents": [-2_i64, 0_i64] } },
+                        "mean": {
+                            "$avg": "$value",
+                            "window": { "range": [-1_i64, 0_i64], "unit": "hour" },
+                        },
+                        "previous": { "$shift": { "output": "$value", "by": -1 } },
+                    },
+                    "partitionBy": "$sensor",
+                }
+            },
+        ]);
+    }
+
+    #[test]
+    fn time_series_options() {
+        let time_series = TimeSeries {
+            time_field: "ts",
+            meta_field: Some("sensor"),
+            granularity: Some(Granularity::Hours),
+            expire_after_seconds: Some(3600),
+        };
+
+        assert_eq!(time_series.to_document(), doc!{
+            "timeField": "ts",
+            "metaField": "sensor",
+            "granularity": "hours",
+        });
+    }
+}
@@ -3,6 +3,7 @@
 use std::{
     str::FromStr,
     borrow::ToOwned,
+    time::{ Duration, SystemTime, UNIX_EPOCH },
     cmp::{ PartialEq, Eq, PartialOrd, Ord, Ordering },
     hash::{ Hash, Hasher },
     fmt::{ Debug, Display, Formatter, Result as FmtResult },
@@ -11,9 +12,15 @@ use serde::{
     ser::{ Serialize, Serializer },
     de::{ Deserialize, Deserializer },
 };
-use bson::{ Bson, oid::ObjectId };
+use bson::{ Bson, Document, oid::ObjectId };
+use mongodb::Database;
+use mongodb::options::FindOptions;
 use crate::{
     doc::Doc,
+    ops::Query,
+    cursor::BoxedTransform,
+    bsn::serialize_bson,
+    sequence,
     error::Error,
 };
 
@@ -27,6 +34,14 @@ use uuid::{ Uuid, Version, Variant, Builder };
 ///
 /// It serializes and deserializes transparently as if it were a value of
 /// type `<T as Doc>::Id`.
+///
+/// The raw ID may also be a struct, in which case it is stored as an
+/// embedded document in the `_id` field, e.g. `{ _id: { tenant, sku } }`.
+/// Since MongoDB compares embedded documents field by field, in order,
+/// the fields of such compound IDs are always serialized in the order of
+/// their declaration. Don't use maps with unspecified iteration order (e.g.
+/// `HashMap`) as compound IDs, because they would be serialized in a
+/// different order every time.
 pub struct Uid<T: Doc>(T::Id);
 
 impl<T: Doc> Uid<T> {
@@ -39,6 +54,14 @@ impl<T: Doc> Uid<T> {
     pub fn into_raw(self) -> T::Id {
         self.0
     }
+
+    /// Converts the ID into a BSON value, exactly as it is stored in the
+    /// `_id` field of documents. This is useful for building filters when
+    /// the raw ID doesn't convert to `Bson` by itself, e.g. compound IDs:
+    /// `doc!{ "_id": id.to_bson()? }`.
+    pub fn to_bson(&self) -> Result<Bson, Error> {
+        serialize_bson(&self.0)
+    }
 }
 
 /// Convenience methods for `ObjectId`-valued `Uid`s.
@@ -58,6 +81,49 @@ impl<T: Doc<Id = ObjectId>> Uid<T> {
     pub fn from_oid_str(s: &str) -> Result<Self, Error> {
         ObjectId::with_string(s).map(Uid::from_raw).map_err(Into::into)
     }
+
+    /// Returns the creation time embedded in the `ObjectId`, with a
+    /// precision of one second.
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(u64::from(self.0.timestamp()))
+    }
+
+    /// Returns the smallest `ObjectId` with the creation time `time`. It is
+    /// not a valid ID for any document; it's only meant to be used as a
+    /// (lower) bound in range queries.
+    ///
+    /// The time is truncated to whole seconds, and clamped to the range
+    /// representable by an `ObjectId`, i.e. years 1970 through 2106.
+    pub fn min_for_time(time: SystemTime) -> Self {
+        Uid::from_raw(ObjectId::with_timestamp(oid_timestamp(time)))
+    }
+
+    /// Returns the greatest `ObjectId` with the creation time `time`. Just
+    /// like `min_for_time()`, it's only meant to be used as an (upper) bound.
+    pub fn max_for_time(time: SystemTime) -> Self {
+        let mut bytes = [0xff; 12];
+        bytes[..4].copy_from_slice(&oid_timestamp(time).to_be_bytes());
+        Uid::from_oid_bytes(bytes)
+    }
+}
+
+/// Converts a point in time to the seconds-resolution timestamp stored
+/// in the first 4 bytes of an `ObjectId`, clamping it to the valid range.
+#[allow(clippy::cast_possible_truncation)]
+fn oid_timestamp(time: SystemTime) -> u32 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs().min(0xffff_ffff) as u32)
+        .unwrap_or(0)
+}
+
+/// Convenience methods for sequential `i64`-valued `Uid`s.
+impl<T: Doc<Id = i64>> Uid<T> {
+    /// Allocates the next ID from the sequence of `T` in `db`, as configured
+    /// by `T::sequence()`, or consecutively if it returns `None`.
+    pub fn next_sequence(db: &Database) -> Result<Self, Error> {
+        let settings = T::sequence().unwrap_or_default();
+        sequence::next_value(db, T::NAME, settings).map(Uid::from_raw)
+    }
 }
 
 /// Convenience methods for `Uuid`-valued `Uid`s.
@@ -194,7 +260,155 @@ impl<T: Doc> From<&Uid<T>> for Bson where
 
 #[cfg(feature = "schema_validation")]
 impl<T: Doc> BsonSchema for Uid<T> where T::Id: BsonSchema {
-    fn bson_schema() -> bson::Document {
+    fn bson_schema() -> Document {
         T::Id::bson_schema()
     }
 }
+
+/// A query for the documents created in a given time range, based on the
+/// creation time embedded in their `ObjectId`. Since it only constrains the
+/// `_id`, it is answered using the index on `_id`, without any additional
+/// index or timestamp field.
+///
+/// The results are sorted by `_id`, i.e. by creation time (and creation
+/// order within the same second, for IDs generated by the same process), so
+/// the range can be paged through cheaply by passing the ID of the last
+/// document of a page to `after()` in order to obtain the next page:
+///
+/// ```no_run
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// # #[macro_use]
+/// # extern crate avocado_derive;
+/// # extern crate avocado;
+/// #
+/// # use std::time::{ Duration, SystemTime };
+/// # use avocado::prelude::*;
+/// # use avocado::uid::CreatedBetween;
+/// #
+/// #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+/// struct Comment {
+///     _id: Uid<Comment>,
+///     text: String,
+/// }
+///
+/// # fn main() -> AvocadoResult<()> {
+/// # let client = Client::with_uri("mongodb://localhost:27017/")?;
+/// # let db = client.db("avocado_example_db");
+/// let comments: Collection<Comment> = db.existing_collection();
+/// let end = SystemTime::now();
+/// let start = end - Duration::from_secs(24 * 60 * 60);
+/// let mut query = CreatedBetween::new(start, end).limit(100);
+///
+/// loop {
+///     let page: Vec<Comment> = comments.find_many(&query)?.collect::<Result<_, _>>()?;
+///
+///     match page.last() {
+///         Some(last) => query = query.after(&last._id),
+///         None => break,
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct CreatedBetween<T: Doc<Id = ObjectId>> {
+    /// The lower bound of the IDs, either inclusive or exclusive.
+    start: Uid<T>,
+    /// Whether `start` itself is excluded, i.e. it's the last ID seen.
+    exclusive: bool,
+    /// The inclusive upper bound of the IDs.
+    end: Uid<T>,
+    /// Additional conditions on the matching documents.
+    filter: Document,
+    /// Maximal number of documents to return.
+    limit: Option<i64>,
+}
+
+impl<T: Doc<Id = ObjectId>> CreatedBetween<T> {
+    /// Matches the documents created between `start` and `end`, both
+    /// inclusive, with a precision of one second.
+    pub fn new(start: SystemTime, end: SystemTime) -> Self {
+        CreatedBetween {
+            start: Uid::min_for_time(start),
+            exclusive: false,
+            end: Uid::max_for_time(end),
+            filter: Document::new(),
+            limit: None,
+        }
+    }
+
+    /// Only matches the documents following the one with the given ID,
+    /// which is usually the last document of the previous page.
+    pub fn after(self, id: &Uid<T>) -> Self {
+        CreatedBetween { start: id.clone(), exclusive: true, ..self }
+    }
+
+    /// Restricts the query to documents also matching this filter. It may
+    /// constrain the `_id` too, in addition to the time range.
+    pub fn filter(self, filter: Document) -> Self {
+        CreatedBetween { filter, ..self }
+    }
+
+    /// Returns at most this many documents.
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn limit(self, n: usize) -> Self {
+        CreatedBetween { limit: Some(n as i64), ..self }
+    }
+}
+
+impl<T: Doc<Id = ObjectId>> Query<T> for CreatedBetween<T> {
+    type Output = T;
+
+    fn filter(&self) -> Document {
+        let lower = if self.exclusive { "$gt" } else { "$gte" };
+        let range = doc!{
+            lower: &self.start,
+            "$lte": &self.end,
+        };
+
+        // A condition on `_id` in the additional filter must hold too.
+        if self.filter.contains_key("_id") {
+            doc!{ "$and": [ self.filter.clone(), { "_id": range } ] }
+        } else {
+            let mut filter = self.filter.clone();
+            filter.insert("_id", range);
+            filter
+        }
+    }
+
+    fn options(&self) -> FindOptions {
+        FindOptions {
+            sort: Some(doc!{ "_id": 1 }),
+            limit: self.limit,
+            ..T::query_options()
+        }
+    }
+
+    fn transformer(&self) -> Option<BoxedTransform> {
+        None
+    }
+}
+
+impl<T: Doc<Id = ObjectId>> Clone for CreatedBetween<T> {
+    fn clone(&self) -> Self {
+        CreatedBetween {
+            start: self.start.clone(),
+            end: self.end.clone(),
+            filter: self.filter.clone(),
+            ..*self
+        }
+    }
+}
+
+impl<T: Doc<Id = ObjectId>> Debug for CreatedBetween<T> {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter
+            .debug_struct("CreatedBetween")
+            .field("start", &self.start)
+            .field("exclusive", &self.exclusive)
+            .field("end", &self.end)
+            .field("filter", &self.filter)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
@@ -1,5 +1,10 @@
 //! Common utility functions and types.
 
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+use std::sync::{ Mutex, MutexGuard };
+use std::hash::{ BuildHasher, Hasher };
+use std::collections::hash_map::RandomState;
+use bson::Bson;
 use crate::error::{ Error, ErrorKind, Result };
 
 /// Converts an `i8`, `i16`, `i32` or `i64` to a `usize` if the range and
@@ -31,10 +36,45 @@ pub fn int_to_usize_with_msg<T: Into<i64>>(x: T, msg: &str) -> Result<usize> {
     }
 }
 
+/// Returns the number of milliseconds elapsed between the Unix epoch and
+/// `time`, or 0 if `time` is before the epoch.
+pub fn unix_millis(time: SystemTime) -> i64 {
+    duration_millis(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+/// Returns the length of `duration` in whole milliseconds.
+#[allow(clippy::cast_possible_wrap)]
+pub fn duration_millis(duration: Duration) -> i64 {
+    duration.as_secs() as i64 * 1000 + i64::from(duration.subsec_millis())
+}
+
+/// Converts a point in time to a BSON UTC datetime, with millisecond precision.
+pub fn bson_date(time: SystemTime) -> Bson {
+    Bson::from_extended_document(doc!{ "$date": { "$numberLong": unix_millis(time) } })
+}
+
+/// Returns a pseudo-random number. It's not suitable for cryptography, but
+/// it's seeded by the OS, so it differs between processes. We just use the
+/// randomly-keyed hasher of the standard library instead of a dependency.
+pub fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Locks `mutex`, ignoring poisoning. Only use it for state that a panic
+/// can't leave inconsistent, e.g. because it's only ever replaced as a whole.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::i64;
-    use super::int_to_usize_with_msg;
+    use std::time::{ Duration, UNIX_EPOCH };
+    use bson::Bson;
+    use super::{ int_to_usize_with_msg, bson_date };
     use crate::error::{ Error, ErrorKind, Result };
 
     #[test]
@@ -72,4 +112,14 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn bson_date_has_millisecond_precision() {
+        let time = UNIX_EPOCH + Duration::new(1_500_000_000, 123_456_789);
+
+        match bson_date(time) {
+            Bson::UtcDatetime(date) => assert_eq!(date.timestamp_millis(), 1_500_000_000_123),
+            other => panic!("expected a datetime, got {:?}", other),
+        }
+    }
 }
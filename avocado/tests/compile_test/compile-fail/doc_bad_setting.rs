@@ -0,0 +1,16 @@
+#[macro_use]
+extern crate avocado_derive;
+extern crate avocado;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+
+use avocado::prelude::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)] //~ ERROR proc-macro derive panicked
+#[avocado(nonexistent_setting)] //~| bad path attribute: nonexistent_setting
+struct MyDoc {
+    _id: Uid<MyDoc>,
+}
+
+fn main() {}
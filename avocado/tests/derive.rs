@@ -379,3 +379,132 @@ fn doc_index_last_field_overwrites_previous() {
         ]
     );
 }
+
+#[test]
+fn doc_field_paths() {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Address {
+        zip: String,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[serde(rename_all = "camelCase")]
+    #[avocado(fields)]
+    struct Customer {
+        #[serde(rename = "_id")]
+        id: Uid<Customer>,
+        legal_name: String,
+        #[serde(rename = "addr")]
+        address: Address,
+        phone_numbers: Vec<String>,
+        #[serde(skip)]
+        cached_score: u32,
+    }
+
+    const ZIP: Field<Address, String> = Field::new("zip");
+
+    let id: Field<Customer, Uid<Customer>> = Customer::id;
+    let legal_name: Field<Customer, String> = Customer::legal_name;
+    let phone_number: Field<Customer, String> = Customer::phone_numbers.each();
+
+    assert_eq!(id.path(), "_id");
+    assert_eq!(legal_name.path(), "legalName");
+    assert_eq!(Customer::address.join(ZIP).path(), "addr.zip");
+    assert_eq!(phone_number.path(), "phoneNumbers");
+
+    // The elements of arrays are returned as separate distinct values
+    type DistinctOf<Q> = <Q as Distinct<Customer>>::Output;
+
+    assert_eq!(TypeId::of::<DistinctOf<Field<Customer, Vec<String>>>>(), TypeId::of::<String>());
+    assert_eq!(TypeId::of::<DistinctOf<Field<Customer, String>>>(), TypeId::of::<String>());
+    assert_eq!(TypeId::of::<DistinctOf<Field<Customer, Option<u32>>>>(), TypeId::of::<Option<u32>>());
+}
+
+#[test]
+fn doc_sequence() {
+    use avocado::sequence::Sequence;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[id_type = "i64"]
+    #[avocado(sequence)]
+    struct Consecutive {
+        _id: Option<Uid<Consecutive>>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[id_type = "i64"]
+    #[avocado(sequence(step = 10, batch = 50))]
+    struct Batched {
+        _id: Option<Uid<Batched>>,
+    }
+
+    assert_eq!(Consecutive::sequence(), Some(Sequence::default()));
+    assert_eq!(Batched::sequence(), Some(Sequence { step: 10, batch: 50 }));
+    assert_eq!(<Sequence as Default>::default(), Sequence { step: 1, batch: 1 });
+}
+
+#[test]
+fn doc_id_generator() -> avocado::error::Result<()> {
+    use avocado::idgen::Ulid;
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[id_type = "String"]
+    #[avocado(id_generator = "Ulid")]
+    struct Named {
+        _id: Option<Uid<Named>>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[id_type = "i64"]
+    #[avocado(id_generator = "avocado::idgen::Snowflake")]
+    struct Numbered {
+        _id: Option<Uid<Numbered>>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    struct ServerAssigned {
+        _id: Option<Uid<ServerAssigned>>,
+    }
+
+    let first = Named::generate_id()?.expect("no ULID generated");
+    let second = Named::generate_id()?.expect("no ULID generated");
+
+    assert_eq!(first.as_ref().len(), 26);
+    assert!(first < second);
+    assert!(Numbered::generate_id()?.map_or(false, |id| id.into_raw() > 0));
+    assert!(ServerAssigned::generate_id()?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn doc_compound_id() -> avocado::error::Result<()> {
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct SkuKey {
+        tenant: String,
+        sku: u32,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+    #[id_type = "SkuKey"]
+    struct Item {
+        #[serde(rename = "_id")]
+        key: Uid<Item>,
+        price: f64,
+    }
+
+    assert_doc_impl!(Doc: Item, Id: SkuKey, name: Item, index: &[]);
+
+    let key = SkuKey { tenant: "acme".into(), sku: 42 };
+    let mut item = Item { key: Uid::from_raw(key.clone()), price: 9.5 };
+
+    // Fields of the embedded `_id` are in declaration order
+    assert_eq!(item.key.to_bson()?, bson!({ "tenant": "acme", "sku": 42_i64 }));
+    assert_eq!(item.id().map(|id| id.as_ref()), Some(&key));
+
+    let other = SkuKey { tenant: "acme".into(), sku: 43 };
+    item.set_id(Uid::from_raw(other.clone()));
+    assert_eq!(item.key.into_raw(), other);
+
+    Ok(())
+}
@@ -30,6 +30,13 @@ use std::collections::{ HashSet, BTreeSet, BTreeMap };
 use std::process::{ Command, Child, Stdio };
 use avocado::error::Result;
 use avocado::prelude::*;
+use avocado::text::TextSearch;
+use avocado::explain::Verbosity;
+use avocado::timeseries::{ Buckets, Window, WindowBounds, TimeUnit };
+use avocado::queue::{ Queue, Schedule, Nack, QueueStats, DeadLetter };
+use avocado::lock::{ Lock, LockRecord, LockFence };
+use avocado::sequence::Counter;
+use avocado::uid::CreatedBetween;
 
 /// Used for killing the MongoDB server process once all tests have run.
 struct ProcessGuard {
@@ -144,6 +151,7 @@ enum Vcs {
 
 #[derive(Debug, Clone, Serialize, Deserialize, BsonSchema, Doc)]
 #[id_type = "u64"]
+#[avocado(fields)]
 struct Issue {
     #[serde(rename = "_id")]
     number: Uid<Issue>,
@@ -189,6 +197,69 @@ struct PullRequest {
     lines_changed: usize,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BsonSchema, Doc)]
+#[index(keys(title = "text", body = "text"), default_language = "english")]
+struct Article {
+    _id: Uid<Article>,
+    title: String,
+    body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BsonSchema, Doc)]
+#[avocado(capped(size = 4096, max = 3))]
+struct Event {
+    _id: Uid<Event>,
+    seq: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[avocado(fields)]
+#[avocado(time_series(time_field = "ts", meta_field = "sensor", granularity = "minutes"))]
+struct Reading {
+    _id: Uid<Reading>,
+    ts: bson::UtcDateTime,
+    sensor: String,
+    value: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+struct Task {
+    _id: Uid<Task>,
+    name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[id_type = "i64"]
+#[avocado(sequence(step = 10, batch = 2))]
+struct Ticket {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<Uid<Ticket>>,
+    title: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Doc)]
+#[id_type = "String"]
+#[avocado(id_generator = "avocado::idgen::Ulid")]
+struct Order {
+    #[serde(rename = "_id")]
+    id: Option<Uid<Order>>,
+    total: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct SkuKey {
+    tenant: String,
+    sku: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Doc)]
+#[id_type = "SkuKey"]
+struct StockItem {
+    #[serde(rename = "_id")]
+    key: Uid<StockItem>,
+    quantity: u32,
+}
+
 // Finally, the actual tests.
 
 implement_tests!{
@@ -500,7 +571,9 @@ implement_tests!{
         impl Distinct<Issue> for ResolvedValues {
             type Output = i64;
 
-            const FIELD: &'static str = "resolved";
+            fn field(&self) -> &str {
+                "resolved"
+            }
 
             fn transform(raw: Bson) -> Result<Bson> {
                 Ok(match raw {
@@ -517,6 +590,21 @@ implement_tests!{
         assert_eq!(bits,     etalon);
         assert_eq!(bits_ref, etalon);
 
+        // The output type of a typed field path is that of the field itself
+        let flags: Vec<bool> = issues.distinct(Issue::resolved)?;
+        let open: Vec<bool> = issues.distinct((
+            Issue::resolved,
+            doc!{ "assignee": null },
+        ))?;
+        let numbers: BTreeSet<Uid<Issue>> = issues.distinct(Issue::number)?;
+
+        assert_eq!(flags.into_iter().collect::<BTreeSet<_>>(),
+                   vec![false, true].into_iter().collect());
+        assert_eq!(open.into_iter().collect::<BTreeSet<_>>(),
+                   vec![false, true].into_iter().collect());
+        assert_eq!(numbers,
+                   issue_entities.iter().map(|issue| issue.number.clone()).collect());
+
         // Testing the `Pipeline` trait
 
         #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
@@ -692,6 +780,1288 @@ implement_tests!{
         Ok(())
     }
 
+    #[test]
+    fn text_search() -> Result<()> {
+        let articles: Collection<Article> = DB_HANDLE.empty_collection()?;
+        let article = |title: &str, body: &str| -> Result<Article> {
+            Ok(Article {
+                _id: Uid::new_oid()?,
+                title: title.into(),
+                body: body.into(),
+            })
+        };
+
+        let entities = vec![
+            article("Avocados", "Growing avocados and more avocados")?,
+            article("Guacamole", "A dip made of avocado, lime and salt")?,
+            article("Bananas", "Nothing to see here")?,
+        ];
+        articles.insert_many(&entities)?;
+
+        let hits: Vec<_> = articles
+            .find_many(TextSearch::new("avocado").sort_by_score())?
+            .collect::<Result<_>>()?;
+
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].value, entities[0]);
+        assert_eq!(hits[1].value, entities[1]);
+        assert!(hits[0].score > hits[1].score);
+
+        // Additional filters and exclusions are applied on top of `$text`
+        let guac = articles.find_one(
+            TextSearch::new("avocado -growing").case_sensitive(false)
+        )?.expect("negated term excluded everything");
+        assert_eq!(guac.value, entities[1]);
+
+        let none = articles.find_one(
+            TextSearch::new("avocado").filter(doc!{ "title": "Bananas" })
+        )?;
+        assert!(none.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn explain_index_usage() -> Result<()> {
+        let users: Collection<User> = DB_HANDLE.empty_collection()?;
+        let entities: Vec<_> = (0..10).map(|i| User {
+            _id: Uid::new_oid().expect("can't generate ObjectId"),
+            legal_name: format!("User #{}", i),
+            username: format!("user_{}", i),
+            repos: HashSet::new(),
+            groups: HashSet::new(),
+        }).collect();
+
+        users.insert_many(&entities)?;
+
+        // Queries on the indexed field use the index
+        let plan = users.explain_find(
+            doc!{ "username": "user_3" },
+            Verbosity::ExecutionStats,
+        )?;
+        assert!(plan.uses_index("username"));
+        assert!(!plan.is_collection_scan());
+        assert_eq!(plan.n_returned(), Some(1));
+        assert_eq!(plan.docs_examined(), Some(1));
+
+        // Queries on other fields have to scan the collection
+        let plan = users.explain_count(
+            doc!{ "legal_name": "User #3" },
+            Verbosity::QueryPlanner,
+        )?;
+        assert!(plan.is_collection_scan());
+        assert!(plan.indexes_used().is_empty());
+        assert!(plan.execution_stats.is_none());
+
+        // The initial `$match` of a pipeline can use an index too
+        #[derive(Debug, Clone, Copy)]
+        struct CountSomeUsers;
+
+        impl Pipeline<User> for CountSomeUsers {
+            type Output = Document;
+
+            fn stages(&self) -> Vec<Document> {
+                vec![
+                    doc!{ "$match": { "username": { "$in": ["user_1", "user_2"] } } },
+                    doc!{ "$group": { "_id": null, "n": { "$sum": 1 } } },
+                ]
+            }
+        }
+
+        let plan = users.explain_aggregate(CountSomeUsers, Verbosity::ExecutionStats)?;
+        assert!(plan.uses_index("username"));
+        assert_eq!(plan.keys_examined().map(|n| n >= 2), Some(true));
+
+        // Commands the server refuses to explain report the server error
+        #[derive(Debug, Clone, Copy)]
+        struct BogusStage;
+
+        impl Pipeline<User> for BogusStage {
+            type Output = Document;
+
+            fn stages(&self) -> Vec<Document> {
+                vec![doc!{ "$bogus": {} }]
+            }
+        }
+
+        let error = users.explain_aggregate(BogusStage, Verbosity::QueryPlanner).unwrap_err();
+        let server_error = error.server_error().expect("no server error details");
+        assert_eq!(server_error.code, 40324);
+        assert!(server_error.message.contains("$bogus"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn array_element_updates() -> Result<()> {
+        use avocado::field::Field;
+
+        let repos: Collection<Repo> = DB_HANDLE.empty_collection()?;
+        let issue = |number, assignee: Option<Uid<User>>| Issue {
+            number: Uid::from_raw(number),
+            description: format!("issue #{}", number),
+            opened: Uid::new_oid().expect("can't generate ObjectId"),
+            assignee,
+            resolved: false,
+        };
+        let repo = Repo {
+            _id: Uid::new_oid()?,
+            owner: Uid::new_oid()?,
+            name: String::from("elements"),
+            url: String::from("githoob.com/jdoe/elements.git"),
+            vcs: Vcs::Hg,
+            issues: vec![
+                issue(1, None),
+                issue(2, Some(Uid::new_oid()?)),
+                issue(3, None),
+            ],
+        };
+        repos.insert_one(&repo)?;
+
+        let issues: Field<Repo, Vec<Issue>> = Field::new("issues");
+
+        // The positional operator `$` refers to the element matched by the filter
+        #[derive(Debug)]
+        struct Describe<'a>(&'a Uid<Repo>, Field<Repo, String>);
+
+        impl<'a> Update<Repo> for Describe<'a> {
+            fn filter(&self) -> Document {
+                doc!{ "_id": self.0, "issues._id": 2 }
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { (self.1.path()): "assigned already" } }
+            }
+        }
+
+        let description = issues.clone().first_matched().join(Issue::description);
+        let result = repos.update_one(Describe(&repo._id, description))?;
+        assert!(result.matched && result.modified);
+
+        // Array filters select elements for the filtered positional operator
+        #[derive(Debug)]
+        struct ResolveUnassigned;
+
+        impl Update<Repo> for ResolveUnassigned {
+            fn filter(&self) -> Document {
+                doc!{}
+            }
+
+            fn update(&self) -> Document {
+                let resolved = Field::<Repo, Vec<Issue>>::new("issues")
+                    .filtered("unassigned")
+                    .join(Issue::resolved);
+
+                doc!{ "$set": { (resolved.path()): true } }
+            }
+
+            fn options(&self) -> UpdateOptions {
+                let assignee = Issue::assignee.with_identifier("unassigned");
+                Repo::update_options().array_filters(vec![doc!{ (assignee.path()): null }])
+            }
+        }
+
+        let result = repos.update_many(ResolveUnassigned)?;
+        assert_eq!(result.num_matched, 1);
+        assert_eq!(result.num_modified, 1);
+
+        let updated = repos.find_one(doc!{ "_id": &repo._id })?.expect("repo not found");
+        let states: Vec<_> = updated.issues
+            .iter()
+            .map(|issue| (issue.description.as_str(), issue.resolved))
+            .collect();
+        assert_eq!(states, [
+            ("issue #1", true),
+            ("assigned already", false),
+            ("issue #3", true),
+        ]);
+
+        // `$[]` refers to every element
+        #[derive(Debug)]
+        struct ReopenAll(Field<Repo, bool>);
+
+        impl Update<Repo> for ReopenAll {
+            fn filter(&self) -> Document {
+                doc!{}
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { (self.0.path()): false } }
+            }
+        }
+
+        let every = issues.all_elements().join(Issue::resolved);
+        let result = repos.update_many(ReopenAll(every))?;
+        assert_eq!(result.num_modified, 1);
+
+        let updated = repos.find_one(doc!{ "_id": &repo._id })?.expect("repo not found");
+        assert!(updated.issues.iter().all(|issue| !issue.resolved));
+
+        // Identifiers without an array filter make the whole command fail
+        #[derive(Debug)]
+        struct ResolveUndefined;
+
+        impl Update<Repo> for ResolveUndefined {
+            fn filter(&self) -> Document {
+                doc!{}
+            }
+
+            fn update(&self) -> Document {
+                let resolved = Field::<Repo, Vec<Issue>>::new("issues")
+                    .filtered("undefined")
+                    .join(Issue::resolved);
+
+                doc!{ "$set": { (resolved.path()): true } }
+            }
+
+            fn options(&self) -> UpdateOptions {
+                let assignee = Issue::assignee.with_identifier("unassigned");
+                Repo::update_options().array_filters(vec![doc!{ (assignee.path()): null }])
+            }
+        }
+
+        let error = repos.update_many(ResolveUndefined).unwrap_err();
+        let server_error = error.server_error().expect("no server error details");
+        assert_ne!(server_error.code, 0);
+        assert!(server_error.message.contains("undefined"));
+
+        let updated = repos.find_one(doc!{ "_id": &repo._id })?.expect("repo not found");
+        assert!(updated.issues.iter().all(|issue| !issue.resolved));
+
+        // Array filters work with `find_one_and_update()` too
+        #[derive(Debug)]
+        struct DescribeThird;
+
+        impl FindAndUpdate<Repo> for DescribeThird {
+            type Output = Repo;
+
+            fn filter(&self) -> Document {
+                doc!{}
+            }
+
+            fn update(&self) -> Document {
+                let description = Field::<Repo, Vec<Issue>>::new("issues")
+                    .filtered("third")
+                    .join(Issue::description);
+
+                doc!{ "$set": { (description.path()): "the third one" } }
+            }
+
+            fn options(&self) -> FindOneAndUpdateOptions {
+                FindOneAndUpdateOptions {
+                    return_document: Some(ReturnDocument::After),
+                    ..Default::default()
+                }
+            }
+
+            fn update_options(&self) -> UpdateOptions {
+                let number = Issue::number.with_identifier("third");
+                UpdateOptions::default().array_filters(vec![doc!{ (number.path()): 3 }])
+            }
+        }
+
+        let updated = repos.find_one_and_update(DescribeThird)?.expect("repo not found");
+        let descriptions: Vec<_> = updated.issues
+            .iter()
+            .map(|issue| issue.description.as_str())
+            .collect();
+        assert_eq!(descriptions, ["issue #1", "assigned already", "the third one"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn collated_writes() -> Result<()> {
+        let repos: Collection<Repo> = DB_HANDLE.empty_collection()?;
+        let repo = Repo {
+            _id: Uid::new_oid()?,
+            owner: Uid::new_oid()?,
+            name: String::from("Collated"),
+            url: String::from("githoob.com/jdoe/collated.git"),
+            vcs: Vcs::Git,
+            issues: Vec::new(),
+        };
+        repos.insert_one(&repo)?;
+
+        // Strength 2 compares strings case-insensitively
+        let collation = doc!{ "locale": "en", "strength": 2 };
+
+        #[derive(Debug)]
+        struct MoveRepo(Option<Document>);
+
+        impl Update<Repo> for MoveRepo {
+            fn filter(&self) -> Document {
+                doc!{ "name": "collated" }
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { "url": "githoob.com/jdoe/moved.git" } }
+            }
+
+            fn options(&self) -> UpdateOptions {
+                UpdateOptions { collation: self.0.clone(), ..Default::default() }
+            }
+        }
+
+        let result = repos.update_one(MoveRepo(None))?;
+        assert!(!result.matched);
+
+        let result = repos.update_one(MoveRepo(Some(collation.clone())))?;
+        assert!(result.matched && result.modified);
+
+        #[derive(Debug)]
+        struct DeleteRepo(Option<Document>);
+
+        impl Delete<Repo> for DeleteRepo {
+            fn filter(&self) -> Document {
+                doc!{ "name": "COLLATED" }
+            }
+
+            fn options(&self) -> DeleteOptions {
+                DeleteOptions { collation: self.0.clone(), ..Default::default() }
+            }
+        }
+
+        assert_eq!(repos.delete_many(DeleteRepo(None))?, 0);
+        assert_eq!(repos.delete_many(DeleteRepo(Some(collation)))?, 1);
+        assert_eq!(repos.count(doc!{})?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_preference_and_concern() -> Result<()> {
+        let users: Collection<User> = DB_HANDLE.empty_collection()?;
+        let entities: Vec<_> = (0..4).map(|i| User {
+            _id: Uid::new_oid().expect("can't generate ObjectId"),
+            legal_name: format!("Reader #{}", i),
+            username: format!("reader_{}", i),
+            repos: HashSet::new(),
+            groups: HashSet::new(),
+        }).collect();
+
+        users.insert_many(&entities)?;
+
+        // A standalone server satisfies these on behalf of the primary
+        let readers = users
+            .with_read_preference(ReadPreference::PrimaryPreferred {
+                tag_sets: None,
+                max_staleness: None,
+            })
+            .with_read_concern(ReadConcern::Local);
+
+        assert_eq!(readers.count(doc!{})?, entities.len());
+        assert_eq!(readers.find_many(doc!{})?.count(), entities.len());
+
+        let username: Field<User, String> = Field::new("username");
+        let names: BTreeSet<String> = readers.distinct(username)?;
+        assert_eq!(names.len(), entities.len());
+
+        // Per-query options override the defaults of the collection handle
+        #[derive(Debug)]
+        struct MajorityReader<'a>(&'a str);
+
+        impl<'a> Query<User> for MajorityReader<'a> {
+            type Output = User;
+
+            fn filter(&self) -> Document {
+                doc!{ "username": self.0 }
+            }
+
+            fn read_options(&self) -> ReadOptions {
+                ReadOptions::default()
+                    .read_preference(ReadPreference::Primary)
+                    .read_concern(ReadConcern::Majority)
+            }
+        }
+
+        let user = readers.find_one(MajorityReader("reader_2"))?.expect("user not found");
+        assert_eq!(user._id, entities[2]._id);
+
+        Ok(())
+    }
+
+    #[test]
+    fn capped_collection_tail() -> Result<()> {
+        use std::thread;
+
+        let events: Collection<Event> = DB_HANDLE.empty_collection()?;
+        let event = |seq| Event {
+            _id: Uid::new_oid().expect("can't generate ObjectId"),
+            seq,
+        };
+
+        for seq in 0..5 {
+            events.insert_one(&event(seq))?;
+        }
+
+        // Only the last `max` documents are retained
+        assert_eq!(events.count(doc!{})?, 3);
+
+        let mut cursor = events.tail(doc!{})?;
+        let retained: Vec<_> = cursor
+            .by_ref()
+            .take(3)
+            .map(|result| result.map(|event| event.seq))
+            .collect::<Result<_>>()?;
+        assert_eq!(retained, [2, 3, 4]);
+
+        // The cursor then blocks until another document is inserted
+        let writer = thread::spawn(move || {
+            let events: Collection<Event> = DB_HANDLE.existing_collection();
+            events.insert_one(&event(5))
+        });
+
+        let next = cursor.next().expect("tailable cursor ended")?;
+        writer.join().expect("writer thread panicked")?;
+        assert_eq!(next.seq, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn capped_collection_tail_empty() -> Result<()> {
+        let events: Collection<Event> = DB_HANDLE.empty_collection()?;
+
+        // The server doesn't keep a tailable cursor open on an empty
+        // collection, so iteration must end instead of polling forever.
+        let mut cursor = events.tail(doc!{})?;
+        assert!(cursor.next().is_none());
+        assert!(cursor.next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn time_series_buckets_and_windows() -> Result<()> {
+        let readings: Collection<Reading> = DB_HANDLE.empty_collection_novalidate()?;
+        let reading = |sensor: &str, minutes: i64| {
+            // An hour boundary, so that buckets contain whole hours
+            let millis = 1_599_998_400_000 + minutes * 60_000;
+            let date = Bson::from_extended_document(doc!{ "$date": { "$numberLong": millis } });
+
+            Reading {
+                _id: Uid::new_oid().expect("can't generate ObjectId"),
+                ts: bson::from_bson(date).expect("can't create UtcDateTime"),
+                sensor: sensor.into(),
+                value: 1.0,
+            }
+        };
+        let entities: Vec<_> = ["a", "b"]
+            .iter()
+            .flat_map(|&sensor| (0..5).map(move |i| reading(sensor, i * 20)))
+            .collect();
+
+        readings.insert_many(&entities)?;
+
+        #[derive(Debug, Deserialize)]
+        struct Hourly {
+            group: String,
+            count: i32,
+        }
+
+        let hourly = Buckets::<Reading, Hourly>::new(Reading::ts, TimeUnit::Hour, 1)
+            .group_by(Reading::sensor)
+            .accumulate("count", doc!{ "$sum": 1 });
+        let buckets: Vec<_> = readings
+            .aggregate(hourly)?
+            .map(|result| result.map(|bucket| (bucket.group, bucket.count)))
+            .collect::<Result<_>>()?;
+
+        assert_eq!(buckets, [
+            (String::from("a"), 3),
+            (String::from("b"), 3),
+            (String::from("a"), 2),
+            (String::from("b"), 2),
+        ]);
+
+        // Running totals of the current and the previous reading, and
+        // the previous reading itself, which takes no window
+        let window = Window::<Reading>::new(Reading::ts)
+            .partition_by(Reading::sensor)
+            .matching(doc!{ "sensor": "a" })
+            .output("pair", doc!{ "$sum": "$value" }, WindowBounds::Documents(-1, 0))
+            .output_unbounded("previous", doc!{
+                "$shift": { "output": "$value", "by": -1, "default": 0.0 }
+            });
+        let sums: Vec<(f64, f64)> = readings
+            .aggregate(window)?
+            .map(|result| result.and_then(|document| {
+                Ok((document.get_f64("pair")?, document.get_f64("previous")?))
+            }))
+            .collect::<Result<_>>()?;
+
+        assert_eq!(sums, [(1.0, 0.0), (2.0, 1.0), (2.0, 1.0), (2.0, 1.0), (2.0, 1.0)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn job_queue() -> Result<()> {
+        use std::time::Duration;
+
+        let dead_letters = DB_HANDLE.empty_collection_novalidate::<DeadLetter<Task>>()?;
+        let policy = RetryPolicy::new(2)
+            .initial_backoff(Duration::from_secs(0))
+            .jitter(0.0);
+        let queue = Queue::new(DB_HANDLE.empty_collection_novalidate()?, dead_letters)?
+            .visibility_timeout(Duration::from_secs(60))
+            .retry_policy(policy);
+        let task = |name: &str| Task {
+            _id: Uid::new_oid().expect("can't generate ObjectId"),
+            name: name.into(),
+        };
+
+        let low = task("low");
+        let high = task("high");
+        let later = task("later");
+
+        assert!(queue.enqueue(&low)?);
+        assert!(!queue.enqueue(&low)?);
+        assert!(queue.enqueue_with(&high, Schedule::default().priority(5))?);
+        assert!(queue.enqueue_with(&later, Schedule::default().delay(Duration::from_secs(3600)))?);
+        assert_eq!(queue.stats()?, QueueStats { ready: 2, leased: 0, delayed: 1, dead: 0 });
+
+        // Higher priority first, delayed jobs not at all
+        let first = queue.lease()?.expect("no job leased");
+        let second = queue.lease()?.expect("no job leased");
+        assert_eq!(first.job().name, "high");
+        assert_eq!(second.job().name, "low");
+        assert_eq!(second.attempts(), 1);
+        assert!(queue.lease()?.is_none());
+        assert_eq!(queue.stats()?, QueueStats { ready: 0, leased: 2, delayed: 1, dead: 0 });
+
+        assert!(queue.ack(&first)?);
+        assert!(!queue.ack(&first)?);
+
+        // A failed job is retried, then dead-lettered when out of attempts
+        assert_eq!(queue.nack(&second, "boom")?, Nack::Retry(Duration::from_secs(0)));
+        let retried = queue.lease()?.expect("failed job not retried");
+        assert_eq!(retried.job().name, "low");
+        assert_eq!(retried.attempts(), 2);
+        assert_eq!(queue.nack(&retried, "boom again")?, Nack::DeadLettered);
+        assert_eq!(queue.nack(&retried, "boom again")?, Nack::LeaseLost);
+        assert_eq!(queue.stats()?, QueueStats { ready: 0, leased: 0, delayed: 1, dead: 1 });
+
+        let letter = DB_HANDLE
+            .existing_collection::<DeadLetter<Task>>()
+            .find_one(doc!{ "queue": "Task" })?
+            .expect("no dead letter");
+        assert_eq!(letter.job.name, "low");
+        assert_eq!(letter.attempts, 2);
+        assert_eq!(letter.error, "boom again");
+        assert_eq!(letter.id.as_ref().job, low._id.to_bson()?);
+
+        // Burying is idempotent: if the job is still in the queue after its
+        // dead letter was inserted, e.g. because the worker crashed, burying
+        // it again only deletes the job
+        #[derive(Debug)]
+        struct Restore(Bson, bson::oid::ObjectId);
+
+        impl Update<Task> for Restore {
+            fn filter(&self) -> Document {
+                doc!{ "_id": self.0.clone() }
+            }
+
+            fn update(&self) -> Document {
+                doc!{
+                    "$set": {
+                        "_queue": {
+                            "priority": 0,
+                            "attempts": 2,
+                            "lease": self.1.clone(),
+                            "last_error": Bson::Null,
+                        }
+                    }
+                }
+            }
+        }
+
+        let tasks = DB_HANDLE.existing_collection::<Task>();
+        let letter_id = letter.id.as_ref();
+
+        tasks.insert_one(&low)?;
+        assert!(tasks.update_one(Restore(letter_id.job.clone(), letter_id.lease.clone()))?.matched);
+        assert_eq!(queue.nack(&retried, "boom again")?, Nack::DeadLettered);
+        assert_eq!(queue.stats()?, QueueStats { ready: 0, leased: 0, delayed: 1, dead: 1 });
+
+        // A lease expiring at once makes the job available again right away
+        let expiring = Queue::new(DB_HANDLE.existing_collection(), DB_HANDLE.existing_collection())?
+            .visibility_timeout(Duration::from_secs(0))
+            .retry_policy(policy);
+        let crashy = task("crashy");
+
+        assert!(expiring.enqueue(&crashy)?);
+
+        let stale = expiring.lease()?.expect("no job leased");
+        let fresh = expiring.lease()?.expect("expired job not leased again");
+        assert_eq!(fresh.job().name, "crashy");
+        assert_eq!(fresh.attempts(), 2);
+        assert!(!expiring.ack(&stale)?);
+        assert_eq!(expiring.nack(&stale, "too late")?, Nack::LeaseLost);
+
+        // Once leased too many times, it goes to the dead letters
+        assert!(expiring.lease()?.is_none());
+        assert!(!expiring.ack(&fresh)?);
+        assert_eq!(expiring.stats()?, QueueStats { ready: 0, leased: 0, delayed: 1, dead: 2 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn distributed_lock() -> Result<()> {
+        use std::time::Duration;
+
+        let locks: Collection<LockRecord> = DB_HANDLE.empty_collection_novalidate()?;
+        let fences: Collection<LockFence> = DB_HANDLE.empty_collection_novalidate()?;
+        let ttl = Duration::from_secs(60);
+
+        let mut first = Lock::try_acquire(&locks, "cron", ttl)?.expect("free lock not acquired");
+        assert_eq!(first.name(), "cron");
+        assert_eq!(first.fence(), 1);
+        assert!(!first.is_expired());
+        assert!(Lock::try_acquire(&locks, "cron", ttl)?.is_none());
+        assert!(Lock::acquire(&locks, "cron", ttl, Duration::from_millis(300))?.is_none());
+
+        // Locks with different names are independent
+        let other = Lock::try_acquire(&locks, "backup", ttl)?.expect("other lock not acquired");
+        assert_eq!(other.fence(), 1);
+
+        assert!(first.renew()?);
+        assert!(first.release()?);
+
+        let second = Lock::acquire(&locks, "cron", ttl, Duration::from_secs(1))?
+            .expect("released lock not acquired");
+        assert_eq!(second.fence(), 2);
+        assert!(second.release()?);
+
+        // An expired lock can be taken over, and the fencing token still grows
+        let mut stale = Lock::try_acquire(&locks, "cron", Duration::from_secs(0))?
+            .expect("released lock not acquired");
+        assert!(stale.is_expired());
+
+        let current = Lock::try_acquire(&locks, "cron", ttl)?.expect("expired lock not acquired");
+        assert_eq!(stale.fence(), 3);
+        assert_eq!(current.fence(), 4);
+        assert!(!stale.renew()?);
+        assert!(!stale.release()?);
+        assert!(Lock::try_acquire(&locks, "cron", ttl)?.is_none());
+
+        // Records of expired locks are deleted by a TTL index, but their
+        // fencing tokens are kept separately, so they don't restart
+        assert!(current.release()?);
+        assert!(locks.delete_one(doc!{ "_id": "cron" })?);
+
+        let revived = Lock::try_acquire(&locks, "cron", ttl)?.expect("deleted lock not acquired");
+        assert_eq!(revived.fence(), 5);
+        assert_eq!(fences.count(doc!{})?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sequential_ids() -> Result<()> {
+        let counters: Collection<Counter> = DB_HANDLE.empty_collection_novalidate()?;
+        let tickets: Collection<Ticket> = DB_HANDLE.empty_collection_novalidate()?;
+        let ticket = |title: &str| Ticket { id: None, title: title.into() };
+
+        // Batches of 2 IDs, 10 apart: the first one comes from the database,
+        // the second one from memory, and the third one from a new batch
+        let first = tickets.insert_one(&ticket("first"))?;
+        let second = Uid::<Ticket>::next_sequence(&DB_HANDLE)?;
+        let third = tickets.insert_one(&ticket("third"))?;
+
+        assert_eq!(first.into_raw(), 10);
+        assert_eq!(second.into_raw(), 20);
+        assert_eq!(third.into_raw(), 30);
+        assert_eq!(counters.find_one(doc!{ "_id": "Ticket" })?.map(|c| c.value), Some(40));
+
+        // Existing IDs are left intact, and `insert_many` fills in missing ones
+        let explicit = Ticket { id: Some(Uid::from_raw(5)), title: "explicit".into() };
+        let ids = tickets.insert_many(vec![explicit, ticket("fourth")])?;
+
+        assert_eq!(ids.values().cloned().map(Uid::into_raw).collect::<Vec<_>>(), [5, 40]);
+
+        let stored = tickets.find_one(doc!{ "title": "fourth" })?.expect("ticket not found");
+        assert_eq!(stored.id.map(Uid::into_raw), Some(40));
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_generated_ids() -> Result<()> {
+        let orders: Collection<Order> = DB_HANDLE.empty_collection_novalidate()?;
+        let order = |total: u32| Order { id: None, total };
+
+        let first = orders.insert_one(&order(1))?;
+        let more = orders.insert_many(vec![order(2), order(3)])?;
+        let mut ids = vec![first];
+        ids.extend(more.values().cloned());
+
+        assert!(ids.iter().all(|id| id.as_ref().len() == 26));
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let mut stored: Vec<_> = orders
+            .find_many(doc!{})?
+            .map(|result| result.map(|order| (order.id, order.total)))
+            .collect::<Result<_>>()?;
+        stored.sort();
+        let expected: Vec<_> = ids.into_iter().map(Some).zip(1..).collect();
+
+        assert_eq!(stored, expected);
+
+        // Explicitly given IDs are kept intact
+        let explicit = Uid::from_raw(String::from("explicit"));
+        let order = Order { id: Some(explicit.clone()), total: 4 };
+        assert_eq!(orders.insert_one(&order)?, explicit);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_mut_assigns_ids() -> Result<()> {
+        let commits: Collection<Commit> = DB_HANDLE.empty_collection_novalidate()?;
+        let commit = |hash: &str| Commit { id: None, hash: hash.into() };
+
+        // Without a sequence or an ID generator, `ObjectId`s are generated
+        let mut first = commit("a1b2c3");
+        let id = commits.insert_one_mut(&mut first)?;
+        assert_eq!(first.id.as_ref(), Some(&id));
+        assert_eq!(commits.find_one(doc!{ "_id": &id })?, Some(first));
+
+        let mut more = vec![commit("d4e5f6"), commit("a7b8c9")];
+        let ids = commits.insert_many_mut(&mut more)?;
+        assert_eq!(ids.len(), 2);
+
+        for (entity, (_, id)) in more.iter().zip(&ids) {
+            assert_eq!(entity.id.as_ref(), Some(id));
+            assert_eq!(commits.find_one(doc!{ "_id": id })?.as_ref(), Some(entity));
+        }
+
+        // Existing IDs are kept intact
+        let explicit = Uid::new_oid()?;
+        let mut existing = Commit { id: Some(explicit.clone()), hash: "123abc".into() };
+        assert_eq!(commits.insert_one_mut(&mut existing)?, explicit);
+        assert_eq!(existing.id, Some(explicit));
+
+        // ID generators are used when available
+        let orders: Collection<Order> = DB_HANDLE.empty_collection_novalidate()?;
+        let mut order = Order { id: None, total: 42 };
+        let id = orders.insert_one_mut(&mut order)?;
+        assert_eq!(id.as_ref().len(), 26);
+        assert_eq!(order.id, Some(id));
+
+        // IDs are assigned even if the insertion fails
+        let mut duplicate = vec![commit("fedcba"), more[0].clone()];
+        assert!(commits.insert_many_mut(&mut duplicate).is_err());
+        assert!(duplicate[0].id.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn created_between_pagination() -> Result<()> {
+        use std::time::{ Duration, UNIX_EPOCH };
+
+        let groups: Collection<Group> = DB_HANDLE.empty_collection_novalidate()?;
+        let base = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+
+        // One group every 10 seconds, two of them in each second
+        let entities: Vec<_> = (0..20_u8).map(|i| {
+            let time = base + Duration::from_secs(u64::from(i / 2) * 10);
+            let mut bytes = Uid::<Group>::min_for_time(time).into_raw().bytes();
+            bytes[11] = i;
+
+            Group {
+                _id: Uid::from_oid_bytes(bytes),
+                name: format!("group {}", i),
+                description: String::new(),
+            }
+        }).collect();
+
+        groups.insert_many(&entities)?;
+
+        for (i, group) in entities.iter().enumerate() {
+            let time = base + Duration::from_secs(i as u64 / 2 * 10);
+            assert_eq!(group._id.created_at(), time);
+            assert!(Uid::min_for_time(time) <= group._id);
+            assert!(group._id < Uid::max_for_time(time));
+        }
+
+        // Both bounds are inclusive, with a precision of one second
+        let start = base + Duration::from_millis(20_500);
+        let end = base + Duration::from_millis(60_900);
+        let mut query = CreatedBetween::new(start, end).limit(3);
+        let mut pages = Vec::new();
+
+        loop {
+            let page: Vec<_> = groups.find_many(&query)?.collect::<Result<_>>()?;
+
+            match page.last() {
+                Some(last) => query = query.after(&last._id),
+                None => break,
+            }
+
+            pages.push(page);
+        }
+
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 3, 3, 1]);
+        assert_eq!(pages.concat(), &entities[4..14]);
+
+        // Additional filters are combined with the time range
+        let query = CreatedBetween::new(start, end).filter(doc!{ "name": "group 5" });
+        let found: Vec<_> = groups.find_many(query)?.collect::<Result<_>>()?;
+        assert_eq!(found, &entities[5..6]);
+
+        let ids: Vec<_> = entities[3..7].iter().map(|group| &group._id).collect();
+        let query = CreatedBetween::new(start, end).filter(doc!{ "_id": { "$in": ids } });
+        let found: Vec<_> = groups.find_many(query)?.collect::<Result<_>>()?;
+        assert_eq!(found, &entities[4..7]);
+
+        // Times outside the range of `ObjectId` timestamps are clamped
+        let all = CreatedBetween::new(UNIX_EPOCH - Duration::from_secs(1), base + Duration::from_secs(1 << 40));
+        assert_eq!(groups.count(Query::<Group>::filter(&all))?, 20);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compound_ids() -> Result<()> {
+        let items: Collection<StockItem> = DB_HANDLE.empty_collection_novalidate()?;
+        let item = |tenant: &str, sku: &str, quantity| StockItem {
+            key: Uid::from_raw(SkuKey { tenant: tenant.into(), sku: sku.into() }),
+            quantity,
+        };
+        let mut apple = item("acme", "apple", 10);
+        let pear = item("acme", "pear", 20);
+        let other_apple = item("globex", "apple", 30);
+
+        assert_eq!(items.insert_one(&apple)?, apple.key);
+        items.insert_many(vec![&pear, &other_apple])?;
+
+        // The `_id` is an embedded document, with fields in declaration order
+        #[derive(Debug, Clone)]
+        struct RawById(Bson);
+
+        impl Query<StockItem> for RawById {
+            type Output = Document;
+
+            fn filter(&self) -> Document {
+                doc!{ "_id": self.0.clone() }
+            }
+        }
+
+        let raw = items.find_one(RawById(apple.key.to_bson()?))?.expect("item not found");
+        let raw_id = raw.get_document("_id").expect("`_id` is not a document");
+        assert_eq!(raw_id.keys().collect::<Vec<_>>(), vec!["tenant", "sku"]);
+        assert_eq!(raw_id, &doc!{ "tenant": "acme", "sku": "apple" });
+
+        // Entity-based replacement matches on the whole compound key
+        apple.quantity = 15;
+        assert_eq!(items.replace_entity(&apple)?,
+                   UpdateOneResult { matched: true, modified: true });
+        assert_eq!(items.find_one(doc!{ "_id": apple.key.to_bson()? })?.as_ref(), Some(&apple));
+        assert_eq!(items.find_one(doc!{ "_id": other_apple.key.to_bson()? })?.as_ref(), Some(&other_apple));
+
+        let banana = item("acme", "banana", 5);
+        assert_eq!(items.replace_entity(&banana)?,
+                   UpdateOneResult { matched: false, modified: false });
+        assert_eq!(items.upsert_entity(&banana)?,
+                   UpsertOneResult { matched: false,
+                                     modified: false,
+                                     upserted_id: Some(banana.key.clone()) });
+
+        // Entity-based deletion, too
+        assert!(items.delete_entity(&apple)?);
+        assert!(!items.delete_entity(&apple)?);
+        assert_eq!(items.delete_entities(vec![&pear, &banana])?, 2);
+
+        let remaining: Vec<_> = items.find_many(doc!{})?.collect::<Result<_>>()?;
+        assert_eq!(remaining, vec![other_apple]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn server_error_classification() -> Result<()> {
+        use avocado::error::{ ErrorKind, ErrorExt };
+
+        let groups: Collection<Group> = DB_HANDLE.empty_collection()?;
+        let group = Group {
+            _id: Uid::new_oid()?,
+            name: String::from("Duplicators Anonymous"),
+            description: String::from("Everything twice"),
+        };
+
+        groups.insert_one(&group)?;
+
+        // Duplicate keys are reported along with the index and the key
+        let error = groups.insert_one(&group).unwrap_err();
+        let server_error = error.server_error().expect("no server error details");
+        assert_eq!(error.kind(), ErrorKind::DuplicateKey);
+        assert_eq!(server_error.code, 11000);
+        assert_eq!(server_error.index.as_ref().map(String::as_str), Some("_id_"));
+        assert!(server_error.key.is_some());
+
+        let error = groups.insert_many(vec![&group]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::DuplicateKey);
+
+        // Writes violating the schema of the collection fail validation
+        #[derive(Debug)]
+        struct Rename<'a>(&'a Uid<Group>);
+
+        impl<'a> Update<Group> for Rename<'a> {
+            fn filter(&self) -> Document {
+                doc!{ "_id": self.0 }
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { "name": 42 } }
+            }
+        }
+
+        let error = groups.update_one(Rename(&group._id)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::DocumentValidation);
+        assert_eq!(error.server_error().map(|server_error| server_error.code), Some(121));
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_many_failures() -> Result<()> {
+        use avocado::error::{ ErrorKind, ErrorExt };
+
+        let groups: Collection<Group> = DB_HANDLE.empty_collection_novalidate()?;
+        let group = |name: &str| -> Result<Group> {
+            Ok(Group {
+                _id: Uid::new_oid()?,
+                name: name.into(),
+                description: String::new(),
+            })
+        };
+        let existing = group("existing")?;
+
+        groups.insert_one(&existing)?;
+
+        // Ordered insertion stops at the first failure
+        let first = group("first")?;
+        let last = group("last")?;
+        let error = groups.insert_many(vec![&first, &existing, &last]).unwrap_err();
+        let ids = error.context::<InsertManyErrorContext<Group>>().expect("no IDs in context");
+        let failures = error.context::<InsertFailure>().expect("no failures in context");
+
+        assert_eq!(error.kind(), ErrorKind::DuplicateKey);
+        assert_eq!(ids.keys().cloned().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(ids.get(&0).cloned(), Some(Ok(first._id.clone())));
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].index, 1);
+        assert_eq!(failures[0].error.code, 11000);
+        assert_eq!(failures[0].entity, None);
+        assert_eq!(groups.count(doc!{})?, 2);
+
+        // Unordered insertion carries on, and reports a partial success
+        let more = vec![group("more")?, existing.clone(), first.clone(), group("most")?];
+        let partial = groups.insert_many_unordered(&more)?;
+
+        assert!(!partial.is_complete());
+        assert_eq!(partial.inserted.keys().cloned().collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(partial.inserted.get(&3), Some(&more[3]._id));
+        assert_eq!(partial.failed.iter().map(|failure| failure.index).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(partial.failed.iter().all(|failure| failure.error.code == 11000));
+        assert!(partial.failed[0].entity.as_ref().map_or(false, |entity| entity.contains("existing")));
+        assert_eq!(groups.count(doc!{})?, 4);
+
+        let complete = groups.insert_many_unordered(vec![group("new")?])?;
+        assert!(complete.is_complete());
+        assert_eq!(complete.inserted.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn insert_many_batches() -> Result<()> {
+        use std::iter;
+
+        let groups: Collection<Group> = DB_HANDLE.empty_collection_novalidate()?;
+
+        // More documents than the server accepts in a single command,
+        // coming from an iterator with no known length
+        let count = 100_010;
+        let mut i = 0;
+        let small = iter::repeat_with(|| {
+            i += 1;
+            Group {
+                _id: Uid::new_oid().expect("can't generate ObjectId"),
+                name: format!("small #{}", i),
+                description: String::new(),
+            }
+        }).take(count);
+        let ids = groups.insert_many(small)?;
+
+        assert_eq!(ids.len(), count);
+        assert_eq!(ids.keys().next_back().cloned(), Some(count as u64 - 1));
+        assert_eq!(groups.count(doc!{})?, count);
+
+        // Documents which only fit in a single command one or two at a time
+        let large: Vec<_> = (0..5).map(|n| Group {
+            _id: Uid::new_oid().expect("can't generate ObjectId"),
+            name: format!("large #{}", n),
+            description: "x".repeat(6 * 1024 * 1024),
+        }).collect();
+        let ids = groups.insert_many(&large)?;
+
+        assert_eq!(ids.keys().cloned().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(ids.get(&4), Some(&large[4]._id));
+
+        // The indexes of failures in later batches are global
+        let existing = large[2].clone();
+        let mixed = (0..count).map(|n| if n == count - 3 {
+            existing.clone()
+        } else {
+            Group {
+                _id: Uid::new_oid().expect("can't generate ObjectId"),
+                name: format!("mixed #{}", n),
+                description: String::new(),
+            }
+        });
+        let partial = groups.insert_many_unordered(mixed)?;
+
+        assert_eq!(partial.inserted.len(), count - 1);
+        assert_eq!(partial.failed.len(), 1);
+        assert_eq!(partial.failed[0].index, count as u64 - 3);
+        assert!(!partial.inserted.contains_key(&(count as u64 - 3)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn document_too_large() -> Result<()> {
+        use avocado::error::{ ErrorKind, ErrorExt };
+
+        let groups: Collection<Group> = DB_HANDLE.empty_collection_novalidate()?;
+        let group = Group {
+            _id: Uid::new_oid()?,
+            name: "huge".into(),
+            description: "x".repeat(17 * 1024 * 1024),
+        };
+
+        let error = groups.insert_one(&group).unwrap_err();
+        let message = error.to_string();
+
+        assert_eq!(error.kind(), ErrorKind::DocumentTooLarge);
+        assert!(message.contains(Group::NAME));
+        assert!(message.contains("`description`"));
+
+        let error = groups.insert_many(vec![&group]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::DocumentTooLarge);
+        assert_eq!(groups.count(doc!{})?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stateful_transforms() -> Result<()> {
+        use avocado::cursor::{ Cursor, BoxedTransform };
+
+        #[derive(Debug)]
+        struct Suffixed(&'static str);
+
+        impl Query<Group> for Suffixed {
+            type Output = String;
+
+            fn options(&self) -> FindOptions {
+                FindOptions {
+                    sort: Some(doc!{ "name": 1 }),
+                    ..Default::default()
+                }
+            }
+
+            fn transformer(&self) -> Option<BoxedTransform> {
+                let suffix = self.0;
+
+                Some(Box::new(move |mut raw| {
+                    let name = raw.remove_str("name")?;
+                    Ok(format!("{}{}", name.as_str().unwrap_or_default(), suffix).into())
+                }))
+            }
+        }
+
+        impl FindAndUpdate<Group> for Suffixed {
+            type Output = String;
+
+            fn filter(&self) -> Document {
+                doc!{ "name": "first" }
+            }
+
+            fn update(&self) -> Document {
+                doc!{ "$set": { "description": self.0 } }
+            }
+
+            fn transformer(&self) -> Option<BoxedTransform> {
+                let suffix = self.0;
+
+                Some(Box::new(move |mut raw| {
+                    let name = raw.remove_str("name")?;
+                    Ok(format!("{}{}", name.as_str().unwrap_or_default(), suffix).into())
+                }))
+            }
+        }
+
+        let groups: Collection<Group> = DB_HANDLE.empty_collection_novalidate()?;
+        let names = ["first", "second"];
+
+        for name in &names {
+            groups.insert_one(&Group {
+                _id: Uid::new_oid()?,
+                name: name.to_string(),
+                description: String::new(),
+            })?;
+        }
+
+        let exclaimed: Vec<_> = groups.find_many(Suffixed("!"))?.collect::<Result<_>>()?;
+        assert_eq!(exclaimed, vec!["first!", "second!"]);
+
+        let query = Suffixed("?");
+        let questioned: Cursor<String> = groups.find_many(&query)?;
+        assert_eq!(questioned.collect::<Result<Vec<_>>>()?, vec!["first?", "second?"]);
+
+        assert_eq!(groups.find_one(&query)?, Some(String::from("first?")));
+        assert_eq!(groups.find_one_and_update(Suffixed("."))?, Some(String::from("first.")));
+
+        // The static form still works
+        let static_names: Vec<Group> = groups.find_many(doc!{})?.collect::<Result<_>>()?;
+        assert_eq!(static_names.len(), names.len());
+
+        // Plain filters deserialize the documents without any transform
+        assert!(Query::<Group>::transformer(&doc!{}).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn instrumented_operations() -> Result<()> {
+        use std::sync::Arc;
+        use std::time::Duration;
+        use avocado::error::{ ErrorKind, ErrorExt };
+        use avocado::instrument::{ self, Instrument, Counts };
+
+        #[derive(Debug, Clone)]
+        struct Recorded {
+            operation: &'static str,
+            collection: &'static str,
+            filter: Option<Document>,
+            update: Option<Document>,
+            documents: Vec<Document>,
+            duration: Duration,
+            counts: Counts,
+            error: Option<ErrorKind>,
+        }
+
+        #[derive(Debug, Default)]
+        struct Recorder(Mutex<Vec<Recorded>>);
+
+        impl Recorder {
+            fn take(&self) -> Vec<Recorded> {
+                std::mem::replace(&mut *self.0.lock().unwrap(), Vec::new())
+            }
+        }
+
+        impl Instrument for Recorder {
+            fn record(&self, event: &instrument::Event) {
+                self.0.lock().unwrap().push(Recorded {
+                    operation: event.operation,
+                    collection: event.collection,
+                    filter: event.filter.clone(),
+                    update: event.update.clone(),
+                    documents: event.documents.clone(),
+                    duration: event.duration,
+                    counts: event.counts,
+                    error: event.error.map(|error| error.kind()),
+                });
+            }
+        }
+
+        #[derive(Debug)]
+        struct GroupNames;
+
+        impl Pipeline<Group> for GroupNames {
+            type Output = Document;
+
+            fn stages(&self) -> Vec<Document> {
+                vec![
+                    doc!{ "$sort": { "name": 1 } },
+                    doc!{ "$project": { "_id": 0, "name": 1 } },
+                ]
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let groups: Collection<Group> = DB_HANDLE
+            .empty_collection_novalidate::<Group>()?
+            .with_instrument(recorder.clone());
+        let mut group = Group {
+            _id: Uid::new_oid()?,
+            name: String::from("instrumented"),
+            description: String::new(),
+        };
+
+        // Successful inserts report the inserted documents and their count
+        groups.insert_one(&group)?;
+
+        let events = recorder.take();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, "insert_one");
+        assert_eq!(events[0].collection, Group::NAME);
+        assert_eq!(events[0].counts, Counts::inserted(1));
+        assert_eq!(events[0].documents.len(), 1);
+        assert_eq!(events[0].documents[0].get_str("name").ok(), Some("instrumented"));
+        assert!(events[0].duration > Duration::from_secs(0));
+        assert!(events[0].error.is_none());
+
+        // Failures report the error
+        assert!(groups.insert_one(&group).is_err());
+
+        let events = recorder.take();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].operation, "insert_one");
+        assert_eq!(events[0].error, Some(ErrorKind::DuplicateKey));
+
+        // Entity writes report the `_id` filter and the replacement
+        group.description = String::from("replaced");
+        groups.replace_entity(&group)?;
+
+        let events = recorder.take();
+        assert_eq!(events[0].operation, "replace_entity");
+        assert_eq!(events[0].counts, Counts::updated(1, 1));
+        assert_eq!(events[0].filter, Some(doc!{ "_id": &group._id }));
+        assert_eq!(
+            events[0].update.as_ref().and_then(|update| update.get_str("description").ok()),
+            Some("replaced")
+        );
+
+        // Reads report their counts, pipelines report their stages
+        assert_eq!(groups.count(doc!{})?, 1);
+        assert_eq!(groups.aggregate(GroupNames)?.count(), 1);
+
+        let events = recorder.take();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].operation, "count");
+        assert_eq!(events[0].filter, Some(doc!{}));
+        assert_eq!(events[0].counts, Counts::matched(1));
+        assert_eq!(events[1].operation, "aggregate");
+        assert_eq!(events[1].documents, GroupNames.stages());
+
+        // Deletions report the number of deleted documents
+        assert!(groups.delete_entity(&group)?);
+
+        let events = recorder.take();
+        assert_eq!(events[0].operation, "delete_entity");
+        assert_eq!(events[0].counts, Counts::deleted(1));
+        assert!(events[0].error.is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn keep_server_alive() {}
 }
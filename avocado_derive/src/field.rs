@@ -0,0 +1,43 @@
+//! Generating typed field path constants for `#[avocado(fields)]`.
+
+use proc_macro2::TokenStream;
+use syn::{ Ident, Type, Visibility };
+use quote::{ ToTokens, TokenStreamExt };
+
+/// A serialized field of the type `Doc` is being derived for.
+#[derive(Debug, Clone)]
+pub struct SerializedField {
+    /// The original identifier of the field.
+    pub ident: Ident,
+    /// The visibility of the field, also used for its path constant.
+    pub vis: Visibility,
+    /// The Rust type of the field.
+    pub ty: Type,
+    /// The name of the field in BSON, after Serde renaming.
+    pub name: String,
+    /// Whether the field is `#[serde(flatten)]`ed into its parent.
+    pub flatten: bool,
+}
+
+/// Renders one `Field<Self, _>` associated constant per serialized field.
+/// It must be quoted inside an inherent `impl` block of the document type.
+#[derive(Debug, Clone)]
+pub struct FieldPaths<'a>(pub &'a [SerializedField]);
+
+impl<'a> ToTokens for FieldPaths<'a> {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        // Flattened fields have no path of their own.
+        for field in self.0.iter().filter(|field| !field.flatten) {
+            let SerializedField { ref ident, ref vis, ref ty, ref name, .. } = *field;
+            let doc = format!("Typed path of the field `{}` (serialized as `{}`).",
+                              ident, name);
+
+            tokens.append_all(quote! {
+                #[doc = #doc]
+                #[allow(non_upper_case_globals)]
+                #vis const #ident: ::avocado::field::Field<Self, #ty> =
+                    ::avocado::field::Field::new(#name);
+            });
+        }
+    }
+}
@@ -44,6 +44,8 @@ mod attr;
 mod case;
 mod index;
 mod option;
+mod settings;
+mod field;
 
 use proc_macro::TokenStream;
 use proc_macro2::Span;
@@ -56,6 +58,8 @@ use self::{
     case::RenameRule,
     index::Spec,
     option::DocOptions,
+    settings::Settings,
+    field::{ SerializedField, FieldPaths },
     error::{ Error, Result, err_msg },
 };
 
@@ -76,13 +80,29 @@ fn impl_avocado_doc(input: TokenStream) -> Result<TokenStream> {
     let id_ty = raw_id_type(&parsed_ast.attrs)?;
     let indexes = Spec::from_attributes(&parsed_ast.attrs)?;
     let options = DocOptions::from_attributes(&parsed_ast.attrs)?;
+    let settings = Settings::from_attributes(&parsed_ast.attrs)?;
     let index_count = indexes.len();
 
     ensure_only_lifetime_params(&generics)?;
 
     match parsed_ast.data {
         Data::Struct(s) => {
-            let id_name = name_of_id_field(s.fields, &parsed_ast.attrs)?;
+            let fields = serialized_fields(s.fields, &parsed_ast.attrs)?;
+            let id_name = name_of_id_field(&fields)?;
+            let field_paths = if settings.fields {
+                let paths = FieldPaths(&fields);
+                Some(quote! {
+                    impl #impl_gen #ty #ty_gen #where_cls {
+                        #paths
+                    }
+                })
+            } else {
+                None
+            };
+            let capped = settings.capped;
+            let time_series = settings.time_series;
+            let sequence = settings.sequence;
+            let id_generator = settings.id_generator;
             let ast = quote! {
                 impl #impl_gen ::avocado::doc::Doc for #ty #ty_gen #where_cls {
                     const NAME: &'static str = #ty_name;
@@ -104,7 +124,17 @@ fn impl_avocado_doc(input: TokenStream) -> Result<TokenStream> {
                     }
 
                     #options
+
+                    #capped
+
+                    #time_series
+
+                    #sequence
+
+                    #id_generator
                 }
+
+                #field_paths
             };
             Ok(ast.into())
         },
@@ -156,9 +186,9 @@ fn raw_id_type(attrs: &[Attribute]) -> Result<Type> {
         }))
 }
 
-/// Returns an error if there is no field serializing as `_id` or if there
-/// are more than 1 of them. (The `_id` field must be unambiguous and unique.)
-fn name_of_id_field(fields: Fields, attrs: &[Attribute]) -> Result<Ident> {
+/// Returns the fields which are serialized and/or deserialized, along with
+/// their names in BSON, with Serde renaming rules applied.
+fn serialized_fields(fields: Fields, attrs: &[Attribute]) -> Result<Vec<SerializedField>> {
     let named = match fields {
         Fields::Named(fields) => fields.named,
         _ => return err_msg("a `Doc` must be a struct with named fields"),
@@ -168,7 +198,7 @@ fn name_of_id_field(fields: Fields, attrs: &[Attribute]) -> Result<Ident> {
         None => None,
         Some(kv) => Some(value_as_str(&kv)?.parse()?)
     };
-    let mut id_name = None;
+    let mut serialized = Vec::with_capacity(named.len());
 
     for field in named {
         // The field isn't inspected if it's never serialized or deserialized.
@@ -192,13 +222,32 @@ fn name_of_id_field(fields: Fields, attrs: &[Attribute]) -> Result<Ident> {
         // The final field name is the exact name specified in the immediate
         // `#[serde(rename = "...")]` attribute applied directly to the field,
         // or the potentially-`rename_all`'d name, if the former doesn't exist.
-        let field_name = serde_renamed_ident(&field.attrs, rename_all_ident)?;
+        let name = serde_renamed_ident(&field.attrs, rename_all_ident)?;
+        let flatten = has_serde_word(&field.attrs, "flatten")?;
+
+        serialized.push(SerializedField {
+            ident,
+            vis: field.vis,
+            ty: field.ty,
+            name,
+            flatten,
+        });
+    }
+
+    Ok(serialized)
+}
+
+/// Returns an error if there is no field serializing as `_id` or if there
+/// are more than 1 of them. (The `_id` field must be unambiguous and unique.)
+fn name_of_id_field(fields: &[SerializedField]) -> Result<Ident> {
+    let mut id_name = None;
 
-        if field_name == "_id" {
+    for field in fields {
+        if field.name == "_id" && !field.flatten {
             if id_name.is_some() {
                 return err_msg("more than one fields serialize as `_id`");
             } else {
-                id_name = Some(ident);
+                id_name = Some(field.ident.clone());
             }
         }
     }
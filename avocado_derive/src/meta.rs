@@ -2,7 +2,7 @@
 
 use std::str;
 use std::str::FromStr;
-use std::i32;
+use std::{ i32, i64 };
 use std::ops::RangeBounds;
 use std::fmt::Debug;
 use syn::{ Attribute, Meta, MetaList, NestedMeta, MetaNameValue, Lit };
@@ -170,6 +170,34 @@ pub fn value_as_i32<R>(key: &str, lit: &Lit, range: R) -> Result<i32>
     }
 }
 
+/// Extracts an `i64` value from an attribute value.
+/// Ensures that the resulting value is contained in the specified `range`.
+#[allow(clippy::cast_possible_wrap)]
+pub fn value_as_i64<R>(key: &str, lit: &Lit, range: R) -> Result<i64>
+    where R: Debug + RangeBoundsExt<i64>
+{
+    let value = match *lit {
+        Lit::Int(ref lit) => {
+            let v = lit.value();
+            if v <= i64::MAX as u64 {
+                v as i64
+            } else {
+                err_fmt!("integer value `{}` for key `{}` overflows i64", v, key)?
+            }
+        }
+        Lit::Str(ref lit) => lit.value().parse()?,
+        Lit::ByteStr(ref lit) => str::from_utf8(&lit.value())?.parse()?,
+        _ => return err_fmt!("value for key `{}` must be an i64", key)
+    };
+
+    if range.contains_value(&value) {
+        Ok(value)
+    } else {
+        err_fmt!("value `{}` for key `{}` exceeds range {:?}",
+                 value, key, range)
+    }
+}
+
 /// Extracts an `f64` value from an attribute value.
 /// Ensures that the resulting value is contained in the specified `range`.
 ///
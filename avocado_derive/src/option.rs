@@ -40,21 +40,29 @@ impl DocOptions {
                 "query_options",
                 &["mongodb", "coll", "options", "FindOptions"],
             ),
+            (
+                "read_options",
+                &["avocado", "options", "ReadOptions"],
+            ),
             (
                 "insert_options",
                 &["mongodb", "coll", "options", "InsertManyOptions"],
             ),
             (
                 "delete_options",
-                &["mongodb", "common", "WriteConcern"],
+                &["avocado", "options", "DeleteOptions"],
             ),
             (
                 "update_options",
-                &["mongodb", "common", "WriteConcern"],
+                &["avocado", "options", "UpdateOptions"],
             ),
             (
                 "upsert_options",
-                &["mongodb", "common", "WriteConcern"],
+                &["avocado", "options", "UpdateOptions"],
+            ),
+            (
+                "replace_options",
+                &["avocado", "options", "ReplaceOptions"],
             ),
             (
                 "find_and_update_options",
@@ -131,7 +139,9 @@ impl ToTokens for DocOptions {
 }
 
 /// If a particular function is implemented from within the derive proc-macro,
-/// render it here.
+/// render it here. The return value of the user-specified function is passed
+/// through `From::from()`, so that e.g. a function returning a bare
+/// `WriteConcern` can still be used for implementing `update_options()`.
 fn fn_to_tokens(fn_name: &str, type_path_components: &[&str], callee_path: &Path, tokens: &mut TokenStream) {
     let fn_name = Ident::new(fn_name, Span::call_site());
     let type_path = Path {
@@ -147,7 +157,7 @@ fn fn_to_tokens(fn_name: &str, type_path_components: &[&str], callee_path: &Path
 
     tokens.append_all(quote! {
         fn #fn_name() -> #type_path {
-            #callee_path()
+            ::std::convert::From::from(#callee_path())
         }
     });
 }
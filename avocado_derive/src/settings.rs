@@ -0,0 +1,333 @@
+//! Parsing the general-purpose `#[avocado(...)]` attribute.
+
+use syn::{ Attribute, Ident, Path };
+use syn::punctuated::Punctuated;
+use proc_macro2::{ TokenStream, Span };
+use quote::{ ToTokens, TokenStreamExt };
+use crate::{
+    error::Result,
+    attr::*,
+    meta::{ value_as_i64, lit_value_as_str },
+};
+
+/// Collection-level settings specified via `#[avocado(...)]` attributes.
+/// The attribute may be repeated; the settings are merged in order.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Whether to generate typed field path constants.
+    pub fields: bool,
+    /// The size limits of the collection, if it is capped.
+    pub capped: Option<Capped>,
+    /// The layout of the collection, if it is a time series.
+    pub time_series: Option<TimeSeries>,
+    /// The allocation of sequential IDs, if the type uses them.
+    pub sequence: Option<Sequence>,
+    /// The client-side ID generator, if any.
+    pub id_generator: Option<IdGenerator>,
+}
+
+/// The `id_generator = "path::to::Generator"` setting.
+#[derive(Debug, Clone)]
+pub struct IdGenerator(Path);
+
+/// Renders the `Doc::generate_id()` method.
+impl ToTokens for IdGenerator {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let generator = &self.0;
+
+        tokens.append_all(quote! {
+            fn generate_id() -> ::avocado::error::Result<
+                ::std::option::Option<::avocado::uid::Uid<Self>>
+            > {
+                <#generator as ::avocado::idgen::IdGenerator>::generate().map(|raw| {
+                    ::std::option::Option::Some(
+                        ::avocado::uid::Uid::from_raw(::std::convert::From::from(raw))
+                    )
+                })
+            }
+        });
+    }
+}
+
+/// The `capped(size = ..., max = ...)` setting.
+#[derive(Debug, Clone, Copy)]
+pub struct Capped {
+    /// The maximal size of the collection in bytes.
+    size: i64,
+    /// The maximal number of documents in the collection.
+    max: Option<i64>,
+}
+
+impl Capped {
+    /// Parses the items of a `capped(...)` list.
+    fn from_list(items: Punctuated<NestedExtMeta, Token![,]>) -> Result<Self> {
+        let mut max_size = None;
+        let mut max_count = None;
+
+        for item in items {
+            match item {
+                NestedExtMeta::Meta(ExtMeta::KeyValue(path, _, lit)) => {
+                    let path_str = path.colon_sep_str();
+
+                    match path_str.as_str() {
+                        "size" => max_size = Some(value_as_i64(&path_str, &lit, 1..)?),
+                        "max" => max_count = Some(value_as_i64(&path_str, &lit, 1..)?),
+                        _ => return err_fmt!("bad name-value attribute: capped::{}", path_str),
+                    }
+                }
+                _ => return err_fmt!(
+                    "attribute `capped` must contain key-value pairs only, not {:#?}", item
+                ),
+            }
+        }
+
+        match max_size {
+            Some(size) => Ok(Capped { size, max: max_count }),
+            None => err_fmt!("attribute `capped` must specify the `size` in bytes"),
+        }
+    }
+}
+
+/// The `sequence` or `sequence(step = ..., batch = ...)` setting.
+#[derive(Debug, Clone, Copy)]
+pub struct Sequence {
+    /// The difference between consecutive IDs.
+    step: i64,
+    /// The number of IDs allocated from the database at once.
+    batch: i64,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence { step: 1, batch: 1 }
+    }
+}
+
+impl Sequence {
+    /// Parses the items of a `sequence(...)` list.
+    fn from_list(items: Punctuated<NestedExtMeta, Token![,]>) -> Result<Self> {
+        let mut sequence = Sequence::default();
+
+        for item in items {
+            match item {
+                NestedExtMeta::Meta(ExtMeta::KeyValue(path, _, lit)) => {
+                    let path_str = path.colon_sep_str();
+
+                    match path_str.as_str() {
+                        "step" => sequence.step = value_as_i64(&path_str, &lit, 1..)?,
+                        // Must fit into the `u32` of `Sequence::batch`
+                        "batch" => sequence.batch = value_as_i64(&path_str, &lit, 1..=0xffff_ffff)?,
+                        _ => return err_fmt!("bad name-value attribute: sequence::{}", path_str),
+                    }
+                }
+                _ => return err_fmt!(
+                    "attribute `sequence` must contain key-value pairs only, not {:#?}", item
+                ),
+            }
+        }
+
+        Ok(sequence)
+    }
+}
+
+/// The `time_series(time_field = "...", ...)` setting.
+#[derive(Debug, Clone)]
+pub struct TimeSeries {
+    /// The name of the field holding the time of each measurement.
+    time_field: String,
+    /// The name of the field holding the metadata of each measurement.
+    meta_field: Option<String>,
+    /// The name of the `Granularity` variant, if specified.
+    granularity: Option<&'static str>,
+    /// The expiration time of measurements, in seconds.
+    expire_after_seconds: Option<i64>,
+}
+
+impl TimeSeries {
+    /// Parses the items of a `time_series(...)` list.
+    fn from_list(items: Punctuated<NestedExtMeta, Token![,]>) -> Result<Self> {
+        let mut time_field = None;
+        let mut meta_field = None;
+        let mut granularity = None;
+        let mut expire_after_seconds = None;
+
+        for item in items {
+            match item {
+                NestedExtMeta::Meta(ExtMeta::KeyValue(path, _, lit)) => {
+                    let path_str = path.colon_sep_str();
+
+                    match path_str.as_str() {
+                        "time_field" => time_field = Some(lit_value_as_str(&path_str, &lit)?),
+                        "meta_field" => meta_field = Some(lit_value_as_str(&path_str, &lit)?),
+                        "granularity" => {
+                            granularity = match lit_value_as_str(&path_str, &lit)?.as_str() {
+                                "seconds" => Some("Seconds"),
+                                "minutes" => Some("Minutes"),
+                                "hours" => Some("Hours"),
+                                other => return err_fmt!(
+                                    "granularity must be `seconds`, `minutes` or `hours`, not `{}`",
+                                    other
+                                ),
+                            }
+                        }
+                        "expire_after_seconds" => {
+                            expire_after_seconds = Some(value_as_i64(&path_str, &lit, 0..)?)
+                        }
+                        _ => return err_fmt!("bad name-value attribute: time_series::{}", path_str),
+                    }
+                }
+                _ => return err_fmt!(
+                    "attribute `time_series` must contain key-value pairs only, not {:#?}", item
+                ),
+            }
+        }
+
+        match time_field {
+            Some(field) => Ok(TimeSeries {
+                time_field: field,
+                meta_field,
+                granularity,
+                expire_after_seconds,
+            }),
+            None => err_fmt!("attribute `time_series` must specify the `time_field`"),
+        }
+    }
+}
+
+/// Renders the `Doc::time_series()` method.
+impl ToTokens for TimeSeries {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let time_field = &self.time_field;
+        let meta_field = match self.meta_field {
+            Some(ref field) => quote!(::std::option::Option::Some(#field)),
+            None => quote!(::std::option::Option::None),
+        };
+        let granularity = match self.granularity {
+            Some(name) => {
+                let variant = Ident::new(name, Span::call_site());
+                quote!(::std::option::Option::Some(::avocado::timeseries::Granularity::#variant))
+            }
+            None => quote!(::std::option::Option::None),
+        };
+        let expire_after_seconds = match self.expire_after_seconds {
+            Some(seconds) => quote!(::std::option::Option::Some(#seconds)),
+            None => quote!(::std::option::Option::None),
+        };
+
+        tokens.append_all(quote! {
+            fn time_series() -> ::std::option::Option<::avocado::timeseries::TimeSeries> {
+                ::std::option::Option::Some(::avocado::timeseries::TimeSeries {
+                    time_field: #time_field,
+                    meta_field: #meta_field,
+                    granularity: #granularity,
+                    expire_after_seconds: #expire_after_seconds,
+                })
+            }
+        });
+    }
+}
+
+/// Renders the `Doc::sequence()` method.
+impl ToTokens for Sequence {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let step = self.step;
+        // The range of `batch` is checked during parsing.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let batch = self.batch as u32;
+
+        tokens.append_all(quote! {
+            fn sequence() -> ::std::option::Option<::avocado::sequence::Sequence> {
+                ::std::option::Option::Some(::avocado::sequence::Sequence {
+                    step: #step,
+                    batch: #batch,
+                })
+            }
+        });
+    }
+}
+
+/// Renders the `Doc::capped()` method.
+impl ToTokens for Capped {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let size = self.size;
+        let max = match self.max {
+            Some(max) => quote!(::std::option::Option::Some(#max)),
+            None => quote!(::std::option::Option::None),
+        };
+
+        tokens.append_all(quote! {
+            fn capped() -> ::std::option::Option<::avocado::options::Capped> {
+                ::std::option::Option::Some(::avocado::options::Capped {
+                    size: #size,
+                    max: #max,
+                })
+            }
+        });
+    }
+}
+
+impl Settings {
+    /// Collects the settings from every `#[avocado(...)]` attribute.
+    pub fn from_attributes(attrs: &[Attribute]) -> Result<Self> {
+        let mut settings = Settings::default();
+
+        for attr in attrs {
+            let nested = match attr.parse_ext_meta() {
+                Some(ExtMeta::List(path, _, nested)) => {
+                    if path.colon_sep_str() == "avocado" {
+                        nested
+                    } else {
+                        continue;
+                    }
+                }
+                Some(meta) => {
+                    if meta.path_str() == "avocado" {
+                        return err_fmt!("attribute must have form `#[avocado(...)]`");
+                    } else {
+                        continue;
+                    }
+                }
+                None => continue,
+            };
+
+            for item in nested {
+                match item {
+                    NestedExtMeta::Meta(meta) => settings.apply(meta)?,
+                    NestedExtMeta::Literal(lit) => {
+                        return err_fmt!("expected a meta item, found literal: {:#?}", lit);
+                    }
+                }
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Applies a single item of an `#[avocado(...)]` attribute.
+    fn apply(&mut self, meta: ExtMeta) -> Result<()> {
+        let path_str = meta.path_str();
+
+        match meta {
+            ExtMeta::Path(_) => match path_str.as_str() {
+                "fields" => self.fields = true,
+                "sequence" => self.sequence = Some(Sequence::default()),
+                _ => return err_fmt!("bad path attribute: {}", path_str),
+            },
+            ExtMeta::KeyValue(_, _, lit) => match path_str.as_str() {
+                "id_generator" => {
+                    let generator = lit_value_as_str(&path_str, &lit)?;
+                    self.id_generator = Some(IdGenerator(syn::parse_str(&generator)?));
+                }
+                _ => return err_fmt!("bad name-value attribute: {}", path_str),
+            },
+            ExtMeta::List(_, _, list) => match path_str.as_str() {
+                "capped" => self.capped = Some(Capped::from_list(list)?),
+                "time_series" => self.time_series = Some(TimeSeries::from_list(list)?),
+                "sequence" => self.sequence = Some(Sequence::from_list(list)?),
+                _ => return err_fmt!("bad list attribute: {}", path_str),
+            },
+        }
+
+        Ok(())
+    }
+}